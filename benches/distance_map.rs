@@ -0,0 +1,105 @@
+//! Times the distance transform against the former brute-force neighborhood scan, on one
+//! thread and then on every thread of the rayon pool.
+//!
+//! Run with `cargo bench --bench distance_map`.
+
+use nalgebra::Vector3;
+use rayon::prelude::*;
+use std::hint::black_box;
+use std::time::Instant;
+use torus::distance::distance_field;
+use torus::map::Map;
+use torus::seed::WorldSeed;
+
+const RADIUS: i32 = 4;
+
+fn brute_force(map: &Map, position: (i32, i32, i32)) -> Vec<u8> {
+    let mut distances = Vec::with_capacity(4096);
+    for z in 0..16 {
+        for y in 0..16 {
+            for x in 0..16 {
+                let world = (
+                    position.0 * 16 + x,
+                    position.1 * 16 + y,
+                    position.2 * 16 + z,
+                );
+                let mut min_distance = RADIUS as f32;
+                for dz in -RADIUS..=RADIUS {
+                    for dy in -RADIUS..=RADIUS {
+                        for dx in -RADIUS..=RADIUS {
+                            if map
+                                .get_voxel(world.0 + dx, world.1 + dy, world.2 + dz)
+                                .is_some_and(|voxel| !voxel.is_empty())
+                            {
+                                let distance = ((dx * dx + dy * dy + dz * dz) as f32).sqrt();
+                                min_distance = min_distance.min(distance);
+                            }
+                        }
+                    }
+                }
+                distances.push(min_distance as u8);
+            }
+        }
+    }
+    distances
+}
+
+/// Seconds taken by `f`.
+fn time(f: impl FnOnce()) -> f64 {
+    let time = Instant::now();
+    f();
+    time.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let mut map = Map::new();
+    map.generate_region(WorldSeed::new(42), (-2, -2, -2), (2, 2, 2));
+    let chunks: Vec<_> = map.chunks.keys().copied().collect();
+    let map = &map;
+    // Both methods compute the same fields from the same map, with no map copy and no
+    // writing back into the chunks, so only the algorithm differs.
+    let transform = |position: &(i32, i32, i32)| {
+        let origin = Vector3::new(position.0, position.1, position.2) * 16;
+        distance_field(map, origin, Vector3::repeat(16), RADIUS)
+    };
+
+    println!("{} chunks, radius {}", chunks.len(), RADIUS);
+    let mut runs = vec![(1, false)];
+    if rayon::current_num_threads() > 1 {
+        runs.push((rayon::current_num_threads(), true));
+    }
+    for (threads, parallel) in runs {
+        let brute_force_time = time(|| {
+            if parallel {
+                chunks.par_iter().for_each(|position| {
+                    black_box(brute_force(map, *position));
+                });
+            } else {
+                chunks.iter().for_each(|position| {
+                    black_box(brute_force(map, *position));
+                });
+            }
+        });
+        let transform_time = time(|| {
+            if parallel {
+                chunks.par_iter().for_each(|position| {
+                    black_box(transform(position));
+                });
+            } else {
+                chunks.iter().for_each(|position| {
+                    black_box(transform(position));
+                });
+            }
+        });
+        println!(
+            "{} thread(s): brute force {:.3} s ({:.2} ms/chunk), \
+             distance transform {:.3} s ({:.2} ms/chunk), {:.1}x faster",
+            threads,
+            brute_force_time,
+            brute_force_time * 1000.0 / chunks.len() as f64,
+            transform_time,
+            transform_time * 1000.0 / chunks.len() as f64,
+            brute_force_time / transform_time
+        );
+    }
+}
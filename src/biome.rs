@@ -0,0 +1,143 @@
+use crate::material::MaterialRegistry;
+use crate::voxel::Voxel;
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+
+const BUILTIN_BIOMES: &str = include_str!("../assets/biomes.toml");
+
+/// A kind of landscape, picked by climate, see `BiomeGenerator`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Biome {
+    pub name: String,
+    pub temperature: f64,
+    pub humidity: f64,
+    pub surface: Voxel,
+    pub subsurface: Voxel,
+    pub height: f64,
+    pub amplitude: f64,
+    /// Empty when the biome has no decorations.
+    pub decoration: Voxel,
+    pub decoration_density: f64,
+    pub decoration_height: i32,
+}
+
+#[derive(Debug)]
+pub enum BiomeError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    UnknownMaterial { biome: String, material: String },
+    NoBiomes,
+}
+
+impl fmt::Display for BiomeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BiomeError::Io(e) => write!(f, "could not read biome file: {}", e),
+            BiomeError::Parse(e) => write!(f, "invalid biome file: {}", e),
+            BiomeError::UnknownMaterial { biome, material } => {
+                write!(f, "biome {} uses unknown material {}", biome, material)
+            }
+            BiomeError::NoBiomes => write!(f, "no biomes defined"),
+        }
+    }
+}
+
+impl std::error::Error for BiomeError {}
+
+impl From<std::io::Error> for BiomeError {
+    fn from(e: std::io::Error) -> Self {
+        BiomeError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for BiomeError {
+    fn from(e: toml::de::Error) -> Self {
+        BiomeError::Parse(e)
+    }
+}
+
+#[derive(Deserialize)]
+struct BiomeFile {
+    #[serde(default)]
+    biome: Vec<BiomeEntry>,
+}
+
+#[derive(Deserialize)]
+struct BiomeEntry {
+    name: String,
+    temperature: f64,
+    humidity: f64,
+    surface: String,
+    subsurface: String,
+    height: f64,
+    amplitude: f64,
+    decoration: Option<String>,
+    #[serde(default)]
+    decoration_density: f64,
+    #[serde(default = "one")]
+    decoration_height: i32,
+}
+
+fn one() -> i32 {
+    1
+}
+
+/// The biomes a world is made of, with their materials resolved against a registry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BiomeTable {
+    pub biomes: Vec<Biome>,
+}
+
+impl BiomeTable {
+    /// The biomes shipped in `assets/biomes.toml`.
+    pub fn builtin(materials: &MaterialRegistry) -> Result<Self, BiomeError> {
+        Self::from_toml(BUILTIN_BIOMES, materials)
+    }
+
+    pub fn load(path: &Path, materials: &MaterialRegistry) -> Result<Self, BiomeError> {
+        Self::from_toml(&std::fs::read_to_string(path)?, materials)
+    }
+
+    pub fn from_toml(source: &str, materials: &MaterialRegistry) -> Result<Self, BiomeError> {
+        let file: BiomeFile = toml::from_str(source)?;
+        if file.biome.is_empty() {
+            return Err(BiomeError::NoBiomes);
+        }
+        let biomes = file
+            .biome
+            .into_iter()
+            .map(|entry| {
+                let voxel = |material: &str| {
+                    materials
+                        .voxel(material)
+                        .ok_or_else(|| BiomeError::UnknownMaterial {
+                            biome: entry.name.clone(),
+                            material: material.to_string(),
+                        })
+                };
+                let decoration = match &entry.decoration {
+                    Some(material) => voxel(material)?,
+                    None => Voxel::empty(),
+                };
+                Ok(Biome {
+                    surface: voxel(&entry.surface)?,
+                    subsurface: voxel(&entry.subsurface)?,
+                    name: entry.name,
+                    temperature: entry.temperature,
+                    humidity: entry.humidity,
+                    height: entry.height,
+                    amplitude: entry.amplitude,
+                    decoration,
+                    decoration_density: entry.decoration_density.clamp(0.0, 1.0),
+                    decoration_height: entry.decoration_height.max(0),
+                })
+            })
+            .collect::<Result<_, BiomeError>>()?;
+        Ok(Self { biomes })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Biome> {
+        self.biomes.iter().find(|biome| biome.name == name)
+    }
+}
@@ -0,0 +1,233 @@
+use crate::chunk::ChunkPosition;
+use crate::map::{Map, VoxelPosition};
+use crate::voxel::Voxel;
+use nalgebra::Vector3;
+use rayon::prelude::*;
+use std::collections::HashSet;
+
+/// Volumes a brush can fill or clear. Voxels are tested at their centers, so a voxel at
+/// `(x, y, z)` is inside a shape when the point `(x + 0.5, y + 0.5, z + 0.5)` is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    /// Axis-aligned box between two voxels, both included.
+    Box {
+        min: Vector3<i32>,
+        max: Vector3<i32>,
+    },
+    Sphere {
+        center: Vector3<f32>,
+        radius: f32,
+    },
+    /// Cylinder with flat caps whose axis goes from `base` to `top`.
+    Cylinder {
+        base: Vector3<f32>,
+        top: Vector3<f32>,
+        radius: f32,
+    },
+    Ellipsoid {
+        center: Vector3<f32>,
+        radii: Vector3<f32>,
+    },
+}
+
+impl Shape {
+    pub fn contains(&self, point: Vector3<f32>) -> bool {
+        match *self {
+            Shape::Box { min, max } => {
+                let min = min.map(|v| v as f32);
+                let max = max.map(|v| v as f32 + 1.0);
+                (0..3).all(|axis| point[axis] >= min[axis] && point[axis] < max[axis])
+            }
+            Shape::Sphere { center, radius } => (point - center).norm_squared() <= radius * radius,
+            Shape::Cylinder { base, top, radius } => {
+                let axis = top - base;
+                let length_squared = axis.norm_squared();
+                if length_squared == 0.0 {
+                    return false;
+                }
+                let t = (point - base).dot(&axis) / length_squared;
+                let offset = point - (base + axis * t);
+                (0.0..=1.0).contains(&t) && offset.norm_squared() <= radius * radius
+            }
+            Shape::Ellipsoid { center, radii } => {
+                if radii.iter().any(|r| *r <= 0.0) {
+                    return false;
+                }
+                (point - center).component_div(&radii).norm_squared() <= 1.0
+            }
+        }
+    }
+
+    /// Smallest box of voxels, bounds included, holding every voxel inside the shape.
+    pub fn bounds(&self) -> (Vector3<i32>, Vector3<i32>) {
+        let around = |center: Vector3<f32>, extent: Vector3<f32>| {
+            (
+                (center - extent).map(|v| v.floor() as i32),
+                (center + extent).map(|v| v.ceil() as i32),
+            )
+        };
+        match *self {
+            Shape::Box { min, max } => (min.inf(&max), min.sup(&max)),
+            Shape::Sphere { center, radius } => around(center, Vector3::repeat(radius.abs())),
+            Shape::Cylinder { base, top, radius } => {
+                let (min, max) = (base.inf(&top), base.sup(&top));
+                around(
+                    (min + max) / 2.0,
+                    (max - min) / 2.0 + Vector3::repeat(radius.abs()),
+                )
+            }
+            Shape::Ellipsoid { center, radii } => around(center, radii.abs()),
+        }
+    }
+}
+
+/// How a brush combines with the voxels already in the map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsgOperation {
+    /// Sets every voxel inside the shape.
+    Union(Voxel),
+    /// Empties every voxel inside the shape.
+    Subtract,
+    /// Empties every voxel of the region that is outside the shape.
+    Intersect,
+}
+
+/// Voxels crossed by the 3D Bresenham line between two voxels, both included.
+pub fn line(from: Vector3<i32>, to: Vector3<i32>) -> Vec<Vector3<i32>> {
+    let delta = (to - from).abs();
+    let step = (to - from).map(i32::signum);
+    let driving = delta.imax();
+    let length = delta[driving];
+
+    let mut voxels = Vec::with_capacity(length as usize + 1);
+    let mut position = from;
+    let mut errors = delta.map(|d| 2 * d - length);
+    voxels.push(position);
+    for _ in 0..length {
+        position[driving] += step[driving];
+        for axis in (0..3).filter(|axis| *axis != driving) {
+            if errors[axis] >= 0 {
+                position[axis] += step[axis];
+                errors[axis] -= 2 * length;
+            }
+            errors[axis] += 2 * delta[axis];
+        }
+        voxels.push(position);
+    }
+    voxels
+}
+
+impl Map {
+    /// Sets every voxel inside the shape, see `apply_csg`.
+    pub fn fill_shape(&mut self, shape: &Shape, voxel: Voxel) -> HashSet<ChunkPosition> {
+        let (min, max) = shape.bounds();
+        self.apply_csg(min, max, CsgOperation::Union(voxel), |point| {
+            shape.contains(point)
+        })
+    }
+
+    /// Empties every voxel inside the shape, see `apply_csg`.
+    pub fn clear_shape(&mut self, shape: &Shape) -> HashSet<ChunkPosition> {
+        let (min, max) = shape.bounds();
+        self.apply_csg(min, max, CsgOperation::Subtract, |point| {
+            shape.contains(point)
+        })
+    }
+
+    /// Sets the voxels of the Bresenham line between two voxels.
+    pub fn fill_line(
+        &mut self,
+        from: Vector3<i32>,
+        to: Vector3<i32>,
+        voxel: Voxel,
+    ) -> HashSet<ChunkPosition> {
+        let edits: Vec<(VoxelPosition, Voxel)> = line(from, to)
+            .into_iter()
+            .filter(|p| self.get_voxel(p.x, p.y, p.z) != Some(&voxel))
+            .map(|p| ((p.x, p.y, p.z), voxel))
+            .collect();
+        let touched = edits
+            .iter()
+            .map(|((x, y, z), _)| (x.div_euclid(16), y.div_euclid(16), z.div_euclid(16)))
+            .collect();
+        self.set_voxels(edits);
+        touched
+    }
+
+    /// Combines the shape described by a signed distance function with the voxels of the
+    /// region between `min` and `max`, both included. A voxel is inside the shape when the
+    /// function is negative or zero at its center.
+    pub fn apply_sdf<F>(
+        &mut self,
+        min: Vector3<i32>,
+        max: Vector3<i32>,
+        operation: CsgOperation,
+        sdf: F,
+    ) -> HashSet<ChunkPosition>
+    where
+        F: Fn(Vector3<f32>) -> f32 + Sync,
+    {
+        self.apply_csg(min, max, operation, |point| sdf(point) <= 0.0)
+    }
+
+    /// Applies a CSG operation to the region between `min` and `max`, both included. Each
+    /// chunk overlapping the region is evaluated on its own rayon task, then the changes are
+    /// written in one batch so distance maps are refreshed once. Returns the chunks in which
+    /// at least one voxel changed.
+    pub fn apply_csg<F>(
+        &mut self,
+        min: Vector3<i32>,
+        max: Vector3<i32>,
+        operation: CsgOperation,
+        inside: F,
+    ) -> HashSet<ChunkPosition>
+    where
+        F: Fn(Vector3<f32>) -> bool + Sync,
+    {
+        let (min, max) = (min.inf(&max), min.sup(&max));
+        let first_chunk = min.map(|v| v.div_euclid(16));
+        let last_chunk = max.map(|v| v.div_euclid(16));
+        let mut positions = Vec::new();
+        for z in first_chunk.z..=last_chunk.z {
+            for y in first_chunk.y..=last_chunk.y {
+                for x in first_chunk.x..=last_chunk.x {
+                    positions.push((x, y, z));
+                }
+            }
+        }
+
+        let map = &*self;
+        let edits: Vec<(ChunkPosition, Vec<(VoxelPosition, Voxel)>)> = positions
+            .into_par_iter()
+            .map(|position| {
+                let origin = Vector3::new(position.0, position.1, position.2) * 16;
+                let from = min.sup(&origin);
+                let to = max.inf(&(origin + Vector3::repeat(15)));
+                let mut edits = Vec::new();
+                for z in from.z..=to.z {
+                    for y in from.y..=to.y {
+                        for x in from.x..=to.x {
+                            let center = Vector3::new(x, y, z).map(|v| v as f32 + 0.5);
+                            let current = map.get_voxel(x, y, z).copied().unwrap_or_default();
+                            let target = match operation {
+                                CsgOperation::Union(voxel) if inside(center) => voxel,
+                                CsgOperation::Subtract if inside(center) => Voxel::empty(),
+                                CsgOperation::Intersect if !inside(center) => Voxel::empty(),
+                                _ => current,
+                            };
+                            if target != current {
+                                edits.push(((x, y, z), target));
+                            }
+                        }
+                    }
+                }
+                (position, edits)
+            })
+            .filter(|(_, edits)| !edits.is_empty())
+            .collect();
+
+        let touched = edits.iter().map(|(position, _)| *position).collect();
+        self.set_voxels(edits.into_iter().flat_map(|(_, edits)| edits));
+        touched
+    }
+}
@@ -1,6 +1,11 @@
-use crate::renderer::Renderer;
+use crate::renderer::{RayHit, Renderer};
+use crate::voxel::Voxel;
+use image::RgbaImage;
 use nalgebra::{Rotation3, Vector3};
 use pixels::Pixels;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
 
 pub struct Camera {
     pub renderer: Renderer,
@@ -28,8 +33,77 @@ impl Camera {
     }
 
     pub fn draw_frame(&self, pixels: &mut Pixels) {
+        self.render_to_buffer(pixels.frame_mut());
+    }
+
+    pub fn render_to_buffer(&self, buf: &mut [u8]) {
+        self.renderer
+            .render_to_buffer(buf, self.position, self.rotation);
+    }
+
+    pub fn render_image(&self) -> RgbaImage {
+        self.renderer.render_image(self.position, self.rotation)
+    }
+
+    /// Renders a frame to a PNG file, storing the world seed and camera pose as text chunks
+    /// so the shot can be reproduced with `torus render`.
+    pub fn save_screenshot(&self, path: &Path) -> Result<(), png::EncodingError> {
+        let image = self.render_image();
+        let writer = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(writer, image.width(), image.height());
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.add_text_chunk("torus:seed".to_string(), self.renderer.map.seed.to_string())?;
+        encoder.add_text_chunk(
+            "torus:pos".to_string(),
+            format!(
+                "{},{},{}",
+                self.position.x, self.position.y, self.position.z
+            ),
+        )?;
+        encoder.add_text_chunk(
+            "torus:rot".to_string(),
+            format!(
+                "{},{},{}",
+                self.rotation.x, self.rotation.y, self.rotation.z
+            ),
+        )?;
+        encoder.write_header()?.write_image_data(&image)?;
+        Ok(())
+    }
+
+    /// The voxel under the center of the screen, if any.
+    pub fn target(&self) -> Option<RayHit<'_>> {
+        let rotation_matrix =
+            Rotation3::from_euler_angles(self.rotation.x, self.rotation.y, self.rotation.z);
+        let direction = rotation_matrix * Vector3::new(0.0, 0.0, 1.0);
+        self.renderer.dda(&self.position, &direction, 64)
+    }
+
+    /// Places a voxel against the face of the targeted voxel. Returns false if nothing
+    /// changed, also when that face borders a chunk that is still being streamed in.
+    pub fn place_voxel(&mut self, voxel: Voxel) -> bool {
+        let Some(hit) = self.target() else {
+            return false;
+        };
+        let position = hit.position + hit.normal.map(|v| v as i32);
+        let placed = self
+            .renderer
+            .map
+            .set_voxels([((position.x, position.y, position.z), voxel)]);
+        placed > 0
+    }
+
+    /// Removes the targeted voxel.
+    pub fn remove_voxel(&mut self) -> bool {
+        let Some(hit) = self.target() else {
+            return false;
+        };
+        let position = hit.position;
         self.renderer
-            .draw_frame(pixels, self.position, self.rotation);
+            .map
+            .remove_voxel(position.x, position.y, position.z);
+        true
     }
 
     pub fn move_forward(&mut self) {
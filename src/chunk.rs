@@ -1,21 +1,24 @@
+use crate::distance::distance_field;
 use crate::map::Map;
-use crate::perlin::PerlinGenerator;
+use crate::palette::PalettedArray;
 use crate::voxel::Voxel;
 use nalgebra::Vector3;
+use std::mem::size_of;
+use std::ops::{Deref, DerefMut};
 
 pub type ChunkPosition = (i32, i32, i32);
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Chunk {
-    data: Vec<Voxel>,
-    distance_map: Vec<u8>,
+    data: PalettedArray<Voxel>,
+    distance_map: PalettedArray<u8>,
     pub position: ChunkPosition,
 }
 
 impl Chunk {
     pub fn new(position: ChunkPosition) -> Self {
-        let data = vec![Voxel::empty(); 4096];
-        let distance_map = vec![0; 4096];
+        let data = PalettedArray::new(4096, Voxel::empty());
+        let distance_map = PalettedArray::new(4096, 0);
         Self {
             data,
             distance_map,
@@ -25,59 +28,64 @@ impl Chunk {
 
     pub fn get_voxel(&self, x: u8, y: u8, z: u8) -> Option<&Voxel> {
         if x < 16 && y < 16 && z < 16 {
-            Some(&self.data[Chunk::get_index(x, y, z)])
+            Some(self.data.get(Chunk::get_index(x, y, z)))
         } else {
             None
         }
     }
 
-    pub fn get_voxel_mut(&mut self, x: u8, y: u8, z: u8) -> Option<&mut Voxel> {
+    pub fn get_voxel_mut(&mut self, x: u8, y: u8, z: u8) -> Option<VoxelMut<'_>> {
         if x < 16 && y < 16 && z < 16 {
-            Some(&mut self.data[Chunk::get_index(x, y, z)])
+            let index = Chunk::get_index(x, y, z);
+            let voxel = *self.data.get(index);
+            Some(VoxelMut {
+                data: &mut self.data,
+                index,
+                voxel,
+            })
         } else {
             None
         }
     }
 
-    pub fn set(&mut self, x: u8, y: u8, z: u8, voxel: Voxel) -> () {
+    pub fn set(&mut self, x: u8, y: u8, z: u8, voxel: Voxel) {
         if x < 16 && y < 16 && z < 16 {
-            self.data[Chunk::get_index(x, y, z)] = voxel;
+            self.data.set(Chunk::get_index(x, y, z), voxel);
         }
     }
 
-    pub fn generate(&mut self, pos: Vector3<i32>, perlin: &PerlinGenerator) {
-        let scale = 16.0;
-        for x in 0..16 {
-            for y in 0..16 {
-                for z in 0..16 {
-                    let noise = perlin.get(
-                        (x + pos.x) as f64 / scale,
-                        (y + pos.y) as f64 / scale,
-                        (z + pos.z) as f64 / scale,
-                    );
-                    if noise > 0.5 {
-                        let color = (noise * 255.0) as u8;
-                        self.set(
-                            x as u8,
-                            y as u8,
-                            z as u8,
-                            Voxel::new(Vector3::new(color, color, color)),
-                        );
-                    }
-                }
-            }
-        }
+    /// Returns true if every voxel of the chunk is empty.
+    pub fn is_empty(&self) -> bool {
+        self.data.uniform().is_some_and(|voxel| voxel.is_empty())
+    }
+
+    /// Drops unused palette entries and narrows the packed indices of the voxel and distance
+    /// storage. Worth calling after a batch of edits.
+    pub fn compact(&mut self) {
+        self.data.compact();
+        self.distance_map.compact();
+    }
+
+    /// Whether `compact` would narrow the voxel storage, because fewer distinct voxels are left
+    /// than its indices can tell apart.
+    pub fn can_shrink(&self) -> bool {
+        self.data.can_shrink()
+    }
+
+    /// Approximate number of bytes used by this chunk.
+    pub fn memory_usage(&self) -> usize {
+        size_of::<ChunkPosition>() + self.data.memory_usage() + self.distance_map.memory_usage()
     }
 
     pub fn set_distance(&mut self, x: u8, y: u8, z: u8, distance: u8) {
         if x < 16 && y < 16 && z < 16 {
-            self.distance_map[Chunk::get_index(x, y, z)] = distance;
+            self.distance_map.set(Chunk::get_index(x, y, z), distance);
         }
     }
 
     pub fn get_distance(&self, x: u8, y: u8, z: u8) -> u8 {
         if x < 16 && y < 16 && z < 16 {
-            self.distance_map[Chunk::get_index(x, y, z)]
+            *self.distance_map.get(Chunk::get_index(x, y, z))
         } else {
             1
         }
@@ -87,36 +95,44 @@ impl Chunk {
         z as usize * 256 + y as usize * 16 + x as usize
     }
 
+    /// Fills the distance map with the distance from each voxel to the nearest solid voxel of
+    /// the map, capped at `radius`. Solid voxels get a distance of 0.
     pub fn generate_distance_map(&mut self, map: &Map, radius: i32) {
-        let start_distance = radius as f32;
-        for x in 0..16 {
-            for y in 0..16 {
-                for z in 0..16 {
-                    let mut min_distance = start_distance;
-                    for dx in -radius..=radius {
-                        for dy in -radius..=radius {
-                            for dz in -radius..=radius {
-                                if dx == 0 && dy == 0 && dz == 0 {
-                                    continue;
-                                }
-
-                                if let Some(voxel) = map.get_voxel(
-                                    (x + dx + (self.position.0 * 16)) as i32,
-                                    (y + dy + (self.position.1 * 16)) as i32,
-                                    (z + dz + (self.position.2 * 16)) as i32,
-                                ) {
-                                    if !voxel.is_empty {
-                                        let distance =
-                                            ((dx * dx + dy * dy + dz * dz) as f32).sqrt();
-                                        min_distance = min_distance.min(distance);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    self.set_distance(x as u8, y as u8, z as u8, min_distance as u8);
-                }
-            }
+        let origin = Vector3::new(self.position.0, self.position.1, self.position.2) * 16;
+        let distances = distance_field(map, origin, Vector3::repeat(16), radius);
+        for (index, distance) in distances.into_iter().enumerate() {
+            self.distance_map.set(index, distance);
+        }
+        self.distance_map.compact();
+    }
+}
+
+/// Mutable access to a single voxel of a chunk. Voxels are stored packed, so the change is
+/// written back to the chunk when the guard is dropped.
+pub struct VoxelMut<'a> {
+    data: &'a mut PalettedArray<Voxel>,
+    index: usize,
+    voxel: Voxel,
+}
+
+impl Deref for VoxelMut<'_> {
+    type Target = Voxel;
+
+    fn deref(&self) -> &Voxel {
+        &self.voxel
+    }
+}
+
+impl DerefMut for VoxelMut<'_> {
+    fn deref_mut(&mut self) -> &mut Voxel {
+        &mut self.voxel
+    }
+}
+
+impl Drop for VoxelMut<'_> {
+    fn drop(&mut self) {
+        if *self.data.get(self.index) != self.voxel {
+            self.data.set(self.index, self.voxel);
         }
     }
 }
@@ -0,0 +1,161 @@
+use crate::chunk::Chunk;
+use crate::map::{ChunkState, Map};
+use nalgebra::Vector3;
+
+/// Computes the distance from every voxel of the box `[min, min + size)` to the nearest solid
+/// voxel of the map, truncated to whole voxels and capped at `radius`.
+///
+/// The map is sampled over the box plus a halo of `radius` voxels, so any solid voxel closer than
+/// `radius` is taken into account. Chunks are never faulted in: those that are not in memory
+/// but may hold voxels in the chunk source count as solid, so the distances never overestimate
+/// until they are loaded. Squared distances come from a separable exact Euclidean distance transform
+/// (Felzenszwalb & Huttenlocher), one linear pass per axis. Values are returned in x-fastest
+/// order.
+pub fn distance_field(map: &Map, min: Vector3<i32>, size: Vector3<usize>, radius: i32) -> Vec<u8> {
+    let radius = radius.max(0);
+    let halo = radius as usize;
+    let dims = size.map(|v| v + 2 * halo);
+    let origin = min.map(|v| v - radius);
+    let index = |x: usize, y: usize, z: usize| (z * dims.y + y) * dims.x + x;
+
+    let mut field = vec![f64::INFINITY; dims.x * dims.y * dims.z];
+    sample_solids(map, origin, dims, &mut field);
+
+    let longest = dims.x.max(dims.y).max(dims.z);
+    let mut line = vec![0.0; longest];
+    let mut output = vec![0.0; longest];
+    let mut sites = vec![0; longest];
+    let mut bounds = vec![0.0; longest + 1];
+    // Transforms the line of `n` samples starting at `start` and `stride` apart.
+    let mut transform = |field: &mut [f64], start: usize, stride: usize, n: usize| {
+        for (i, value) in line[..n].iter_mut().enumerate() {
+            *value = field[start + i * stride];
+        }
+        transform_line(
+            &line[..n],
+            &mut output[..n],
+            &mut sites[..n],
+            &mut bounds[..n + 1],
+        );
+        for (i, value) in output[..n].iter().enumerate() {
+            field[start + i * stride] = *value;
+        }
+    };
+
+    for z in 0..dims.z {
+        for y in 0..dims.y {
+            transform(&mut field, index(0, y, z), 1, dims.x);
+        }
+    }
+    for z in 0..dims.z {
+        for x in 0..dims.x {
+            transform(&mut field, index(x, 0, z), dims.x, dims.y);
+        }
+    }
+    for y in 0..dims.y {
+        for x in 0..dims.x {
+            transform(&mut field, index(x, y, 0), dims.x * dims.y, dims.z);
+        }
+    }
+
+    let mut distances = Vec::with_capacity(size.x * size.y * size.z);
+    for z in 0..size.z {
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let squared = field[index(x + halo, y + halo, z + halo)];
+                distances.push(squared.sqrt().min(radius as f64) as u8);
+            }
+        }
+    }
+    distances
+}
+
+/// Writes 0 into `field` for every solid voxel of the box, reading each overlapping chunk once
+/// instead of looking every voxel up through the map.
+fn sample_solids(map: &Map, origin: Vector3<i32>, dims: Vector3<usize>, field: &mut [f64]) {
+    let end = origin + dims.map(|v| v as i32);
+    let first_chunk = origin.map(|v| v.div_euclid(16));
+    let last_chunk = (end - Vector3::repeat(1)).map(|v| v.div_euclid(16));
+
+    for chunk_z in first_chunk.z..=last_chunk.z {
+        for chunk_y in first_chunk.y..=last_chunk.y {
+            for chunk_x in first_chunk.x..=last_chunk.x {
+                let chunk_origin = Vector3::new(chunk_x, chunk_y, chunk_z) * 16;
+                let chunk = match map.distance_chunk_state(
+                    chunk_origin.x,
+                    chunk_origin.y,
+                    chunk_origin.z,
+                ) {
+                    ChunkState::Loaded(chunk) => Some(chunk),
+                    ChunkState::Unloaded => None,
+                    ChunkState::Empty => continue,
+                };
+                if chunk.is_some_and(Chunk::is_empty) {
+                    continue;
+                }
+
+                let from = origin.zip_map(&chunk_origin, |a, b| a.max(b));
+                let to = end.zip_map(&(chunk_origin + Vector3::repeat(16)), |a, b| a.min(b));
+                for z in from.z..to.z {
+                    for y in from.y..to.y {
+                        for x in from.x..to.x {
+                            let local = Vector3::new(x, y, z) - chunk_origin;
+                            let solid = chunk.is_none_or(|chunk| {
+                                chunk
+                                    .get_voxel(local.x as u8, local.y as u8, local.z as u8)
+                                    .is_some_and(|voxel| !voxel.is_empty())
+                            });
+                            if solid {
+                                let cell = Vector3::new(x, y, z) - origin;
+                                let index = (cell.z as usize * dims.y + cell.y as usize) * dims.x
+                                    + cell.x as usize;
+                                field[index] = 0.0;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// One-dimensional squared distance transform: `output[q] = min_p (q - p)² + input[p]`, using
+/// the lower envelope of the parabolas rooted at the finite input samples.
+fn transform_line(input: &[f64], output: &mut [f64], sites: &mut [usize], bounds: &mut [f64]) {
+    let intersection = |p: usize, q: usize| {
+        let (p_f, q_f) = (p as f64, q as f64);
+        ((input[q] + q_f * q_f) - (input[p] + p_f * p_f)) / (2.0 * q_f - 2.0 * p_f)
+    };
+
+    let mut count = 0;
+    for (q, sample) in input.iter().enumerate() {
+        if sample.is_infinite() {
+            continue;
+        }
+        while count > 0 && intersection(sites[count - 1], q) <= bounds[count - 1] {
+            count -= 1;
+        }
+        bounds[count] = if count == 0 {
+            f64::NEG_INFINITY
+        } else {
+            intersection(sites[count - 1], q)
+        };
+        sites[count] = q;
+        count += 1;
+    }
+
+    if count == 0 {
+        output.fill(f64::INFINITY);
+        return;
+    }
+    bounds[count] = f64::INFINITY;
+
+    let mut k = 0;
+    for (q, value) in output.iter_mut().enumerate() {
+        while bounds[k + 1] < q as f64 {
+            k += 1;
+        }
+        let offset = q as f64 - sites[k] as f64;
+        *value = offset * offset + input[sites[k]];
+    }
+}
@@ -0,0 +1,300 @@
+use crate::material::MaterialRegistry;
+use crate::voxel::Voxel;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+const BUILTIN_FEATURES: &str = include_str!("../assets/features.toml");
+
+/// A small voxel template, like a tree or a boulder, placed as a whole by a `Feature`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Structure {
+    pub name: String,
+    /// Solid voxels of the template, relative to its anchor, which is put right above the
+    /// ground.
+    pub voxels: Vec<((i32, i32, i32), Voxel)>,
+}
+
+impl Structure {
+    /// Voxels of the template turned `quarter_turns` times around the y axis of its anchor.
+    pub fn rotated(
+        &self,
+        quarter_turns: u8,
+    ) -> impl Iterator<Item = ((i32, i32, i32), Voxel)> + '_ {
+        self.voxels.iter().map(move |&((x, y, z), voxel)| {
+            let (x, z) = match quarter_turns % 4 {
+                0 => (x, z),
+                1 => (-z, x),
+                2 => (-x, -z),
+                _ => (z, -x),
+            };
+            ((x, y, z), voxel)
+        })
+    }
+
+    /// Largest horizontal distance from the anchor to a voxel, along x or z, in any rotation.
+    pub fn reach(&self) -> i32 {
+        self.voxels
+            .iter()
+            .map(|((x, _, z), _)| x.abs().max(z.abs()))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Lowest and highest voxel relative to the anchor.
+    pub fn vertical_extent(&self) -> (i32, i32) {
+        let ys = self.voxels.iter().map(|((_, y, _), _)| *y);
+        (ys.clone().min().unwrap_or(0), ys.max().unwrap_or(0))
+    }
+}
+
+/// Scatters a structure over the ground.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Feature {
+    /// Names the random stream of the feature, so adding a feature never moves the others.
+    pub name: String,
+    pub structure: Structure,
+    /// Ground voxels the structure may stand on, any ground when empty.
+    pub on: Vec<Voxel>,
+    /// Fraction of the columns with a suitable ground that get the structure.
+    pub density: f64,
+    /// Turns every structure a random number of quarter turns.
+    pub rotate: bool,
+}
+
+/// Blobs of a material, like ores, replacing other voxels underground.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vein {
+    pub name: String,
+    pub material: Voxel,
+    /// Voxels the vein may replace, everything else is left as is.
+    pub replaces: Vec<Voxel>,
+    /// Length of the random walk that traces the vein, in voxels.
+    pub size: i32,
+    /// Veins started in every 16×16×16 block of voxels.
+    pub count: u32,
+    /// Veins start between these heights, inclusive.
+    pub min_y: i32,
+    pub max_y: i32,
+}
+
+#[derive(Debug)]
+pub enum FeatureError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    UnknownMaterial { name: String, material: String },
+    UnknownStructure(String),
+    InvalidTemplate { structure: String, character: char },
+}
+
+impl fmt::Display for FeatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeatureError::Io(e) => write!(f, "could not read feature file: {}", e),
+            FeatureError::Parse(e) => write!(f, "invalid feature file: {}", e),
+            FeatureError::UnknownMaterial { name, material } => {
+                write!(f, "{} uses unknown material {}", name, material)
+            }
+            FeatureError::UnknownStructure(name) => write!(f, "unknown structure {}", name),
+            FeatureError::InvalidTemplate {
+                structure,
+                character,
+            } => write!(
+                f,
+                "structure {} uses {:?}, which is not in its palette",
+                structure, character
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FeatureError {}
+
+impl From<std::io::Error> for FeatureError {
+    fn from(e: std::io::Error) -> Self {
+        FeatureError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for FeatureError {
+    fn from(e: toml::de::Error) -> Self {
+        FeatureError::Parse(e)
+    }
+}
+
+#[derive(Deserialize)]
+struct FeatureFile {
+    #[serde(default)]
+    structure: Vec<StructureEntry>,
+    #[serde(default)]
+    feature: Vec<FeatureEntry>,
+    #[serde(default)]
+    vein: Vec<VeinEntry>,
+}
+
+#[derive(Deserialize)]
+struct StructureEntry {
+    name: String,
+    palette: HashMap<char, String>,
+    #[serde(default)]
+    anchor: [i32; 3],
+    layers: Vec<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct FeatureEntry {
+    name: Option<String>,
+    structure: String,
+    #[serde(default)]
+    on: Vec<String>,
+    density: f64,
+    #[serde(default)]
+    rotate: bool,
+}
+
+#[derive(Deserialize)]
+struct VeinEntry {
+    name: Option<String>,
+    material: String,
+    replaces: Vec<String>,
+    size: i32,
+    count: u32,
+    #[serde(default = "lowest")]
+    min_y: i32,
+    #[serde(default = "highest")]
+    max_y: i32,
+}
+
+fn lowest() -> i32 {
+    i32::MIN
+}
+
+fn highest() -> i32 {
+    i32::MAX
+}
+
+/// The structures, features and veins added to generated worlds, see `FeatureGenerator`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeatureSet {
+    pub structures: Vec<Structure>,
+    /// Placed in order: where structures overlap, the first one wins.
+    pub features: Vec<Feature>,
+    pub veins: Vec<Vein>,
+}
+
+impl FeatureSet {
+    /// The features shipped in `assets/features.toml`.
+    pub fn builtin(materials: &MaterialRegistry) -> Result<Self, FeatureError> {
+        Self::from_toml(BUILTIN_FEATURES, materials)
+    }
+
+    pub fn load(path: &Path, materials: &MaterialRegistry) -> Result<Self, FeatureError> {
+        Self::from_toml(&std::fs::read_to_string(path)?, materials)
+    }
+
+    pub fn from_toml(source: &str, materials: &MaterialRegistry) -> Result<Self, FeatureError> {
+        let file: FeatureFile = toml::from_str(source)?;
+        let voxel = |name: &str, material: &str| {
+            materials
+                .voxel(material)
+                .ok_or_else(|| FeatureError::UnknownMaterial {
+                    name: name.to_string(),
+                    material: material.to_string(),
+                })
+        };
+        let voxels = |name: &str, materials: &[String]| {
+            materials
+                .iter()
+                .map(|material| voxel(name, material))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        let structures = file
+            .structure
+            .into_iter()
+            .map(|entry| {
+                let palette = entry
+                    .palette
+                    .iter()
+                    .map(|(character, material)| Ok((*character, voxel(&entry.name, material)?)))
+                    .collect::<Result<HashMap<_, _>, FeatureError>>()?;
+                let [anchor_x, anchor_y, anchor_z] = entry.anchor;
+                let mut voxels = Vec::new();
+                for (y, layer) in entry.layers.iter().enumerate() {
+                    for (z, row) in layer.iter().enumerate() {
+                        for (x, character) in row.chars().enumerate() {
+                            if character == '.' || character == ' ' {
+                                continue;
+                            }
+                            let voxel = *palette.get(&character).ok_or_else(|| {
+                                FeatureError::InvalidTemplate {
+                                    structure: entry.name.clone(),
+                                    character,
+                                }
+                            })?;
+                            let offset = (
+                                x as i32 - anchor_x,
+                                y as i32 - anchor_y,
+                                z as i32 - anchor_z,
+                            );
+                            voxels.push((offset, voxel));
+                        }
+                    }
+                }
+                Ok(Structure {
+                    name: entry.name,
+                    voxels,
+                })
+            })
+            .collect::<Result<Vec<_>, FeatureError>>()?;
+
+        let features = file
+            .feature
+            .into_iter()
+            .map(|entry| {
+                let structure = structures
+                    .iter()
+                    .find(|structure| structure.name == entry.structure)
+                    .ok_or_else(|| FeatureError::UnknownStructure(entry.structure.clone()))?;
+                let name = entry.name.unwrap_or(entry.structure);
+                Ok(Feature {
+                    on: voxels(&name, &entry.on)?,
+                    name,
+                    structure: structure.clone(),
+                    density: entry.density.clamp(0.0, 1.0),
+                    rotate: entry.rotate,
+                })
+            })
+            .collect::<Result<_, FeatureError>>()?;
+
+        let veins = file
+            .vein
+            .into_iter()
+            .map(|entry| {
+                let name = entry.name.unwrap_or_else(|| entry.material.clone());
+                Ok(Vein {
+                    material: voxel(&name, &entry.material)?,
+                    replaces: voxels(&name, &entry.replaces)?,
+                    name,
+                    size: entry.size.max(1),
+                    count: entry.count,
+                    min_y: entry.min_y,
+                    max_y: entry.max_y,
+                })
+            })
+            .collect::<Result<_, FeatureError>>()?;
+
+        Ok(Self {
+            structures,
+            features,
+            veins,
+        })
+    }
+
+    pub fn structure(&self, name: &str) -> Option<&Structure> {
+        self.structures
+            .iter()
+            .find(|structure| structure.name == name)
+    }
+}
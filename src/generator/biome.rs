@@ -0,0 +1,187 @@
+use super::{TerrainGenerator, WorldGenerator};
+use crate::biome::{Biome, BiomeTable};
+use crate::chunk::{Chunk, ChunkPosition};
+use crate::material::MaterialRegistry;
+use crate::perlin::PerlinGenerator;
+use crate::seed::WorldSeed;
+use crate::voxel::Voxel;
+
+/// Terrain shaped and dressed by biomes. Two noise fields give each column a temperature and
+/// a humidity, and every biome weighs in according to how close its climate is, so heights
+/// blend smoothly across borders. Surface materials and decorations come from one biome per
+/// column, drawn at random by weight, which dithers the borders.
+///
+/// The heightmap noise, the caves, the subsurface depth and the snow level come from
+/// `terrain`.
+pub struct BiomeGenerator {
+    pub terrain: TerrainGenerator,
+    pub biomes: BiomeTable,
+    temperature: PerlinGenerator,
+    humidity: PerlinGenerator,
+    seed: WorldSeed,
+    /// Horizontal size in voxels of the climate zones.
+    pub climate_scale: f64,
+    /// Factor applied to the climate noise, which rarely strays far from 0, before it is
+    /// clamped to [-1, 1].
+    pub climate_contrast: f64,
+    /// Climate distance over which a biome fades out, larger values widen the borders. 0 or
+    /// less gives hard borders.
+    pub blend: f64,
+}
+
+impl BiomeGenerator {
+    pub fn new(seed: WorldSeed, biomes: BiomeTable, materials: &MaterialRegistry) -> Self {
+        Self {
+            terrain: TerrainGenerator::new(seed, materials),
+            biomes,
+            temperature: PerlinGenerator::new(seed.derive_u32("temperature")),
+            humidity: PerlinGenerator::new(seed.derive_u32("humidity")),
+            seed,
+            climate_scale: 512.0,
+            climate_contrast: 2.5,
+            blend: 0.25,
+        }
+    }
+
+    /// Temperature and humidity of the column, both in [-1, 1].
+    pub fn climate_at(&self, x: i32, z: i32) -> (f64, f64) {
+        let sample = |noise: &PerlinGenerator| {
+            let value = noise.fbm_2d(
+                x as f64 / self.climate_scale,
+                z as f64 / self.climate_scale,
+                3,
+                2.0,
+                0.5,
+            );
+            (value * self.climate_contrast).clamp(-1.0, 1.0)
+        };
+        (sample(&self.temperature), sample(&self.humidity))
+    }
+
+    /// Influence of every biome on the column, in table order, summing to 1.
+    pub fn weights_at(&self, x: i32, z: i32) -> Vec<f64> {
+        let (temperature, humidity) = self.climate_at(x, z);
+        let distances: Vec<f64> = self
+            .biomes
+            .biomes
+            .iter()
+            .map(|biome| {
+                (biome.temperature - temperature).powi(2) + (biome.humidity - humidity).powi(2)
+            })
+            .collect();
+        // Relative to the closest biome, so far from every biome the weights do not all
+        // underflow.
+        let closest = distances.iter().copied().fold(f64::INFINITY, f64::min);
+        let spread = 2.0 * self.blend.powi(2);
+        let weights: Vec<f64> = if self.blend > 0.0 && spread > 0.0 {
+            distances
+                .iter()
+                .map(|distance| (-(distance - closest) / spread).exp())
+                .collect()
+        } else {
+            // No blending at all: the closest biome takes the whole column.
+            let nearest = distances.iter().position(|distance| *distance == closest);
+            (0..distances.len())
+                .map(|index| if Some(index) == nearest { 1.0 } else { 0.0 })
+                .collect()
+        };
+        let total: f64 = weights.iter().sum();
+        weights.into_iter().map(|weight| weight / total).collect()
+    }
+
+    /// The biome with the most influence on the column.
+    pub fn biome_at(&self, x: i32, z: i32) -> &Biome {
+        let weights = self.weights_at(x, z);
+        let strongest = (0..weights.len())
+            .max_by(|a, b| weights[*a].total_cmp(&weights[*b]))
+            .unwrap_or(0);
+        &self.biomes.biomes[strongest]
+    }
+
+    /// Height of the ground in the column: its highest solid voxel is at `height - 1`.
+    pub fn height_at(&self, x: i32, z: i32) -> i32 {
+        self.height_with(x, z, &self.weights_at(x, z))
+    }
+
+    fn height_with(&self, x: i32, z: i32, weights: &[f64]) -> i32 {
+        let noise = self.terrain.noise_at(x, z);
+        let height: f64 = self
+            .biomes
+            .biomes
+            .iter()
+            .zip(weights)
+            .map(|(biome, weight)| (biome.height + noise * biome.amplitude) * weight)
+            .sum();
+        height.round() as i32
+    }
+
+    /// The biome dressing the column, drawn by weight.
+    fn surface_biome(&self, x: i32, z: i32, weights: &[f64]) -> &Biome {
+        let mut draw = self.seed.random_at("biome_surface", (x, 0, z));
+        for (biome, weight) in self.biomes.biomes.iter().zip(weights) {
+            if draw < *weight {
+                return biome;
+            }
+            draw -= weight;
+        }
+        self.biomes.biomes.last().unwrap()
+    }
+}
+
+impl WorldGenerator for BiomeGenerator {
+    fn generate_chunk(&self, position: ChunkPosition, chunk: &mut Chunk) {
+        let origin = (position.0 * 16, position.1 * 16, position.2 * 16);
+        let terrain = &self.terrain;
+        for z in 0..16 {
+            for x in 0..16 {
+                let (world_x, world_z) = (origin.0 + x, origin.2 + z);
+                let weights = self.weights_at(world_x, world_z);
+                let height = self.height_with(world_x, world_z, &weights);
+                let biome = self.surface_biome(world_x, world_z, &weights);
+
+                let decorated = !biome.decoration.is_empty()
+                    && self.seed.random_at("decorations", (world_x, 0, world_z))
+                        < biome.decoration_density;
+                let top = if decorated {
+                    height + biome.decoration_height
+                } else {
+                    height
+                };
+                for y in 0..16 {
+                    let world_y = origin.1 + y;
+                    if world_y >= top {
+                        break;
+                    }
+                    let depth = height - 1 - world_y;
+                    let voxel = if depth < 0 {
+                        biome.decoration
+                    } else if depth >= terrain.cave_depth
+                        && terrain.is_cave(world_x, world_y, world_z)
+                    {
+                        continue;
+                    } else if depth == 0 && height >= terrain.snow_level {
+                        terrain.layer_at(height, 0)
+                    } else if depth == 0 {
+                        biome.surface
+                    } else if depth <= terrain.subsurface_depth {
+                        biome.subsurface
+                    } else {
+                        terrain.layer_at(height, depth)
+                    };
+                    chunk.set(x as u8, y as u8, z as u8, voxel);
+                }
+            }
+        }
+    }
+
+    fn surface_at(&self, x: i32, z: i32) -> Option<(i32, Voxel)> {
+        let weights = self.weights_at(x, z);
+        let height = self.height_with(x, z, &weights);
+        let surface = if height >= self.terrain.snow_level {
+            self.terrain.layer_at(height, 0)
+        } else {
+            self.surface_biome(x, z, &weights).surface
+        };
+        Some((height, surface))
+    }
+}
@@ -0,0 +1,47 @@
+use super::WorldGenerator;
+use crate::chunk::{Chunk, ChunkPosition};
+use crate::material::MaterialRegistry;
+use crate::perlin::PerlinGenerator;
+use crate::seed::WorldSeed;
+use crate::voxel::Voxel;
+
+/// Stone wherever 3D Perlin noise exceeds a threshold, carving a maze of caves.
+pub struct PerlinCaves {
+    perlin: PerlinGenerator,
+    /// Size in voxels of the noise features.
+    pub scale: f64,
+    /// Noise value above which voxels are solid, higher values leave more empty space.
+    pub threshold: f64,
+    pub voxel: Voxel,
+}
+
+impl PerlinCaves {
+    pub fn new(seed: WorldSeed, materials: &MaterialRegistry) -> Self {
+        Self {
+            perlin: PerlinGenerator::new(seed.derive_u32("caves")),
+            scale: 16.0,
+            threshold: 0.5,
+            voxel: materials.voxel("stone").unwrap_or_default(),
+        }
+    }
+}
+
+impl WorldGenerator for PerlinCaves {
+    fn generate_chunk(&self, position: ChunkPosition, chunk: &mut Chunk) {
+        let origin = (position.0 * 16, position.1 * 16, position.2 * 16);
+        for x in 0..16 {
+            for y in 0..16 {
+                for z in 0..16 {
+                    let noise = self.perlin.get(
+                        (x + origin.0) as f64 / self.scale,
+                        (y + origin.1) as f64 / self.scale,
+                        (z + origin.2) as f64 / self.scale,
+                    );
+                    if noise > self.threshold {
+                        chunk.set(x as u8, y as u8, z as u8, self.voxel);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use super::WorldGenerator;
+use crate::chunk::{Chunk, ChunkPosition};
+use crate::material::MaterialRegistry;
+use crate::voxel::Voxel;
+
+/// A checkerboard floor with a marker along each positive axis from the origin, to tell at a
+/// glance where a camera is and which way it looks.
+///
+/// The floor is the layer y = -1, in squares of `square_size` voxels. The markers are
+/// columns of `marker_length` voxels starting next to the origin: lava along x, grass along
+/// y and gold ore along z.
+pub struct DebugGenerator {
+    pub square_size: i32,
+    pub marker_length: i32,
+    pub floor: [Voxel; 2],
+    pub markers: [Voxel; 3],
+}
+
+impl DebugGenerator {
+    pub fn new(materials: &MaterialRegistry) -> Self {
+        let voxel = |name| materials.voxel(name).unwrap_or_default();
+        Self {
+            square_size: 4,
+            marker_length: 8,
+            floor: [voxel("stone"), voxel("snow")],
+            markers: [voxel("lava"), voxel("grass"), voxel("gold_ore")],
+        }
+    }
+
+    fn voxel_at(&self, x: i32, y: i32, z: i32) -> Voxel {
+        if y == -1 {
+            let square =
+                (x.div_euclid(self.square_size) + z.div_euclid(self.square_size)).rem_euclid(2);
+            return self.floor[square as usize];
+        }
+        let on_marker = |along: i32, across: [i32; 2]| {
+            across == [0, 0] && (1..=self.marker_length).contains(&along)
+        };
+        if on_marker(x, [y, z]) {
+            self.markers[0]
+        } else if on_marker(y, [x, z]) {
+            self.markers[1]
+        } else if on_marker(z, [x, y]) {
+            self.markers[2]
+        } else {
+            Voxel::empty()
+        }
+    }
+}
+
+impl WorldGenerator for DebugGenerator {
+    fn generate_chunk(&self, position: ChunkPosition, chunk: &mut Chunk) {
+        let origin = (position.0 * 16, position.1 * 16, position.2 * 16);
+        // Everything lies in the floor layer or on the axes.
+        if origin.1 > self.marker_length || origin.1 + 15 < -1 {
+            return;
+        }
+        for z in 0..16 {
+            for y in 0..16 {
+                for x in 0..16 {
+                    let voxel = self.voxel_at(origin.0 + x, origin.1 + y, origin.2 + z);
+                    if !voxel.is_empty() {
+                        chunk.set(x as u8, y as u8, z as u8, voxel);
+                    }
+                }
+            }
+        }
+    }
+}
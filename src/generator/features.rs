@@ -0,0 +1,150 @@
+use super::WorldGenerator;
+use crate::chunk::{Chunk, ChunkPosition};
+use crate::feature::{Feature, FeatureSet, Vein};
+use crate::seed::WorldSeed;
+use crate::voxel::Voxel;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::Arc;
+
+/// Adds features on top of another generator: structures standing on its ground and veins
+/// replacing its voxels underground.
+///
+/// Structures and veins may reach into neighboring chunks. Every chunk looks at all the ones
+/// that could touch it, placed from positional hashes of the world seed, and keeps the part
+/// that falls inside, so chunks still only depend on their position. Structures only fill
+/// empty voxels and veins only replace their materials, both as the base generator left them.
+pub struct FeatureGenerator {
+    pub base: Arc<dyn WorldGenerator>,
+    pub features: FeatureSet,
+    seed: WorldSeed,
+}
+
+impl FeatureGenerator {
+    pub fn new(seed: WorldSeed, base: Arc<dyn WorldGenerator>, features: FeatureSet) -> Self {
+        Self {
+            base,
+            features,
+            seed,
+        }
+    }
+
+    fn place_vein(&self, vein: &Vein, position: ChunkPosition, chunk: &mut Chunk) {
+        let origin = (position.0 * 16, position.1 * 16, position.2 * 16);
+        // Blocks whose veins can reach the chunk.
+        let blocks = |origin: i32| {
+            (origin - vein.size).div_euclid(16)..=(origin + 15 + vein.size).div_euclid(16)
+        };
+        let start = [origin.0, origin.1, origin.2];
+        let stream = format!("vein:{}", vein.name);
+        for block_z in blocks(origin.2) {
+            for block_y in blocks(origin.1) {
+                for block_x in blocks(origin.0) {
+                    let block = (block_x, block_y, block_z);
+                    let mut rng = StdRng::seed_from_u64(self.seed.hash_position(&stream, block));
+                    for _ in 0..vein.count {
+                        let mut voxel = [
+                            block_x * 16 + rng.gen_range(0..16),
+                            block_y * 16 + rng.gen_range(0..16),
+                            block_z * 16 + rng.gen_range(0..16),
+                        ];
+                        // Draws the whole walk even when it is skipped, so the next veins
+                        // of the block do not depend on it.
+                        let steps: Vec<(usize, bool)> = (1..vein.size)
+                            .map(|_| (rng.gen_range(0..3), rng.gen()))
+                            .collect();
+                        let out_of_reach = (0..3).any(|axis| {
+                            voxel[axis] < start[axis] - vein.size
+                                || voxel[axis] > start[axis] + 15 + vein.size
+                        });
+                        if out_of_reach || voxel[1] < vein.min_y || voxel[1] > vein.max_y {
+                            continue;
+                        }
+                        self.replace(vein, voxel, origin, chunk);
+                        for (axis, forward) in steps {
+                            voxel[axis] += if forward { 1 } else { -1 };
+                            self.replace(vein, voxel, origin, chunk);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn replace(&self, vein: &Vein, voxel: [i32; 3], origin: (i32, i32, i32), chunk: &mut Chunk) {
+        let Some((x, y, z)) = local(voxel, origin) else {
+            return;
+        };
+        if chunk
+            .get_voxel(x, y, z)
+            .is_some_and(|current| vein.replaces.contains(current))
+        {
+            chunk.set(x, y, z, vein.material);
+        }
+    }
+
+    fn place_feature(&self, feature: &Feature, position: ChunkPosition, chunk: &mut Chunk) {
+        let origin = (position.0 * 16, position.1 * 16, position.2 * 16);
+        let reach = feature.structure.reach();
+        let (bottom, top) = feature.structure.vertical_extent();
+        let stream = format!("feature:{}", feature.name);
+        for z in origin.2 - reach..origin.2 + 16 + reach {
+            for x in origin.0 - reach..origin.0 + 16 + reach {
+                if self.seed.random_at(&stream, (x, 0, z)) >= feature.density {
+                    continue;
+                }
+                let Some((height, ground)) = self.base.surface_at(x, z) else {
+                    continue;
+                };
+                if height + top < origin.1 || height + bottom >= origin.1 + 16 {
+                    continue;
+                }
+                if !feature.on.is_empty() && !feature.on.contains(&ground) {
+                    continue;
+                }
+                let quarter_turns = if feature.rotate {
+                    self.seed.hash_position(&stream, (x, 1, z)) as u8
+                } else {
+                    0
+                };
+                for ((dx, dy, dz), voxel) in feature.structure.rotated(quarter_turns) {
+                    let Some((x, y, z)) = local([x + dx, height + dy, z + dz], origin) else {
+                        continue;
+                    };
+                    if chunk.get_voxel(x, y, z).is_some_and(Voxel::is_empty) {
+                        chunk.set(x, y, z, voxel);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Position of a world voxel within the chunk at `origin`, if it is inside.
+fn local(voxel: [i32; 3], origin: (i32, i32, i32)) -> Option<(u8, u8, u8)> {
+    let (x, y, z) = (
+        voxel[0] - origin.0,
+        voxel[1] - origin.1,
+        voxel[2] - origin.2,
+    );
+    let inside = |v: i32| (0..16).contains(&v);
+    (inside(x) && inside(y) && inside(z)).then_some((x as u8, y as u8, z as u8))
+}
+
+impl WorldGenerator for FeatureGenerator {
+    fn generate_chunk(&self, position: ChunkPosition, chunk: &mut Chunk) {
+        self.base.generate_chunk(position, chunk);
+        for vein in &self.features.veins {
+            self.place_vein(vein, position, chunk);
+        }
+        // In file order, every feature over the whole area before the next one, so the
+        // first one wins wherever they overlap, whichever chunk is generated.
+        for feature in &self.features.features {
+            self.place_feature(feature, position, chunk);
+        }
+    }
+
+    fn surface_at(&self, x: i32, z: i32) -> Option<(i32, Voxel)> {
+        self.base.surface_at(x, z)
+    }
+}
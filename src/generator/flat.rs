@@ -0,0 +1,61 @@
+use super::WorldGenerator;
+use crate::chunk::{Chunk, ChunkPosition};
+use crate::material::MaterialRegistry;
+use crate::voxel::Voxel;
+
+/// Horizontal layers of voxels below a fixed height, and nothing above.
+pub struct FlatGenerator {
+    /// Height of the top of the ground: the highest solid voxels are at `height - 1`.
+    pub height: i32,
+    /// Layers from the surface down, as a voxel and a thickness. Below the last layer, its
+    /// voxel goes on forever.
+    pub layers: Vec<(Voxel, i32)>,
+}
+
+impl FlatGenerator {
+    /// Grass over three layers of dirt over stone, with the surface at y = 0.
+    pub fn new(materials: &MaterialRegistry) -> Self {
+        let voxel = |name| materials.voxel(name).unwrap_or_default();
+        Self {
+            height: 0,
+            layers: vec![(voxel("grass"), 1), (voxel("dirt"), 3), (voxel("stone"), 1)],
+        }
+    }
+
+    fn voxel_at(&self, y: i32) -> Voxel {
+        let mut top = self.height;
+        for (voxel, thickness) in &self.layers {
+            if y >= top - thickness {
+                return *voxel;
+            }
+            top -= thickness;
+        }
+        self.layers
+            .last()
+            .map_or_else(Voxel::empty, |(voxel, _)| *voxel)
+    }
+}
+
+impl WorldGenerator for FlatGenerator {
+    fn generate_chunk(&self, position: ChunkPosition, chunk: &mut Chunk) {
+        for y in 0..16 {
+            let world_y = position.1 * 16 + y;
+            if world_y >= self.height {
+                break;
+            }
+            let voxel = self.voxel_at(world_y);
+            if voxel.is_empty() {
+                continue;
+            }
+            for z in 0..16 {
+                for x in 0..16 {
+                    chunk.set(x, y as u8, z, voxel);
+                }
+            }
+        }
+    }
+
+    fn surface_at(&self, _x: i32, _z: i32) -> Option<(i32, Voxel)> {
+        Some((self.height, self.voxel_at(self.height - 1)))
+    }
+}
@@ -0,0 +1,32 @@
+//! World generators, which fill chunks from their position alone so chunks can be generated
+//! in any order and on any thread.
+
+mod biome;
+mod caves;
+mod debug;
+mod features;
+mod flat;
+mod terrain;
+
+pub use biome::BiomeGenerator;
+pub use caves::PerlinCaves;
+pub use debug::DebugGenerator;
+pub use features::FeatureGenerator;
+pub use flat::FlatGenerator;
+pub use terrain::TerrainGenerator;
+
+use crate::chunk::{Chunk, ChunkPosition};
+use crate::voxel::Voxel;
+
+pub trait WorldGenerator: Send + Sync {
+    /// Fills `chunk`, an empty chunk at `position`, with the voxels of the world. The result
+    /// must only depend on the generator and the position.
+    fn generate_chunk(&self, position: ChunkPosition, chunk: &mut Chunk);
+
+    /// Ground of the column as generated, without looking at any chunk: the height of the
+    /// ground, whose highest solid voxel is at `height - 1`, and that voxel. `None` when the
+    /// world has no single ground, like caves.
+    fn surface_at(&self, _x: i32, _z: i32) -> Option<(i32, Voxel)> {
+        None
+    }
+}
@@ -0,0 +1,143 @@
+use super::WorldGenerator;
+use crate::chunk::{Chunk, ChunkPosition};
+use crate::material::MaterialRegistry;
+use crate::perlin::PerlinGenerator;
+use crate::seed::WorldSeed;
+use crate::voxel::Voxel;
+
+/// Rolling terrain from a fractal heightmap, with caves carved below the surface.
+///
+/// Each column is filled up to its height: a surface voxel, then `subsurface_depth` voxels
+/// of subsurface, then stone. Low columns get sand over sandstone, high ones snow over
+/// stone, and the rest grass over dirt.
+pub struct TerrainGenerator {
+    height_noise: PerlinGenerator,
+    cave_noise: PerlinGenerator,
+    /// Number of noise layers summed into the heightmap.
+    pub octaves: u32,
+    /// Frequency ratio between successive octaves.
+    pub lacunarity: f64,
+    /// Amplitude ratio between successive octaves.
+    pub persistence: f64,
+    /// Horizontal size in voxels of the largest hills.
+    pub scale: f64,
+    /// Height of the terrain where the noise is 0.
+    pub base_height: i32,
+    /// Largest distance of the terrain above or below the base height.
+    pub amplitude: f64,
+    /// Columns up to this height get a beach.
+    pub sand_level: i32,
+    /// Columns from this height up are covered with snow.
+    pub snow_level: i32,
+    pub subsurface_depth: i32,
+    /// Size in voxels of the cave noise features.
+    pub cave_scale: f64,
+    /// Cave noise value above which voxels are carved out, higher values carve less.
+    pub cave_threshold: f64,
+    /// Depth below the surface where caves may start.
+    pub cave_depth: i32,
+    grass: Voxel,
+    dirt: Voxel,
+    sand: Voxel,
+    sandstone: Voxel,
+    snow: Voxel,
+    stone: Voxel,
+}
+
+impl TerrainGenerator {
+    pub fn new(seed: WorldSeed, materials: &MaterialRegistry) -> Self {
+        let voxel = |name| materials.voxel(name).unwrap_or_default();
+        Self {
+            height_noise: PerlinGenerator::new(seed.derive_u32("terrain")),
+            cave_noise: PerlinGenerator::new(seed.derive_u32("terrain_caves")),
+            octaves: 5,
+            lacunarity: 2.0,
+            persistence: 0.5,
+            scale: 128.0,
+            base_height: 0,
+            amplitude: 32.0,
+            sand_level: -12,
+            snow_level: 20,
+            subsurface_depth: 3,
+            cave_scale: 24.0,
+            cave_threshold: 0.45,
+            cave_depth: 6,
+            grass: voxel("grass"),
+            dirt: voxel("dirt"),
+            sand: voxel("sand"),
+            sandstone: voxel("sandstone"),
+            snow: voxel("snow"),
+            stone: voxel("stone"),
+        }
+    }
+
+    /// Height of the ground in the column: its highest solid voxel is at `height - 1`.
+    pub fn height_at(&self, x: i32, z: i32) -> i32 {
+        self.base_height + (self.noise_at(x, z) * self.amplitude).round() as i32
+    }
+
+    /// Voxel of a column of the given height, `depth` voxels below its surface, before caves
+    /// are carved.
+    pub fn layer_at(&self, height: i32, depth: i32) -> Voxel {
+        let (surface, subsurface) = if height <= self.sand_level {
+            (self.sand, self.sandstone)
+        } else if height >= self.snow_level {
+            (self.snow, self.stone)
+        } else {
+            (self.grass, self.dirt)
+        };
+        match depth {
+            0 => surface,
+            depth if depth <= self.subsurface_depth => subsurface,
+            _ => self.stone,
+        }
+    }
+
+    /// Heightmap noise of the column, in [-1, 1].
+    pub(super) fn noise_at(&self, x: i32, z: i32) -> f64 {
+        self.height_noise.fbm_2d(
+            x as f64 / self.scale,
+            z as f64 / self.scale,
+            self.octaves,
+            self.lacunarity,
+            self.persistence,
+        )
+    }
+
+    pub(super) fn is_cave(&self, x: i32, y: i32, z: i32) -> bool {
+        let noise = self.cave_noise.get(
+            x as f64 / self.cave_scale,
+            y as f64 / self.cave_scale,
+            z as f64 / self.cave_scale,
+        );
+        noise > self.cave_threshold
+    }
+}
+
+impl WorldGenerator for TerrainGenerator {
+    fn generate_chunk(&self, position: ChunkPosition, chunk: &mut Chunk) {
+        let origin = (position.0 * 16, position.1 * 16, position.2 * 16);
+        for z in 0..16 {
+            for x in 0..16 {
+                let (world_x, world_z) = (origin.0 + x, origin.2 + z);
+                let height = self.height_at(world_x, world_z);
+                for y in 0..16 {
+                    let world_y = origin.1 + y;
+                    if world_y >= height {
+                        break;
+                    }
+                    let depth = height - 1 - world_y;
+                    if depth >= self.cave_depth && self.is_cave(world_x, world_y, world_z) {
+                        continue;
+                    }
+                    chunk.set(x as u8, y as u8, z as u8, self.layer_at(height, depth));
+                }
+            }
+        }
+    }
+
+    fn surface_at(&self, x: i32, z: i32) -> Option<(i32, Voxel)> {
+        let height = self.height_at(x, z);
+        Some((height, self.layer_at(height, 0)))
+    }
+}
@@ -0,0 +1,226 @@
+use crate::map::VoxelPosition;
+use crate::voxel::Voxel;
+use std::collections::VecDeque;
+use std::mem::size_of;
+
+/// Default memory budget of a map history, in bytes.
+pub const DEFAULT_HISTORY_BUDGET: usize = 16 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoxelChange {
+    pub position: VoxelPosition,
+    pub before: Voxel,
+    pub after: Voxel,
+}
+
+/// Changes undone and redone together, in the order they were made.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    pub changes: Vec<VoxelChange>,
+}
+
+impl Transaction {
+    pub fn memory_usage(&self) -> usize {
+        size_of::<Self>() + self.changes.capacity() * size_of::<VoxelChange>()
+    }
+}
+
+/// Journal of the voxel edits made to a map. Edits recorded while no transaction is open
+/// form a transaction of their own. When the journal grows past `budget` bytes, the oldest
+/// undoable transactions are forgotten, the open transaction counting towards the budget.
+/// The most recent transaction is always kept, even when it alone is over budget, so the
+/// last edit can be undone however large it is.
+#[derive(Debug, Clone)]
+pub struct History {
+    pub budget: usize,
+    undo: VecDeque<Transaction>,
+    redo: Vec<Transaction>,
+    open: Option<Transaction>,
+    depth: usize,
+    /// Bytes used by the transactions, updated as they come and go.
+    memory: usize,
+}
+
+impl History {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            open: None,
+            depth: 0,
+            memory: 0,
+        }
+    }
+
+    /// Rebuilds a history from its transactions, oldest first, for example after loading it.
+    pub fn from_transactions(
+        budget: usize,
+        undo: Vec<Transaction>,
+        redo: Vec<Transaction>,
+    ) -> Self {
+        let memory = undo
+            .iter()
+            .chain(&redo)
+            .map(Transaction::memory_usage)
+            .sum();
+        let mut history = Self {
+            undo: undo.into(),
+            redo,
+            memory,
+            ..Self::new(budget)
+        };
+        history.enforce_budget();
+        history
+    }
+
+    /// Starts grouping the following changes into one transaction. Transactions may be
+    /// nested, the changes are grouped until the outermost one is committed.
+    pub fn begin(&mut self) {
+        self.depth += 1;
+        if self.open.is_none() {
+            let transaction = Transaction::default();
+            self.memory += transaction.memory_usage();
+            self.open = Some(transaction);
+        }
+    }
+
+    pub fn commit(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+        if self.depth == 0 {
+            self.close();
+        }
+    }
+
+    /// Commits the open transaction however deeply it is nested.
+    pub fn commit_all(&mut self) {
+        self.depth = 0;
+        self.close();
+    }
+
+    pub fn is_recording_transaction(&self) -> bool {
+        self.open.is_some()
+    }
+
+    /// Adds changes to the open transaction, or as a transaction of their own. Anything
+    /// that could be redone is forgotten.
+    pub fn record(&mut self, changes: Vec<VoxelChange>) {
+        if changes.is_empty() {
+            return;
+        }
+        for transaction in self.redo.drain(..) {
+            self.memory -= transaction.memory_usage();
+        }
+        match &mut self.open {
+            Some(transaction) => {
+                self.memory -= transaction.memory_usage();
+                transaction.changes.extend(changes);
+                self.memory += transaction.memory_usage();
+            }
+            None => {
+                let transaction = Transaction { changes };
+                self.memory += transaction.memory_usage();
+                self.undo.push_back(transaction);
+            }
+        }
+        self.enforce_budget();
+    }
+
+    /// Takes the most recent transaction to undo. The caller reverts it and hands it back
+    /// with `push_redo`.
+    pub fn pop_undo(&mut self) -> Option<Transaction> {
+        self.commit_all();
+        let transaction = self.undo.pop_back()?;
+        self.memory -= transaction.memory_usage();
+        Some(transaction)
+    }
+
+    pub fn push_redo(&mut self, transaction: Transaction) {
+        self.memory += transaction.memory_usage();
+        self.redo.push(transaction);
+    }
+
+    /// Takes the most recently undone transaction. The caller applies it again and hands it
+    /// back with `push_undo`.
+    pub fn pop_redo(&mut self) -> Option<Transaction> {
+        self.commit_all();
+        let transaction = self.redo.pop()?;
+        self.memory -= transaction.memory_usage();
+        Some(transaction)
+    }
+
+    pub fn push_undo(&mut self, transaction: Transaction) {
+        self.memory += transaction.memory_usage();
+        self.undo.push_back(transaction);
+        self.enforce_budget();
+    }
+
+    /// Transactions that can be undone, oldest first.
+    pub fn undo_transactions(&self) -> impl Iterator<Item = &Transaction> {
+        self.undo.iter()
+    }
+
+    /// Transactions that can be redone, the next one to redo last.
+    pub fn redo_transactions(&self) -> impl Iterator<Item = &Transaction> {
+        self.redo.iter()
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty() || self.open.as_ref().is_some_and(|t| !t.changes.is_empty())
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.open = None;
+        self.depth = 0;
+        self.memory = 0;
+    }
+
+    /// Bytes used by the recorded transactions.
+    pub fn memory_usage(&self) -> usize {
+        self.memory
+    }
+
+    fn close(&mut self) {
+        if let Some(transaction) = self.open.take() {
+            if transaction.changes.is_empty() {
+                self.memory -= transaction.memory_usage();
+            } else {
+                self.undo.push_back(transaction);
+                self.enforce_budget();
+            }
+        }
+    }
+
+    fn enforce_budget(&mut self) {
+        // The open transaction is the most recent one, otherwise it is the last to undo.
+        let kept = if self.open.is_some() { 0 } else { 1 };
+        while self.memory > self.budget && self.undo.len() > kept {
+            if let Some(transaction) = self.undo.pop_front() {
+                self.memory -= transaction.memory_usage();
+            }
+        }
+    }
+}
+
+// The memory total follows from the transactions, and their capacities may differ.
+impl PartialEq for History {
+    fn eq(&self, other: &Self) -> bool {
+        self.budget == other.budget
+            && self.undo == other.undo
+            && self.redo == other.redo
+            && self.open == other.open
+            && self.depth == other.depth
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_BUDGET)
+    }
+}
@@ -1,7 +1,21 @@
+pub mod biome;
+pub mod brush;
 pub mod camera;
 pub mod chunk;
+pub mod distance;
+pub mod feature;
+pub mod generator;
+pub mod history;
 pub mod map;
+pub mod material;
+pub mod mesh;
+pub mod palette;
 pub mod perlin;
+pub mod region;
 pub mod renderer;
+pub mod seed;
+pub mod streaming;
 pub mod utils;
+pub mod vox;
 pub mod voxel;
+pub mod world_file;
@@ -1,5 +1,4 @@
 use pixels::{Pixels, SurfaceTexture};
-use rand::prelude::*;
 use winit::{
     event::{ElementState, Event, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
@@ -7,12 +6,374 @@ use winit::{
 };
 
 use nalgebra::Vector3;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use torus::biome::BiomeTable;
 use torus::camera::Camera;
+use torus::feature::FeatureSet;
+use torus::generator::{
+    BiomeGenerator, DebugGenerator, FeatureGenerator, FlatGenerator, PerlinCaves, TerrainGenerator,
+    WorldGenerator,
+};
 use torus::map::Map;
-use torus::perlin::PerlinGenerator;
+use torus::material::MaterialRegistry;
 use torus::renderer::Renderer;
+use torus::seed::WorldSeed;
+use torus::streaming::ChunkStreamer;
+
+const USAGE: &str = "\
+Usage:
+    torus [--seed SEED] [--generator NAME] [--world FILE] [--stream RADIUS]
+    torus render [--seed SEED] [--generator NAME] [--world FILE] [--pos X,Y,Z]
+                 [--rot A,B,C] [--size WxH] [--sun X,Y,Z] [--shadows] [--ao] [-o OUTPUT]
+    torus export [--seed SEED] [--generator NAME] [--world FILE] -o OUTPUT
+
+SEED is a number or any text, which is hashed into a number.
+NAME is caves (the default), terrain, biomes, flat or debug. biomes=TABLE reads the
+biomes from a TOML file laid out like assets/biomes.toml. terrain and biomes get the
+trees, boulders and ores of assets/features.toml.
+FILE is loaded if it exists, otherwise the generated world is saved to it.
+With --stream, chunks within RADIUS chunks of the camera are generated as it moves
+instead of generating a fixed world up front.
+The export format follows the extension of OUTPUT: .obj, .ply or .gltf.";
+
+struct WindowOptions {
+    seed: WorldSeed,
+    generator: GeneratorKind,
+    world: Option<PathBuf>,
+    stream: Option<i32>,
+}
+
+impl WindowOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Self {
+            seed: WorldSeed::random(),
+            generator: GeneratorKind::Caves,
+            world: None,
+            stream: None,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--seed" => options.seed = parse_seed(value()?),
+                "--generator" => options.generator = value()?.parse()?,
+                "--world" => options.world = Some(PathBuf::from(value()?)),
+                "--stream" => {
+                    let value = value()?;
+                    let radius = value
+                        .parse()
+                        .map_err(|_| format!("invalid radius {}", value))?;
+                    options.stream = Some(radius);
+                }
+                _ => return Err(format!("unexpected argument {}", arg)),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+struct RenderOptions {
+    seed: WorldSeed,
+    generator: GeneratorKind,
+    world: Option<PathBuf>,
+    position: Vector3<f32>,
+    rotation: Vector3<f32>,
+    width: u32,
+    height: u32,
+    sun_direction: Option<Vector3<f32>>,
+    shadows: bool,
+    ambient_occlusion: bool,
+    output: PathBuf,
+}
+
+impl RenderOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Self {
+            seed: WorldSeed::random(),
+            generator: GeneratorKind::Caves,
+            world: None,
+            position: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Vector3::new(0.0, 0.0, 0.0),
+            width: 480,
+            height: 360,
+            sun_direction: None,
+            shadows: false,
+            ambient_occlusion: false,
+            output: PathBuf::from("torus.png"),
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--seed" => options.seed = parse_seed(value()?),
+                "--generator" => options.generator = value()?.parse()?,
+                "--world" => options.world = Some(PathBuf::from(value()?)),
+                "--pos" => options.position = parse_vector3(value()?)?,
+                "--rot" => options.rotation = parse_vector3(value()?)?,
+                "--size" => (options.width, options.height) = parse_size(value()?)?,
+                "--sun" => options.sun_direction = Some(parse_vector3(value()?)?),
+                "--shadows" => options.shadows = true,
+                "--ao" => options.ambient_occlusion = true,
+                "-o" | "--output" => options.output = PathBuf::from(value()?),
+                _ => return Err(format!("unknown argument {}", arg)),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+struct ExportOptions {
+    seed: WorldSeed,
+    generator: GeneratorKind,
+    world: Option<PathBuf>,
+    output: PathBuf,
+}
+
+impl ExportOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut seed = WorldSeed::random();
+        let mut generator = GeneratorKind::Caves;
+        let mut world = None;
+        let mut output = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--seed" => seed = parse_seed(value()?),
+                "--generator" => generator = value()?.parse()?,
+                "--world" => world = Some(PathBuf::from(value()?)),
+                "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+                _ => return Err(format!("unknown argument {}", arg)),
+            }
+        }
+
+        let output = output.ok_or("missing output file")?;
+        Ok(Self {
+            seed,
+            generator,
+            world,
+            output,
+        })
+    }
+}
+
+enum GeneratorKind {
+    Caves,
+    Terrain,
+    /// Biomes from the given file, or the built-in ones.
+    Biomes(Option<PathBuf>),
+    Flat,
+    Debug,
+}
+
+impl GeneratorKind {
+    fn build(
+        &self,
+        seed: WorldSeed,
+        materials: &MaterialRegistry,
+    ) -> Result<Arc<dyn WorldGenerator>, String> {
+        let base: Arc<dyn WorldGenerator> = match self {
+            Self::Caves => Arc::new(PerlinCaves::new(seed, materials)),
+            Self::Terrain => Arc::new(TerrainGenerator::new(seed, materials)),
+            Self::Biomes(path) => {
+                let biomes = match path {
+                    Some(path) => BiomeTable::load(path, materials)
+                        .map_err(|e| format!("could not load {}: {}", path.display(), e))?,
+                    None => BiomeTable::builtin(materials).map_err(|e| e.to_string())?,
+                };
+                Arc::new(BiomeGenerator::new(seed, biomes, materials))
+            }
+            Self::Flat => Arc::new(FlatGenerator::new(materials)),
+            Self::Debug => Arc::new(DebugGenerator::new(materials)),
+        };
+        Ok(match self {
+            Self::Terrain | Self::Biomes(_) => {
+                let features = FeatureSet::builtin(materials).map_err(|e| e.to_string())?;
+                Arc::new(FeatureGenerator::new(seed, base, features))
+            }
+            _ => base,
+        })
+    }
+}
+
+impl FromStr for GeneratorKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        match value {
+            "caves" => Ok(Self::Caves),
+            "terrain" => Ok(Self::Terrain),
+            "biomes" => Ok(Self::Biomes(None)),
+            "flat" => Ok(Self::Flat),
+            "debug" => Ok(Self::Debug),
+            _ => match value.strip_prefix("biomes=") {
+                Some(path) => Ok(Self::Biomes(Some(PathBuf::from(path)))),
+                None => Err(format!("unknown generator {}", value)),
+            },
+        }
+    }
+}
+
+fn parse_seed(value: &str) -> WorldSeed {
+    value.parse().unwrap_or_default()
+}
+
+fn parse_vector3(value: &str) -> Result<Vector3<f32>, String> {
+    let components = value
+        .split(',')
+        .map(|v| v.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("invalid vector {}", value))?;
+    match components[..] {
+        [x, y, z] => Ok(Vector3::new(x, y, z)),
+        _ => Err(format!("expected 3 comma separated values, got {}", value)),
+    }
+}
+
+fn parse_size(value: &str) -> Result<(u32, u32), String> {
+    let (width, height) = value
+        .split_once('x')
+        .ok_or_else(|| format!("expected WxH, got {}", value))?;
+    let width = width
+        .parse()
+        .map_err(|_| format!("invalid width {}", width))?;
+    let height = height
+        .parse()
+        .map_err(|_| format!("invalid height {}", height))?;
+    if width == 0 || height == 0 {
+        return Err("size must not be zero".to_string());
+    }
+    Ok((width, height))
+}
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("render") => RenderOptions::parse(&args[1..]).and_then(|o| render(&o)),
+        Some("export") => ExportOptions::parse(&args[1..]).and_then(|o| export(&o)),
+        _ => WindowOptions::parse(&args).and_then(run_window),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+}
+
+fn generate_map(seed: WorldSeed, generator: &GeneratorKind) -> Result<Map, String> {
+    let mut map = Map::new();
+    println!("Seed: {}", seed);
+    println!("Generating map...");
+    map.seed = seed;
+    let generator = generator.build(seed, &map.materials)?;
+    map.generate_region_with(&*generator, (-4, -4, -4), (4, 4, 4));
+    println!("Map generated!");
+    println!("Generating distance maps...");
+    map.generate_all_distance_maps(4);
+    println!(
+        "Map memory usage: {:.2} MiB for {} chunks",
+        map.memory_usage() as f32 / (1024.0 * 1024.0),
+        map.chunks.len()
+    );
+    Ok(map)
+}
+
+/// Loads the world file if there is one, otherwise generates the world and saves it there.
+fn load_or_generate_map(
+    seed: WorldSeed,
+    generator: &GeneratorKind,
+    world: Option<&Path>,
+) -> Result<Map, String> {
+    match world {
+        Some(path) if path.exists() => {
+            println!("Loading world from {}...", path.display());
+            let map =
+                Map::load(path).map_err(|e| format!("could not load {}: {}", path.display(), e))?;
+            println!("Seed: {}", map.seed);
+            Ok(map)
+        }
+        Some(path) => {
+            let map = generate_map(seed, generator)?;
+            save_map(&map, path)?;
+            Ok(map)
+        }
+        None => generate_map(seed, generator),
+    }
+}
+
+fn save_map(map: &Map, path: &Path) -> Result<(), String> {
+    map.save(path)
+        .map_err(|e| format!("could not save {}: {}", path.display(), e))?;
+    println!("World saved to {}", path.display());
+    Ok(())
+}
+
+fn render(options: &RenderOptions) -> Result<(), String> {
+    let map = load_or_generate_map(options.seed, &options.generator, options.world.as_deref())?;
+    let mut renderer = Renderer::new(map, options.width, options.height, num_cpus::get());
+    if let Some(sun_direction) = options.sun_direction {
+        if !renderer.set_sun_direction(sun_direction) {
+            return Err("the sun direction must not be zero".to_string());
+        }
+    }
+    renderer.shadows = options.shadows;
+    renderer.ambient_occlusion = options.ambient_occlusion;
+    let camera = Camera::new(renderer, options.position, options.rotation, 1.0, 0.1);
+    camera
+        .save_screenshot(&options.output)
+        .map_err(|e| format!("could not write {}: {}", options.output.display(), e))?;
+    println!("Frame written to {}", options.output.display());
+    Ok(())
+}
+
+fn export(options: &ExportOptions) -> Result<(), String> {
+    let map = load_or_generate_map(options.seed, &options.generator, options.world.as_deref())?;
+    println!("Meshing {} chunks...", map.chunks.len());
+    let mesh = map.mesh_all();
+    mesh.save(&options.output)
+        .map_err(|e| format!("could not write {}: {}", options.output.display(), e))?;
+    println!(
+        "Mesh with {} triangles written to {}",
+        mesh.triangle_count(),
+        options.output.display()
+    );
+    Ok(())
+}
+
+fn run_window(options: WindowOptions) -> Result<(), String> {
+    let mut streamer = options.stream.map(ChunkStreamer::new);
+    let map = match (&streamer, options.world.as_deref()) {
+        (Some(_), Some(path)) if path.exists() => {
+            Map::load(path).map_err(|e| format!("could not load {}: {}", path.display(), e))?
+        }
+        (Some(_), _) => {
+            println!("Seed: {}", options.seed);
+            let mut map = Map::new();
+            map.seed = options.seed;
+            map
+        }
+        (None, world) => load_or_generate_map(options.seed, &options.generator, world)?,
+    };
+    if let Some(streamer) = &mut streamer {
+        streamer.set_generator(options.generator.build(map.seed, &map.materials)?);
+    }
+
     let event_loop = EventLoop::new();
 
     let window = WindowBuilder::new()
@@ -25,16 +386,8 @@ fn main() {
     let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
     let mut pixels = Pixels::new(window_size.width, window_size.height, surface_texture).unwrap();
 
-    let mut map = Map::new();
-    let mut rng = thread_rng();
-    println!("Seed: {}", rng.next_u32());
-    println!("Generating map...");
-    map.generate(&PerlinGenerator::new(rng.next_u32()));
-    println!("Map generated!");
-    println!("Generating distance maps...");
-    map.generate_all_distance_maps(4);
-
-    let renderer = Renderer::new(map.clone(), window_size.width, window_size.height, 8);
+    let renderer = Renderer::new(map, window_size.width, window_size.height, 8);
+    let mut screenshot_count = 0;
 
     let mut camera = Camera::new(
         renderer,
@@ -55,56 +408,60 @@ fn main() {
                     .expect("Error resizing surface");
             }
             WindowEvent::KeyboardInput { input, .. } => {
+                if input.state != ElementState::Pressed {
+                    return;
+                }
                 if let Some(keycode) = input.virtual_keycode {
                     match keycode {
-                        VirtualKeyCode::Z => {
-                            if input.state == ElementState::Pressed {
-                                camera.move_forward();
+                        VirtualKeyCode::Z => camera.move_forward(),
+                        VirtualKeyCode::S => camera.move_backward(),
+                        VirtualKeyCode::Q => camera.move_left(),
+                        VirtualKeyCode::D => camera.move_right(),
+                        VirtualKeyCode::R => camera.move_up(),
+                        VirtualKeyCode::F => camera.move_down(),
+                        VirtualKeyCode::Up => camera.rotate_up(),
+                        VirtualKeyCode::Down => camera.rotate_down(),
+                        VirtualKeyCode::Left => camera.rotate_left(),
+                        VirtualKeyCode::Right => camera.rotate_right(),
+                        VirtualKeyCode::E => {
+                            let stone = camera.renderer.map.materials.voxel("stone");
+                            if let Some(stone) = stone {
+                                camera.place_voxel(stone);
                             }
                         }
-                        VirtualKeyCode::S => {
-                            if input.state == ElementState::Pressed {
-                                camera.move_backward();
-                            }
+                        VirtualKeyCode::X => {
+                            camera.remove_voxel();
                         }
-                        VirtualKeyCode::Q => {
-                            if input.state == ElementState::Pressed {
-                                camera.move_left();
-                            }
-                        }
-                        VirtualKeyCode::D => {
-                            if input.state == ElementState::Pressed {
-                                camera.move_right();
-                            }
-                        }
-                        VirtualKeyCode::R => {
-                            if input.state == ElementState::Pressed {
-                                camera.move_up();
-                            }
+                        VirtualKeyCode::U => {
+                            camera.renderer.map.undo();
                         }
-                        VirtualKeyCode::F => {
-                            if input.state == ElementState::Pressed {
-                                camera.move_down();
-                            }
+                        VirtualKeyCode::I => {
+                            camera.renderer.map.redo();
                         }
-                        VirtualKeyCode::Up => {
-                            if input.state == ElementState::Pressed {
-                                camera.rotate_up();
-                            }
+                        VirtualKeyCode::H => {
+                            camera.renderer.shadows = !camera.renderer.shadows;
+                            println!("Shadows: {}", camera.renderer.shadows);
                         }
-                        VirtualKeyCode::Down => {
-                            if input.state == ElementState::Pressed {
-                                camera.rotate_down();
-                            }
+                        VirtualKeyCode::O => {
+                            camera.renderer.ambient_occlusion = !camera.renderer.ambient_occlusion;
+                            println!("Ambient occlusion: {}", camera.renderer.ambient_occlusion);
                         }
-                        VirtualKeyCode::Left => {
-                            if input.state == ElementState::Pressed {
-                                camera.rotate_left();
+                        VirtualKeyCode::F5 => {
+                            if let Some(path) = &options.world {
+                                if let Err(e) = save_map(&camera.renderer.map, path) {
+                                    eprintln!("{}", e);
+                                }
                             }
                         }
-                        VirtualKeyCode::Right => {
-                            if input.state == ElementState::Pressed {
-                                camera.rotate_right();
+                        VirtualKeyCode::P => {
+                            screenshot_count += 1;
+                            let path = PathBuf::from(format!(
+                                "torus-{}-{}.png",
+                                camera.renderer.map.seed, screenshot_count
+                            ));
+                            match camera.save_screenshot(&path) {
+                                Ok(()) => println!("Screenshot saved to {}", path.display()),
+                                Err(e) => eprintln!("Screenshot failed: {}", e),
                             }
                         }
                         _ => {}
@@ -115,6 +472,11 @@ fn main() {
         },
         Event::RedrawRequested(_) => {
             let time = std::time::Instant::now();
+            if let Some(streamer) = &mut streamer {
+                if let Err(e) = streamer.update(&mut camera.renderer.map, camera.position) {
+                    eprintln!("Could not unload chunks: {}", e);
+                }
+            }
             camera.draw_frame(&mut pixels);
             println!("Redraw requested");
             println!("FPS: {}", 1.0 / time.elapsed().as_secs_f32());
@@ -1,80 +1,495 @@
 use crate::chunk::{Chunk, ChunkPosition};
-use crate::perlin::PerlinGenerator;
+use crate::distance::distance_field;
+use crate::generator::{PerlinCaves, WorldGenerator};
+use crate::history::{History, Transaction, VoxelChange};
+use crate::material::{MaterialRegistry, AIR};
+use crate::region::ChunkCache;
+use crate::seed::WorldSeed;
 use crate::voxel::Voxel;
 use nalgebra::Vector3;
+use rayon::prelude::*;
 use rayon::scope;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::mem::size_of;
+use std::ops::{Deref, DerefMut};
 use std::sync::{Arc, Mutex};
 
-#[derive(Debug, Default, Clone)]
+/// World coordinates of a voxel.
+pub type VoxelPosition = (i32, i32, i32);
+
+static EMPTY_VOXEL: Voxel = Voxel { material: AIR };
+
+/// What the map knows about the chunk containing a position.
+#[derive(Debug, Clone, Copy)]
+pub enum ChunkState<'a> {
+    /// Nothing is known about this chunk: it is outside the world or not loaded yet.
+    Unloaded,
+    /// The chunk exists but holds only empty voxels, it has no storage.
+    Empty,
+    Loaded(&'a Chunk),
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Map {
     pub chunks: HashMap<ChunkPosition, Chunk>,
+    /// Chunks known to be entirely empty, kept without any voxel storage.
+    pub empty_chunks: HashSet<ChunkPosition>,
+    pub seed: WorldSeed,
+    pub materials: MaterialRegistry,
+    /// Radius the distance maps were generated with, 0 while there are none.
+    pub distance_radius: i32,
+    /// Journal of the voxel edits, for undo and redo.
+    pub history: History,
+    /// Set when chunks are loaded lazily from a chunk source, see `set_chunk_source`.
+    pub chunk_cache: Option<ChunkCache>,
+    /// Per chunk, the inclusive local bounds of the distances that must be recomputed.
+    dirty_distances: HashMap<ChunkPosition, (Vector3<i32>, Vector3<i32>)>,
+    /// Set once a `ChunkStreamer` fills the map: chunks that are not in memory are still to
+    /// come, so voxel edits do not create them.
+    pub(crate) streamed: bool,
 }
 
 impl Map {
     pub fn new() -> Self {
         Self {
             chunks: HashMap::new(),
+            empty_chunks: HashSet::new(),
+            seed: WorldSeed::default(),
+            materials: MaterialRegistry::default(),
+            distance_radius: 0,
+            history: History::default(),
+            chunk_cache: None,
+            dirty_distances: HashMap::new(),
+            streamed: false,
+        }
+    }
+
+    /// State of the chunk containing the position, faulting it in from the chunk source if
+    /// it is not in memory.
+    pub fn chunk_state(&self, x: i32, y: i32, z: i32) -> ChunkState<'_> {
+        match self.resident_chunk_state(x, y, z) {
+            ChunkState::Unloaded => {
+                self.fault_in_shared((x.div_euclid(16), y.div_euclid(16), z.div_euclid(16)))
+            }
+            state => state,
+        }
+    }
+
+    /// State of the chunk containing the position as far as the chunks in memory tell.
+    pub(crate) fn resident_chunk_state(&self, x: i32, y: i32, z: i32) -> ChunkState<'_> {
+        let position = (x.div_euclid(16), y.div_euclid(16), z.div_euclid(16));
+        if let Some(chunk) = self.chunks.get(&position) {
+            ChunkState::Loaded(chunk)
+        } else if self.empty_chunks.contains(&position) {
+            ChunkState::Empty
+        } else {
+            ChunkState::Unloaded
         }
     }
 
     pub fn get(&self, x: i32, y: i32, z: i32) -> Option<&Chunk> {
-        let chunk_x = x.div_euclid(16) as i32;
-        let chunk_y = y.div_euclid(16) as i32;
-        let chunk_z = z.div_euclid(16) as i32;
-        self.chunks.get(&(chunk_x, chunk_y, chunk_z))
+        match self.chunk_state(x, y, z) {
+            ChunkState::Loaded(chunk) => Some(chunk),
+            ChunkState::Unloaded | ChunkState::Empty => None,
+        }
     }
 
-    pub fn get_mut(&mut self, x: i32, y: i32, z: i32) -> Option<&mut Chunk> {
-        let chunk_x = x.div_euclid(16);
-        let chunk_y = y.div_euclid(16);
-        let chunk_z = z.div_euclid(16);
-        self.chunks.get_mut(&(chunk_x, chunk_y, chunk_z))
+    /// Mutable access to the chunk containing the position, if it is loaded or known to be
+    /// empty. The chunk is written back with `set` when the guard is dropped, so its changes
+    /// are journaled and the distance maps around them updated.
+    pub fn get_mut(&mut self, x: i32, y: i32, z: i32) -> Option<MapChunkMut<'_>> {
+        let position = (x.div_euclid(16), y.div_euclid(16), z.div_euclid(16));
+        self.fault_in_chunk(position);
+        let chunk = match self.chunks.get(&position) {
+            Some(chunk) => chunk.clone(),
+            None if self.empty_chunks.contains(&position) => Chunk::new(position),
+            None => return None,
+        };
+        Some(MapChunkMut {
+            map: self,
+            position,
+            chunk,
+            touched: false,
+        })
     }
 
+    /// Stores the chunk at the position, or only records it as empty if it has no voxels.
+    /// The voxels it replaces are journaled in the history, and existing distance maps around
+    /// it are brought up to date.
     pub fn set(&mut self, x: i32, y: i32, z: i32, chunk: Chunk) {
-        let chunk_x = x.div_euclid(16);
-        let chunk_y = y.div_euclid(16);
-        let chunk_z = z.div_euclid(16);
-        self.chunks.insert((chunk_x, chunk_y, chunk_z), chunk);
+        let position = (x.div_euclid(16), y.div_euclid(16), z.div_euclid(16));
+        let origin = Vector3::new(position.0, position.1, position.2) * 16;
+        self.fault_in_chunk(position);
+        let previous = self.chunks.get(&position);
+        let mut changes = Vec::new();
+        for local_z in 0..16u8 {
+            for local_y in 0..16u8 {
+                for local_x in 0..16u8 {
+                    let before = previous
+                        .and_then(|chunk| chunk.get_voxel(local_x, local_y, local_z))
+                        .copied()
+                        .unwrap_or_default();
+                    let after = *chunk.get_voxel(local_x, local_y, local_z).unwrap();
+                    if before != after {
+                        let voxel = origin + Vector3::new(local_x, local_y, local_z).map(i32::from);
+                        changes.push(VoxelChange {
+                            position: (voxel.x, voxel.y, voxel.z),
+                            before,
+                            after,
+                        });
+                    }
+                }
+            }
+        }
+        self.history.record(changes);
+        self.store(position, chunk);
     }
 
-    pub fn get_voxel(&self, x: i32, y: i32, z: i32) -> Option<&Voxel> {
-        let chunk_x = x.div_euclid(16);
-        let chunk_y = y.div_euclid(16);
-        let chunk_z = z.div_euclid(16);
-        let chunk = self.chunks.get(&(chunk_x, chunk_y, chunk_z));
-        if let Some(chunk) = chunk {
-            let voxel_x = x.rem_euclid(16);
-            let voxel_y = y.rem_euclid(16);
-            let voxel_z = z.rem_euclid(16);
-            chunk.get_voxel(voxel_x as u8, voxel_y as u8, voxel_z as u8)
+    /// Stores a chunk without journaling it, for chunks that are generated rather than edited.
+    fn store(&mut self, position: ChunkPosition, chunk: Chunk) {
+        if chunk.is_empty() {
+            self.chunks.remove(&position);
+            self.empty_chunks.insert(position);
         } else {
-            None
+            self.empty_chunks.remove(&position);
+            self.chunks.insert(position, chunk);
         }
+
+        let origin = Vector3::new(position.0, position.1, position.2) * 16;
+        self.mark_distances_dirty(origin, origin + Vector3::repeat(15));
+        self.refresh_distance_maps();
+        self.mark_chunk_modified(position);
+        let _ = self.evict_chunks();
     }
 
-    pub fn get_voxel_mut(&mut self, x: i32, y: i32, z: i32) -> Option<&mut Voxel> {
-        let chunk_x = x.div_euclid(16);
-        let chunk_y = y.div_euclid(16);
-        let chunk_z = z.div_euclid(16);
-        let chunk = self.chunks.get_mut(&(chunk_x, chunk_y, chunk_z));
-        if let Some(chunk) = chunk {
-            let voxel_x = x.rem_euclid(16);
-            let voxel_y = y.rem_euclid(16);
-            let voxel_z = z.rem_euclid(16);
-            chunk.get_voxel_mut(voxel_x as u8, voxel_y as u8, voxel_z as u8)
-        } else {
-            None
+    /// Like `get_mut`, but starts from an empty chunk if the map has none at the position.
+    pub fn entry_chunk(&mut self, x: i32, y: i32, z: i32) -> MapChunkMut<'_> {
+        let position = (x.div_euclid(16), y.div_euclid(16), z.div_euclid(16));
+        self.fault_in_chunk(position);
+        let chunk = self
+            .chunks
+            .get(&position)
+            .cloned()
+            .unwrap_or_else(|| Chunk::new(position));
+        MapChunkMut {
+            map: self,
+            position,
+            chunk,
+            touched: false,
+        }
+    }
+
+    /// Sets a single voxel, creating its chunk if needed, and updates the distance maps it
+    /// influences so rays never skip over it. Returns the previous voxel.
+    pub fn set_voxel(&mut self, x: i32, y: i32, z: i32, voxel: Voxel) -> Voxel {
+        let previous = self.get_voxel(x, y, z).copied().unwrap_or_default();
+        self.set_voxels([((x, y, z), voxel)]);
+        previous
+    }
+
+    /// Empties a single voxel. Returns the previous voxel.
+    pub fn remove_voxel(&mut self, x: i32, y: i32, z: i32) -> Voxel {
+        self.set_voxel(x, y, z, Voxel::empty())
+    }
+
+    /// Applies a batch of edits. Edits are grouped by chunk so each chunk is looked up,
+    /// allocated and marked dirty once, chunks left without voxels drop their storage, and
+    /// distance maps are refreshed once at the end. The batch is journaled as a single
+    /// transaction unless one is already open. Returns the number of voxels that changed.
+    ///
+    /// In a streamed map, edits into chunks that have not arrived yet are dropped: the chunk
+    /// would otherwise be created empty and the streamer would never fill it in.
+    pub fn set_voxels<I>(&mut self, edits: I) -> usize
+    where
+        I: IntoIterator<Item = (VoxelPosition, Voxel)>,
+    {
+        let changes = self.write_voxels(edits);
+        let changed = changes.len();
+        self.history.record(changes);
+        changed
+    }
+
+    /// Applies a batch of edits without journaling them and returns what changed.
+    fn write_voxels<I>(&mut self, edits: I) -> Vec<VoxelChange>
+    where
+        I: IntoIterator<Item = (VoxelPosition, Voxel)>,
+    {
+        let mut edits_by_chunk: HashMap<ChunkPosition, Vec<(Vector3<u8>, Voxel)>> = HashMap::new();
+        for ((x, y, z), voxel) in edits {
+            let position = (x.div_euclid(16), y.div_euclid(16), z.div_euclid(16));
+            let local = Vector3::new(x, y, z).map(|v| v.rem_euclid(16) as u8);
+            edits_by_chunk
+                .entry(position)
+                .or_default()
+                .push((local, voxel));
+        }
+
+        let mut changes = Vec::new();
+        for (position, edits) in edits_by_chunk {
+            let origin = Vector3::new(position.0, position.1, position.2) * 16;
+            self.fault_in_chunk(position);
+            if self.streamed && !self.is_within_bounds(origin.x, origin.y, origin.z) {
+                continue;
+            }
+            let was_loaded = self.chunks.contains_key(&position);
+            if !was_loaded && edits.iter().all(|(_, voxel)| voxel.is_empty()) {
+                if self.empty_chunks.insert(position) {
+                    self.mark_chunk_modified(position);
+                }
+                continue;
+            }
+
+            self.empty_chunks.remove(&position);
+            let chunk = self
+                .chunks
+                .entry(position)
+                .or_insert_with(|| Chunk::new(position));
+            let mut min = Vector3::repeat(15);
+            let mut max = Vector3::zeros();
+            let mut chunk_changed = false;
+            for (local, voxel) in edits {
+                let before = *chunk.get_voxel(local.x, local.y, local.z).unwrap();
+                if before != voxel {
+                    chunk.set(local.x, local.y, local.z, voxel);
+                    min = min.inf(&local);
+                    max = max.sup(&local);
+                    chunk_changed = true;
+                    let world = origin + local.map(i32::from);
+                    changes.push(VoxelChange {
+                        position: (world.x, world.y, world.z),
+                        before,
+                        after: voxel,
+                    });
+                }
+            }
+            // Repacking goes over the whole chunk, it only pays off once the voxels left fit in
+            // narrower indices.
+            if chunk.can_shrink() {
+                chunk.compact();
+            }
+            if chunk.is_empty() {
+                self.chunks.remove(&position);
+                self.empty_chunks.insert(position);
+            }
+            if chunk_changed || !was_loaded {
+                self.mark_chunk_modified(position);
+            }
+
+            if !was_loaded {
+                // The chunk just got storage, its distance map has to be computed from scratch.
+                self.mark_distances_dirty(origin, origin + Vector3::repeat(15));
+            } else if chunk_changed {
+                self.mark_distances_dirty(
+                    origin + min.map(|v| v as i32),
+                    origin + max.map(|v| v as i32),
+                );
+            }
+        }
+
+        self.refresh_distance_maps();
+        let _ = self.evict_chunks();
+        changes
+    }
+
+    /// Groups the following edits into one undoable transaction until `commit_transaction`.
+    pub fn begin_transaction(&mut self) {
+        self.history.begin();
+    }
+
+    pub fn commit_transaction(&mut self) {
+        self.history.commit();
+    }
+
+    /// Reverts the most recent transaction and refreshes the distance maps around it. Any
+    /// open transaction is committed first. Returns false if there is nothing to undo, or if
+    /// the map is streamed and some of the chunks it touches have not arrived yet.
+    pub fn undo(&mut self) -> bool {
+        self.history.commit_all();
+        let next = self.history.undo_transactions().last();
+        if !self.transaction_is_writable(next.map(target_chunks)) {
+            return false;
         }
+        let Some(transaction) = self.history.pop_undo() else {
+            return false;
+        };
+        let edits = transaction.changes.iter().rev();
+        self.write_voxels(edits.map(|change| (change.position, change.before)));
+        self.history.push_redo(transaction);
+        true
+    }
+
+    /// Applies the most recently undone transaction again. Returns false if there is
+    /// nothing to redo, or if it cannot be written yet, see `undo`.
+    pub fn redo(&mut self) -> bool {
+        self.history.commit_all();
+        let next = self.history.redo_transactions().last();
+        if !self.transaction_is_writable(next.map(target_chunks)) {
+            return false;
+        }
+        let Some(transaction) = self.history.pop_redo() else {
+            return false;
+        };
+        let edits = transaction.changes.iter();
+        self.write_voxels(edits.map(|change| (change.position, change.after)));
+        self.history.push_undo(transaction);
+        true
+    }
+
+    /// Whether a transaction touching these chunks, if there is one, can be written: in a
+    /// streamed map, edits into chunks that have not arrived yet would be dropped.
+    fn transaction_is_writable(&mut self, chunks: Option<HashSet<ChunkPosition>>) -> bool {
+        let Some(chunks) = chunks else {
+            return false;
+        };
+        if !self.streamed {
+            return true;
+        }
+        chunks.into_iter().all(|position| {
+            self.fault_in_chunk(position);
+            self.is_within_bounds(position.0 * 16, position.1 * 16, position.2 * 16)
+        })
+    }
+
+    /// Empties every listed voxel, see `set_voxels`.
+    pub fn remove_voxels<I>(&mut self, positions: I) -> usize
+    where
+        I: IntoIterator<Item = VoxelPosition>,
+    {
+        self.set_voxels(
+            positions
+                .into_iter()
+                .map(|position| (position, Voxel::empty())),
+        )
+    }
+
+    /// Records that the voxels between `min` and `max` (inclusive) changed, so every distance
+    /// within the distance radius of them must be recomputed by `refresh_distance_maps`.
+    pub fn mark_distances_dirty(&mut self, min: Vector3<i32>, max: Vector3<i32>) {
+        if self.distance_radius <= 0 {
+            return;
+        }
+        let min = min.map(|v| v - self.distance_radius);
+        let max = max.map(|v| v + self.distance_radius);
+        let first_chunk = min.map(|v| v.div_euclid(16));
+        let last_chunk = max.map(|v| v.div_euclid(16));
+
+        for chunk_z in first_chunk.z..=last_chunk.z {
+            for chunk_y in first_chunk.y..=last_chunk.y {
+                for chunk_x in first_chunk.x..=last_chunk.x {
+                    let position = (chunk_x, chunk_y, chunk_z);
+                    if !self.chunks.contains_key(&position) {
+                        continue;
+                    }
+                    let origin = Vector3::new(chunk_x, chunk_y, chunk_z) * 16;
+                    let local_min = (min - origin).map(|v| v.clamp(0, 15));
+                    let local_max = (max - origin).map(|v| v.clamp(0, 15));
+                    self.dirty_distances
+                        .entry(position)
+                        .and_modify(|(dirty_min, dirty_max)| {
+                            *dirty_min = dirty_min.inf(&local_min);
+                            *dirty_max = dirty_max.sup(&local_max);
+                        })
+                        .or_insert((local_min, local_max));
+                }
+            }
+        }
+    }
+
+    /// Recomputes the parts of the distance maps marked dirty, in parallel per chunk.
+    pub fn refresh_distance_maps(&mut self) {
+        if self.dirty_distances.is_empty() {
+            return;
+        }
+        let dirty: Vec<_> = std::mem::take(&mut self.dirty_distances)
+            .into_iter()
+            .collect();
+        let radius = self.distance_radius;
+        let map = &*self;
+        let updates: Vec<_> = dirty
+            .into_par_iter()
+            .filter(|(position, _)| map.chunks.contains_key(position))
+            .map(|(position, (min, max))| {
+                let origin = Vector3::new(position.0, position.1, position.2) * 16 + min;
+                let size = (max - min).map(|v| v as usize + 1);
+                let distances = distance_field(map, origin, size, radius);
+                (position, min, size, distances)
+            })
+            .collect();
+
+        for (position, min, size, distances) in updates {
+            let chunk = self.chunks.get_mut(&position).unwrap();
+            let mut distances = distances.into_iter();
+            for z in 0..size.z {
+                for y in 0..size.y {
+                    for x in 0..size.x {
+                        let local = min + Vector3::new(x, y, z).map(|v| v as i32);
+                        chunk.set_distance(
+                            local.x as u8,
+                            local.y as u8,
+                            local.z as u8,
+                            distances.next().unwrap(),
+                        );
+                    }
+                }
+            }
+            chunk.compact();
+        }
+    }
+
+    /// Drops the storage of loaded chunks that no longer contain any voxel.
+    pub fn release_empty_chunks(&mut self) {
+        let empty: Vec<ChunkPosition> = self
+            .chunks
+            .iter_mut()
+            .filter_map(|(position, chunk)| {
+                chunk.compact();
+                chunk.is_empty().then_some(*position)
+            })
+            .collect();
+        for position in empty {
+            self.chunks.remove(&position);
+            self.empty_chunks.insert(position);
+        }
+    }
+
+    pub fn get_voxel(&self, x: i32, y: i32, z: i32) -> Option<&Voxel> {
+        match self.chunk_state(x, y, z) {
+            ChunkState::Loaded(chunk) => {
+                let voxel_x = x.rem_euclid(16);
+                let voxel_y = y.rem_euclid(16);
+                let voxel_z = z.rem_euclid(16);
+                chunk.get_voxel(voxel_x as u8, voxel_y as u8, voxel_z as u8)
+            }
+            ChunkState::Empty => Some(&EMPTY_VOXEL),
+            ChunkState::Unloaded => None,
+        }
+    }
+
+    /// Mutable access to a voxel of a loaded or known-empty chunk. The change is written with
+    /// `set_voxel` when the guard is dropped, so it is journaled and the distance maps around
+    /// it are updated.
+    pub fn get_voxel_mut(&mut self, x: i32, y: i32, z: i32) -> Option<MapVoxelMut<'_>> {
+        let position = (x.div_euclid(16), y.div_euclid(16), z.div_euclid(16));
+        self.fault_in_chunk(position);
+        let before = match self.resident_chunk_state(x, y, z) {
+            ChunkState::Loaded(chunk) => *chunk.get_voxel(
+                x.rem_euclid(16) as u8,
+                y.rem_euclid(16) as u8,
+                z.rem_euclid(16) as u8,
+            )?,
+            ChunkState::Empty => Voxel::empty(),
+            ChunkState::Unloaded => return None,
+        };
+        Some(MapVoxelMut {
+            map: self,
+            position: (x, y, z),
+            voxel: before,
+            before,
+        })
     }
 
     pub fn get_distance(&self, x: i32, y: i32, z: i32) -> u8 {
-        let chunk_x = x.div_euclid(16);
-        let chunk_y = y.div_euclid(16);
-        let chunk_z = z.div_euclid(16);
-        let chunk = self.chunks.get(&(chunk_x, chunk_y, chunk_z));
-        if let Some(chunk) = chunk {
+        if let ChunkState::Loaded(chunk) = self.chunk_state(x, y, z) {
             let voxel_x = x.rem_euclid(16);
             let voxel_y = y.rem_euclid(16);
             let voxel_z = z.rem_euclid(16);
@@ -84,26 +499,64 @@ impl Map {
         }
     }
 
+    /// Approximate number of bytes used by the chunks of the map.
+    pub fn memory_usage(&self) -> usize {
+        self.chunks.values().map(Chunk::memory_usage).sum::<usize>()
+            + self.empty_chunks.len() * size_of::<ChunkPosition>()
+    }
+
     pub fn is_within_bounds(&self, x: i32, y: i32, z: i32) -> bool {
         let chunk_x = x.div_euclid(16);
         let chunk_y = y.div_euclid(16);
         let chunk_z = z.div_euclid(16);
-        self.chunks.contains_key(&(chunk_x, chunk_y, chunk_z))
+        let position = (chunk_x, chunk_y, chunk_z);
+        self.chunks.contains_key(&position) || self.empty_chunks.contains(&position)
     }
 
-    pub fn generate(&mut self, perlin: &PerlinGenerator) {
-        for x in -4..=4 {
-            for y in -4..=4 {
-                for z in -4..=4 {
-                    let mut chunk = Chunk::new((x, y, z));
-                    chunk.generate(Vector3::new(x, y, z) * 16, perlin);
-                    self.set(x * 16, y * 16, z * 16, chunk);
+    pub fn generate(&mut self, seed: WorldSeed) {
+        self.generate_region(seed, (-4, -4, -4), (4, 4, 4));
+    }
+
+    /// Generates the Perlin caves of the seed in every chunk between `min` and `max`
+    /// (inclusive, in chunk coordinates).
+    pub fn generate_region(&mut self, seed: WorldSeed, min: ChunkPosition, max: ChunkPosition) {
+        self.seed = seed;
+        let caves = PerlinCaves::new(seed, &self.materials);
+        self.generate_region_with(&caves, min, max);
+    }
+
+    /// Replaces every chunk between `min` and `max` (inclusive, in chunk coordinates) by the
+    /// chunks of a generator. The seed of the map is left alone.
+    pub fn generate_region_with(
+        &mut self,
+        generator: &dyn WorldGenerator,
+        min: ChunkPosition,
+        max: ChunkPosition,
+    ) {
+        let mut positions = Vec::new();
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                for z in min.2..=max.2 {
+                    positions.push((x, y, z));
                 }
             }
         }
+        let chunks: Vec<Chunk> = positions
+            .into_par_iter()
+            .map(|position| {
+                let mut chunk = Chunk::new(position);
+                generator.generate_chunk(position, &mut chunk);
+                chunk
+            })
+            .collect();
+        for chunk in chunks {
+            self.store(chunk.position, chunk);
+        }
     }
 
     pub fn generate_all_distance_maps(&mut self, radius: i32) {
+        self.distance_radius = radius;
+        self.dirty_distances.clear();
         let map_clone = Arc::new(self.clone());
         let total_chunks = self.chunks.len();
         let time = std::time::Instant::now();
@@ -141,4 +594,94 @@ impl Map {
             total_completed as f32 / total_elapsed
         );
     }
+
+    /// Same as `generate_all_distance_maps`, without reporting progress on stdout.
+    pub(crate) fn rebuild_distance_maps(&mut self, radius: i32) {
+        self.distance_radius = radius;
+        self.dirty_distances.clear();
+        let map = self.clone();
+        self.chunks
+            .par_iter_mut()
+            .for_each(|(_, chunk)| chunk.generate_distance_map(&map, radius));
+    }
+}
+
+/// Chunks containing the voxels changed by a transaction.
+fn target_chunks(transaction: &Transaction) -> HashSet<ChunkPosition> {
+    transaction
+        .changes
+        .iter()
+        .map(|change| {
+            let (x, y, z) = change.position;
+            (x.div_euclid(16), y.div_euclid(16), z.div_euclid(16))
+        })
+        .collect()
+}
+
+/// Mutable access to a chunk of a map, see `Map::get_mut`. Changes are made to a copy, which
+/// replaces the chunk of the map when the guard is dropped.
+pub struct MapChunkMut<'a> {
+    map: &'a mut Map,
+    position: ChunkPosition,
+    chunk: Chunk,
+    touched: bool,
+}
+
+impl Deref for MapChunkMut<'_> {
+    type Target = Chunk;
+
+    fn deref(&self) -> &Chunk {
+        &self.chunk
+    }
+}
+
+impl DerefMut for MapChunkMut<'_> {
+    fn deref_mut(&mut self) -> &mut Chunk {
+        self.touched = true;
+        &mut self.chunk
+    }
+}
+
+impl Drop for MapChunkMut<'_> {
+    fn drop(&mut self) {
+        // Like voxel edits, nothing is written to a streamed chunk that has not arrived yet.
+        let origin = Vector3::new(self.position.0, self.position.1, self.position.2) * 16;
+        let waiting = self.map.streamed && !self.map.is_within_bounds(origin.x, origin.y, origin.z);
+        if !self.touched || waiting {
+            return;
+        }
+        let mut chunk = std::mem::take(&mut self.chunk);
+        chunk.position = self.position;
+        self.map.set(origin.x, origin.y, origin.z, chunk);
+    }
+}
+
+/// Mutable access to a single voxel of a map, see `Map::get_voxel_mut`.
+pub struct MapVoxelMut<'a> {
+    map: &'a mut Map,
+    position: VoxelPosition,
+    voxel: Voxel,
+    before: Voxel,
+}
+
+impl Deref for MapVoxelMut<'_> {
+    type Target = Voxel;
+
+    fn deref(&self) -> &Voxel {
+        &self.voxel
+    }
+}
+
+impl DerefMut for MapVoxelMut<'_> {
+    fn deref_mut(&mut self) -> &mut Voxel {
+        &mut self.voxel
+    }
+}
+
+impl Drop for MapVoxelMut<'_> {
+    fn drop(&mut self) {
+        if self.voxel != self.before {
+            self.map.set_voxels([(self.position, self.voxel)]);
+        }
+    }
 }
@@ -0,0 +1,190 @@
+use crate::voxel::Voxel;
+use nalgebra::Vector3;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+pub type MaterialId = u16;
+
+/// Id of the implicit air material every registry starts with.
+pub const AIR: MaterialId = 0;
+
+const BUILTIN_MATERIALS: &str = include_str!("../assets/materials.toml");
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Material {
+    pub name: String,
+    pub albedo: Vector3<u8>,
+    pub emissive: f32,
+    pub opacity: f32,
+    pub reflectivity: f32,
+    pub roughness: f32,
+}
+
+impl Material {
+    pub fn new(name: &str, albedo: Vector3<u8>) -> Self {
+        Self {
+            name: name.to_string(),
+            albedo,
+            emissive: 0.0,
+            opacity: 1.0,
+            reflectivity: 0.0,
+            roughness: 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MaterialError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    ReservedName(String),
+    TooManyMaterials,
+}
+
+impl fmt::Display for MaterialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaterialError::Io(e) => write!(f, "could not read material file: {}", e),
+            MaterialError::Parse(e) => write!(f, "invalid material file: {}", e),
+            MaterialError::ReservedName(name) => write!(f, "material name {} is reserved", name),
+            MaterialError::TooManyMaterials => write!(f, "too many materials"),
+        }
+    }
+}
+
+impl std::error::Error for MaterialError {}
+
+impl From<std::io::Error> for MaterialError {
+    fn from(e: std::io::Error) -> Self {
+        MaterialError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for MaterialError {
+    fn from(e: toml::de::Error) -> Self {
+        MaterialError::Parse(e)
+    }
+}
+
+#[derive(Deserialize)]
+struct MaterialFile {
+    #[serde(default)]
+    material: Vec<MaterialEntry>,
+}
+
+#[derive(Deserialize)]
+struct MaterialEntry {
+    name: String,
+    albedo: [u8; 3],
+    #[serde(default)]
+    emissive: f32,
+    #[serde(default = "one")]
+    opacity: f32,
+    #[serde(default)]
+    reflectivity: f32,
+    #[serde(default = "one")]
+    roughness: f32,
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+/// Maps material ids stored in voxels to their properties, and names to ids.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterialRegistry {
+    materials: Vec<Material>,
+    ids: HashMap<String, MaterialId>,
+}
+
+impl MaterialRegistry {
+    /// Creates a registry containing only air.
+    pub fn new() -> Self {
+        let air = Material {
+            opacity: 0.0,
+            ..Material::new("air", Vector3::new(0, 0, 0))
+        };
+        Self {
+            ids: HashMap::from([(air.name.clone(), AIR)]),
+            materials: vec![air],
+        }
+    }
+
+    /// The materials shipped in `assets/materials.toml`.
+    pub fn builtin() -> Self {
+        Self::from_toml(BUILTIN_MATERIALS).expect("built-in materials are valid")
+    }
+
+    pub fn load(path: &Path) -> Result<Self, MaterialError> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn from_toml(source: &str) -> Result<Self, MaterialError> {
+        let file: MaterialFile = toml::from_str(source)?;
+        let mut registry = Self::new();
+        for entry in file.material {
+            registry.register(Material {
+                name: entry.name,
+                albedo: Vector3::from(entry.albedo),
+                emissive: entry.emissive,
+                opacity: entry.opacity.clamp(0.0, 1.0),
+                reflectivity: entry.reflectivity.clamp(0.0, 1.0),
+                roughness: entry.roughness.clamp(0.0, 1.0),
+            })?;
+        }
+        Ok(registry)
+    }
+
+    /// Adds a material, or replaces the properties of the material with the same name.
+    pub fn register(&mut self, material: Material) -> Result<MaterialId, MaterialError> {
+        if let Some(&id) = self.ids.get(&material.name) {
+            if id == AIR {
+                return Err(MaterialError::ReservedName(material.name));
+            }
+            self.materials[id as usize] = material;
+            return Ok(id);
+        }
+
+        let id = MaterialId::try_from(self.materials.len())
+            .map_err(|_| MaterialError::TooManyMaterials)?;
+        self.ids.insert(material.name.clone(), id);
+        self.materials.push(material);
+        Ok(id)
+    }
+
+    pub fn get(&self, id: MaterialId) -> Option<&Material> {
+        self.materials.get(id as usize)
+    }
+
+    pub fn id(&self, name: &str) -> Option<MaterialId> {
+        self.ids.get(name).copied()
+    }
+
+    /// A voxel made of the named material.
+    pub fn voxel(&self, name: &str) -> Option<Voxel> {
+        self.id(name).map(Voxel::new)
+    }
+
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.materials.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (MaterialId, &Material)> {
+        self.materials
+            .iter()
+            .enumerate()
+            .map(|(id, material)| (id as MaterialId, material))
+    }
+}
+
+impl Default for MaterialRegistry {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
@@ -0,0 +1,407 @@
+//! Polygon meshes of the map, for tools that do not understand voxels.
+//!
+//! Faces between two voxels that hide each other are culled, also across chunk boundaries,
+//! and coplanar faces of the same material are merged into larger quads. Vertices are in
+//! voxel units, with a voxel spanning `[x, x + 1]` on each axis.
+
+use crate::chunk::{Chunk, ChunkPosition};
+use crate::map::{ChunkState, Map};
+use crate::material::{MaterialId, AIR};
+use nalgebra::Vector3;
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const CHUNK_SIZE: i32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quad {
+    /// Corners, counter-clockwise when seen from the side the quad faces.
+    pub corners: [Vector3<f32>; 4],
+    pub normal: Vector3<f32>,
+    pub material: MaterialId,
+    pub color: Vector3<u8>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Mesh {
+    pub quads: Vec<Quad>,
+}
+
+impl Mesh {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn append(&mut self, other: Mesh) {
+        self.quads.extend(other.quads);
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.quads.len() * 4
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.quads.len() * 2
+    }
+
+    /// Writes the mesh in the format given by the extension of `path`: `obj`, `ply`, or
+    /// `gltf`.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let mut writer = BufWriter::new(File::create(path)?);
+        match extension.to_ascii_lowercase().as_str() {
+            "obj" => self.write_obj(&mut writer)?,
+            "ply" => self.write_ply(&mut writer)?,
+            "gltf" => self.write_gltf(&mut writer)?,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown mesh format {}", path.display()),
+                ))
+            }
+        }
+        writer.flush()
+    }
+
+    /// Wavefront OBJ, with the vertex colors appended to the positions as most tools expect.
+    pub fn write_obj<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "# torus mesh, {} quads", self.quads.len())?;
+        for quad in &self.quads {
+            let color = quad.color.map(|c| c as f32 / 255.0);
+            for corner in &quad.corners {
+                writeln!(
+                    writer,
+                    "v {} {} {} {:.4} {:.4} {:.4}",
+                    corner.x, corner.y, corner.z, color.x, color.y, color.z
+                )?;
+            }
+        }
+        for quad in &self.quads {
+            writeln!(
+                writer,
+                "vn {} {} {}",
+                quad.normal.x, quad.normal.y, quad.normal.z
+            )?;
+        }
+        for (i, _) in self.quads.iter().enumerate() {
+            let (v, n) = (i * 4 + 1, i + 1);
+            writeln!(
+                writer,
+                "f {}//{} {}//{} {}//{} {}//{}",
+                v,
+                n,
+                v + 1,
+                n,
+                v + 2,
+                n,
+                v + 3,
+                n
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Binary little endian PLY with positions, normals and colors per vertex, and a quad
+    /// per face.
+    pub fn write_ply<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(
+            writer,
+            "ply\n\
+             format binary_little_endian 1.0\n\
+             comment torus mesh\n\
+             element vertex {}\n\
+             property float x\n\
+             property float y\n\
+             property float z\n\
+             property float nx\n\
+             property float ny\n\
+             property float nz\n\
+             property uchar red\n\
+             property uchar green\n\
+             property uchar blue\n\
+             element face {}\n\
+             property list uchar uint vertex_indices\n\
+             end_header\n",
+            self.vertex_count(),
+            self.quads.len()
+        )?;
+        for quad in &self.quads {
+            for corner in &quad.corners {
+                for value in corner.iter().chain(quad.normal.iter()) {
+                    writer.write_all(&value.to_le_bytes())?;
+                }
+                writer.write_all(quad.color.as_slice())?;
+            }
+        }
+        for i in 0..self.quads.len() as u32 {
+            writer.write_all(&[4])?;
+            for index in i * 4..i * 4 + 4 {
+                writer.write_all(&index.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// glTF 2.0 with a single triangle mesh, its buffer embedded as a data URI.
+    pub fn write_gltf<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let vertices = self.vertex_count();
+        let mut buffer = Vec::with_capacity(vertices * 28 + self.triangle_count() * 12);
+        let mut min = Vector3::repeat(f32::MAX);
+        let mut max = Vector3::repeat(f32::MIN);
+        for quad in &self.quads {
+            for corner in &quad.corners {
+                min = min.inf(corner);
+                max = max.sup(corner);
+                for value in corner.iter() {
+                    buffer.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+        for quad in &self.quads {
+            for _ in 0..4 {
+                for value in quad.normal.iter() {
+                    buffer.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+        for quad in &self.quads {
+            for _ in 0..4 {
+                buffer.extend_from_slice(quad.color.as_slice());
+                buffer.push(0xff);
+            }
+        }
+        for i in 0..self.quads.len() as u32 {
+            for corner in [0, 1, 2, 0, 2, 3] {
+                buffer.extend_from_slice(&(i * 4 + corner).to_le_bytes());
+            }
+        }
+        if vertices == 0 {
+            (min, max) = (Vector3::zeros(), Vector3::zeros());
+        }
+
+        let (positions, normals, colors) = (0, vertices * 12, vertices * 24);
+        let indices = vertices * 28;
+        write!(
+            writer,
+            r#"{{"asset":{{"version":"2.0","generator":"torus"}},"scene":0,"scenes":[{{"nodes":[0]}}],"nodes":[{{"mesh":0}}],"#
+        )?;
+        write!(
+            writer,
+            r#""meshes":[{{"primitives":[{{"attributes":{{"POSITION":0,"NORMAL":1,"COLOR_0":2}},"indices":3}}]}}],"#
+        )?;
+        write!(
+            writer,
+            r#""accessors":[{{"bufferView":0,"componentType":5126,"count":{v},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}},"#,
+            min.x,
+            min.y,
+            min.z,
+            max.x,
+            max.y,
+            max.z,
+            v = vertices
+        )?;
+        write!(
+            writer,
+            r#"{{"bufferView":1,"componentType":5126,"count":{v},"type":"VEC3"}},{{"bufferView":2,"componentType":5121,"normalized":true,"count":{v},"type":"VEC4"}},{{"bufferView":3,"componentType":5125,"count":{},"type":"SCALAR"}}],"#,
+            self.triangle_count() * 3,
+            v = vertices
+        )?;
+        write!(
+            writer,
+            r#""bufferViews":[{{"buffer":0,"byteOffset":{},"byteLength":{},"target":34962}},{{"buffer":0,"byteOffset":{},"byteLength":{},"target":34962}},{{"buffer":0,"byteOffset":{},"byteLength":{},"target":34962}},{{"buffer":0,"byteOffset":{},"byteLength":{},"target":34963}}],"#,
+            positions,
+            vertices * 12,
+            normals,
+            vertices * 12,
+            colors,
+            vertices * 4,
+            indices,
+            buffer.len() - indices
+        )?;
+        writeln!(
+            writer,
+            r#""buffers":[{{"byteLength":{},"uri":"data:application/octet-stream;base64,{}"}}]}}"#,
+            buffer.len(),
+            base64(&buffer)
+        )
+    }
+}
+
+impl Map {
+    /// Meshes a chunk, culling faces against the neighboring chunks. Faces towards chunks
+    /// that are not in memory are kept.
+    pub fn mesh_chunk(&self, position: ChunkPosition) -> Mesh {
+        match self.chunks.get(&position) {
+            Some(chunk) => self.mesh(chunk),
+            None => Mesh::new(),
+        }
+    }
+
+    /// Meshes the chunks between `min` and `max`, both included, in parallel.
+    pub fn mesh_region(&self, min: ChunkPosition, max: ChunkPosition) -> Mesh {
+        let mut positions: Vec<_> = self
+            .chunks
+            .keys()
+            .filter(|(x, y, z)| {
+                (min.0..=max.0).contains(x)
+                    && (min.1..=max.1).contains(y)
+                    && (min.2..=max.2).contains(z)
+            })
+            .copied()
+            .collect();
+        positions.sort();
+        self.mesh_chunks(&positions)
+    }
+
+    /// Meshes every chunk in memory.
+    pub fn mesh_all(&self) -> Mesh {
+        let mut positions: Vec<_> = self.chunks.keys().copied().collect();
+        positions.sort();
+        self.mesh_chunks(&positions)
+    }
+
+    fn mesh_chunks(&self, positions: &[ChunkPosition]) -> Mesh {
+        let meshes: Vec<Mesh> = positions
+            .par_iter()
+            .map(|position| self.mesh_chunk(*position))
+            .collect();
+        let mut mesh = Mesh::new();
+        for chunk_mesh in meshes {
+            mesh.append(chunk_mesh);
+        }
+        mesh
+    }
+
+    /// Greedy meshing: each slice of faces pointing the same way is covered by rectangles of
+    /// one material, grown along the first axis of the slice and then along the second.
+    fn mesh(&self, chunk: &Chunk) -> Mesh {
+        let origin =
+            Vector3::new(chunk.position.0, chunk.position.1, chunk.position.2) * CHUNK_SIZE;
+        let material_at = |position: Vector3<i32>| {
+            let local = position - origin;
+            if local.iter().all(|v| (0..CHUNK_SIZE).contains(v)) {
+                chunk
+                    .get_voxel(local.x as u8, local.y as u8, local.z as u8)
+                    .copied()
+            } else {
+                // Neighbors are never faulted in, faces towards missing chunks are kept.
+                match self.resident_chunk_state(position.x, position.y, position.z) {
+                    ChunkState::Loaded(neighbor) => {
+                        let local = position.map(|v| v.rem_euclid(CHUNK_SIZE) as u8);
+                        neighbor.get_voxel(local.x, local.y, local.z).copied()
+                    }
+                    ChunkState::Empty | ChunkState::Unloaded => None,
+                }
+            }
+            .map_or(AIR, |v| v.material)
+        };
+
+        let mut mesh = Mesh::new();
+        let size = CHUNK_SIZE as usize;
+        for axis in 0..3 {
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            for sign in [1, -1] {
+                let mut normal = Vector3::zeros();
+                normal[axis] = sign;
+                for depth in 0..CHUNK_SIZE {
+                    let mut mask = vec![None; size * size];
+                    for j in 0..CHUNK_SIZE {
+                        for i in 0..CHUNK_SIZE {
+                            let mut local = Vector3::zeros();
+                            (local[axis], local[u], local[v]) = (depth, i, j);
+                            let position = origin + local;
+                            let material = material_at(position);
+                            if self.face_visible(material, material_at(position + normal)) {
+                                mask[j as usize * size + i as usize] = Some(material);
+                            }
+                        }
+                    }
+
+                    for j in 0..size {
+                        let mut i = 0;
+                        while i < size {
+                            let Some(material) = mask[j * size + i] else {
+                                i += 1;
+                                continue;
+                            };
+                            let mut width = 1;
+                            while i + width < size && mask[j * size + i + width] == Some(material) {
+                                width += 1;
+                            }
+                            let mut height = 1;
+                            while j + height < size
+                                && (i..i + width)
+                                    .all(|k| mask[(j + height) * size + k] == Some(material))
+                            {
+                                height += 1;
+                            }
+                            for row in j..j + height {
+                                mask[row * size + i..row * size + i + width].fill(None);
+                            }
+
+                            let mut corner = origin.map(|c| c as f32);
+                            corner[axis] += (depth + i32::from(sign > 0)) as f32;
+                            corner[u] += i as f32;
+                            corner[v] += j as f32;
+                            let (mut du, mut dv) = (Vector3::zeros(), Vector3::zeros());
+                            du[u] = width as f32;
+                            dv[v] = height as f32;
+                            let mut corners = [corner, corner + du, corner + du + dv, corner + dv];
+                            if sign < 0 {
+                                corners.reverse();
+                            }
+                            let color = self
+                                .materials
+                                .get(material)
+                                .map_or(Vector3::zeros(), |m| m.albedo);
+                            mesh.quads.push(Quad {
+                                corners,
+                                normal: normal.map(|n| n as f32),
+                                material,
+                                color,
+                            });
+                            i += width;
+                        }
+                    }
+                }
+            }
+        }
+        mesh
+    }
+
+    /// A solid face is hidden by an opaque neighbor, or by a see-through neighbor of the
+    /// same material so that the inside of water is not meshed.
+    fn face_visible(&self, material: MaterialId, neighbor: MaterialId) -> bool {
+        if material == AIR {
+            return false;
+        }
+        if neighbor == AIR {
+            return true;
+        }
+        let opaque = self
+            .materials
+            .get(neighbor)
+            .is_none_or(|m| m.opacity >= 1.0);
+        !opaque && neighbor != material
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for group in bytes.chunks(3) {
+        let value = group.iter().enumerate().fold(0u32, |value, (i, byte)| {
+            value | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= group.len() {
+                encoded.push(ALPHABET[(value >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
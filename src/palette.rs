@@ -0,0 +1,223 @@
+use std::mem::size_of;
+
+/// Fixed-length array storing each distinct value once in a palette and the elements as
+/// bit-packed palette indices. An array holding a single value needs no index storage at all.
+///
+/// Palette entries are reference counted: entries left unused by `set` are reused for new
+/// values, and `compact` drops them and narrows the indices.
+#[derive(Debug, Clone)]
+pub struct PalettedArray<T> {
+    len: usize,
+    palette: Vec<T>,
+    /// Number of elements referring to each palette entry.
+    counts: Vec<u32>,
+    /// Bits per index, one of 0, 1, 2, 4, 8 or 16 so that indices never straddle two words.
+    bits: u32,
+    words: Vec<u64>,
+}
+
+impl<T: Copy + PartialEq> PalettedArray<T> {
+    pub fn new(len: usize, value: T) -> Self {
+        Self {
+            len,
+            palette: vec![value],
+            counts: vec![len as u32],
+            bits: 0,
+            words: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the value shared by every element, if there is only one.
+    pub fn uniform(&self) -> Option<&T> {
+        if self.bits == 0 {
+            return self.palette.first();
+        }
+        let mut used = self
+            .palette
+            .iter()
+            .zip(&self.counts)
+            .filter(|(_, count)| **count > 0);
+        match (used.next(), used.next()) {
+            (Some((value, _)), None) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn palette(&self) -> &[T] {
+        &self.palette
+    }
+
+    pub fn get(&self, index: usize) -> &T {
+        &self.palette[self.palette_index(index)]
+    }
+
+    pub fn set(&mut self, index: usize, value: T) {
+        assert!(index < self.len, "index {} out of bounds", index);
+        let previous = self.palette_index(index);
+        if self.palette[previous] == value {
+            return;
+        }
+        self.counts[previous] -= 1;
+        let palette_index = match self.palette.iter().position(|v| *v == value) {
+            Some(palette_index) => palette_index,
+            None if self.palette.len() < 1 << self.bits => self.push(value),
+            // The palette is full: an entry no element refers to anymore is reused before the
+            // indices are widened.
+            None => match self.counts.iter().position(|count| *count == 0) {
+                Some(unused) => {
+                    self.palette[unused] = value;
+                    unused
+                }
+                None => {
+                    self.repack(next_bits(self.bits));
+                    self.push(value)
+                }
+            },
+        };
+        self.counts[palette_index] += 1;
+        if self.bits > 0 {
+            self.write_index(index, palette_index);
+        }
+    }
+
+    /// Sets every element to `value`, dropping the index storage.
+    pub fn fill(&mut self, value: T) {
+        self.palette = vec![value];
+        self.counts = vec![self.len as u32];
+        self.bits = 0;
+        self.words = Vec::new();
+    }
+
+    /// Removes palette entries no element refers to anymore and shrinks the indices to the
+    /// smallest width that fits, collapsing to the single-value representation when possible.
+    pub fn compact(&mut self) {
+        if self.bits == 0 {
+            return;
+        }
+
+        let mut remap = vec![0; self.palette.len()];
+        let mut palette = Vec::new();
+        let mut counts = Vec::new();
+        for (old, (value, count)) in self.palette.iter().zip(&self.counts).enumerate() {
+            if *count > 0 {
+                remap[old] = palette.len();
+                palette.push(*value);
+                counts.push(*count);
+            }
+        }
+
+        let bits = bits_for(palette.len());
+        let indices: Vec<usize> = (0..self.len)
+            .map(|index| remap[self.palette_index(index)])
+            .collect();
+        self.palette = palette;
+        self.counts = counts;
+        self.bits = bits;
+        self.words = vec![0; words_for(self.len, bits)];
+        if bits > 0 {
+            for (index, palette_index) in indices.into_iter().enumerate() {
+                self.write_index(index, palette_index);
+            }
+        }
+        self.palette.shrink_to_fit();
+        self.counts.shrink_to_fit();
+    }
+
+    /// Whether `compact` would narrow the indices, because the values still in use fit in
+    /// fewer bits. Cheap, unlike `compact` which goes over every element.
+    pub fn can_shrink(&self) -> bool {
+        let used = self.counts.iter().filter(|count| **count > 0).count();
+        bits_for(used) < self.bits
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        (0..self.len).map(move |index| self.get(index))
+    }
+
+    /// Heap and inline bytes used by this array.
+    pub fn memory_usage(&self) -> usize {
+        size_of::<Self>()
+            + self.palette.capacity() * size_of::<T>()
+            + self.counts.capacity() * size_of::<u32>()
+            + self.words.capacity() * size_of::<u64>()
+    }
+
+    /// Appends a value no element refers to yet, returning its palette index.
+    fn push(&mut self, value: T) -> usize {
+        self.palette.push(value);
+        self.counts.push(0);
+        self.palette.len() - 1
+    }
+
+    fn palette_index(&self, index: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+        let per_word = 64 / self.bits as usize;
+        let shift = (index % per_word) as u32 * self.bits;
+        let mask = (1u64 << self.bits) - 1;
+        ((self.words[index / per_word] >> shift) & mask) as usize
+    }
+
+    fn write_index(&mut self, index: usize, palette_index: usize) {
+        let per_word = 64 / self.bits as usize;
+        let shift = (index % per_word) as u32 * self.bits;
+        let mask = (1u64 << self.bits) - 1;
+        let word = &mut self.words[index / per_word];
+        *word = (*word & !(mask << shift)) | ((palette_index as u64 & mask) << shift);
+    }
+
+    fn repack(&mut self, bits: u32) {
+        let indices: Vec<usize> = (0..self.len).map(|i| self.palette_index(i)).collect();
+        self.bits = bits;
+        self.words = vec![0; words_for(self.len, bits)];
+        for (index, palette_index) in indices.into_iter().enumerate() {
+            self.write_index(index, palette_index);
+        }
+    }
+}
+
+impl<T: Copy + PartialEq> PartialEq for PalettedArray<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Copy + PartialEq + Default> Default for PalettedArray<T> {
+    fn default() -> Self {
+        Self::new(0, T::default())
+    }
+}
+
+fn next_bits(bits: u32) -> u32 {
+    match bits {
+        0 => 1,
+        16 => panic!("palette cannot hold more than 65536 values"),
+        bits => bits * 2,
+    }
+}
+
+/// Smallest index width addressing `entries` palette entries.
+fn bits_for(entries: usize) -> u32 {
+    let mut bits = 0;
+    while 1 << bits < entries {
+        bits = next_bits(bits);
+    }
+    bits
+}
+
+fn words_for(len: usize, bits: u32) -> usize {
+    if bits == 0 {
+        0
+    } else {
+        len.div_ceil(64 / bits as usize)
+    }
+}
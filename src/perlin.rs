@@ -13,4 +13,23 @@ impl PerlinGenerator {
     pub fn get(&self, x: f64, y: f64, z: f64) -> f64 {
         self.noise.get([x, y, z])
     }
+
+    /// Fractal Brownian motion over 2D noise: `octaves` layers whose frequency grows by
+    /// `lacunarity` and amplitude by `persistence` from one to the next. Normalized to
+    /// [-1, 1].
+    pub fn fbm_2d(&self, x: f64, z: f64, octaves: u32, lacunarity: f64, persistence: f64) -> f64 {
+        let (mut frequency, mut amplitude) = (1.0, 1.0);
+        let (mut sum, mut total) = (0.0, 0.0);
+        for _ in 0..octaves {
+            sum += self.noise.get([x * frequency, z * frequency]) * amplitude;
+            total += amplitude;
+            frequency *= lacunarity;
+            amplitude *= persistence;
+        }
+        if total > 0.0 {
+            sum / total
+        } else {
+            0.0
+        }
+    }
 }
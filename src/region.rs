@@ -0,0 +1,814 @@
+//! Chunk storage on disk, loaded lazily.
+//!
+//! A region file holds up to 16x16x16 chunks. It starts with the magic `TRSR`, a format version
+//! (u16), the region size (u8) and a reserved byte, followed by an offset table with one entry
+//! per chunk: the offset (u64), length (u32) and CRC-32 (u32) of its deflate-compressed voxels.
+//! An entry of zeros means the chunk is not stored, an offset of `u64::MAX` means the chunk is
+//! stored but empty. Rewritten chunks go to the first gap left by earlier rewrites that fits,
+//! or are appended, and their entry is updated in place. The space of the previous copy is only
+//! freed once the entry points to the new one, so an interrupted write never loses a chunk.
+//! Distance maps are not stored, they are recomputed when chunks are loaded.
+
+use crate::chunk::{Chunk, ChunkPosition};
+use crate::map::{ChunkState, Map};
+use crate::material::MaterialRegistry;
+use crate::world_file::{
+    decode_chunk_bytes, deflate, encode_chunk, inflate, WorldFileError, MAX_CHUNK_PAYLOAD,
+};
+use nalgebra::Vector3;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Chunks per axis in a region file.
+pub const REGION_SIZE: i32 = 16;
+
+/// Name of the file holding everything but the chunks in a region directory.
+pub const LEVEL_FILE: &str = "level.torus";
+
+const MAGIC: &[u8; 4] = b"TRSR";
+const REGION_VERSION: u16 = 1;
+const REGION_VOLUME: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+const HEADER_LENGTH: u64 = 8;
+const ENTRY_LENGTH: usize = 16;
+const EMPTY_OFFSET: u64 = u64::MAX;
+const FAULTED_BUCKETS: usize = 256;
+
+pub type RegionPosition = (i32, i32, i32);
+
+/// Where a map loads the chunks it does not hold in memory from, and writes them back to.
+pub trait ChunkSource: Send {
+    /// Reads a chunk, returning `None` if the source does not have it. Empty chunks are
+    /// returned as chunks without voxels.
+    fn load_chunk(
+        &mut self,
+        position: ChunkPosition,
+        materials: &MaterialRegistry,
+    ) -> Result<Option<Chunk>, WorldFileError>;
+
+    fn store_chunk(&mut self, chunk: &Chunk) -> Result<(), WorldFileError>;
+
+    /// Whether the chunk may hold solid voxels, told without reading it: false only if the
+    /// source does not have it or has it empty. Distance maps treat the chunks that may as
+    /// solid until they are loaded, so rays never skip into them.
+    fn may_hold_voxels(&mut self, _position: ChunkPosition) -> bool {
+        true
+    }
+
+    /// Makes sure everything stored so far is on disk.
+    fn flush(&mut self) -> Result<(), WorldFileError> {
+        Ok(())
+    }
+}
+
+/// Chunk source reading and writing the region files of a directory.
+pub struct RegionStore {
+    directory: PathBuf,
+    regions: HashMap<RegionPosition, RegionFile>,
+}
+
+impl RegionStore {
+    pub fn open(directory: &Path) -> Result<Self, WorldFileError> {
+        std::fs::create_dir_all(directory)?;
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            regions: HashMap::new(),
+        })
+    }
+
+    fn region_path(&self, (x, y, z): RegionPosition) -> PathBuf {
+        self.directory.join(format!("r.{}.{}.{}.region", x, y, z))
+    }
+
+    /// Returns the open region file, opening or creating it first if needed. Returns `None`
+    /// when the file does not exist and `create` is false.
+    fn region(
+        &mut self,
+        position: RegionPosition,
+        create: bool,
+    ) -> Result<Option<&mut RegionFile>, WorldFileError> {
+        if !self.regions.contains_key(&position) {
+            let path = self.region_path(position);
+            if !create && !path.exists() {
+                return Ok(None);
+            }
+            self.regions.insert(position, RegionFile::open(&path)?);
+        }
+        Ok(self.regions.get_mut(&position))
+    }
+}
+
+impl ChunkSource for RegionStore {
+    fn load_chunk(
+        &mut self,
+        position: ChunkPosition,
+        materials: &MaterialRegistry,
+    ) -> Result<Option<Chunk>, WorldFileError> {
+        let (region, index) = region_of(position);
+        match self.region(region, false)? {
+            Some(file) => file.read(index, position, materials),
+            None => Ok(None),
+        }
+    }
+
+    fn store_chunk(&mut self, chunk: &Chunk) -> Result<(), WorldFileError> {
+        let (region, index) = region_of(chunk.position);
+        self.region(region, true)?.unwrap().write(index, chunk)
+    }
+
+    fn may_hold_voxels(&mut self, position: ChunkPosition) -> bool {
+        let (region, index) = region_of(position);
+        match self.region(region, false) {
+            Ok(Some(file)) => {
+                let entry = file.table[index];
+                entry != Entry::default() && entry.offset != EMPTY_OFFSET
+            }
+            Ok(None) => false,
+            // Unreadable regions are loaded as not stored, but may be fixed meanwhile.
+            Err(_) => true,
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), WorldFileError> {
+        for region in self.regions.values_mut() {
+            region.file.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+/// Region containing a chunk, and the index of the chunk in its offset table.
+fn region_of(position: ChunkPosition) -> (RegionPosition, usize) {
+    let position = Vector3::new(position.0, position.1, position.2);
+    let region = position.map(|v| v.div_euclid(REGION_SIZE));
+    let local = position.map(|v| v.rem_euclid(REGION_SIZE) as usize);
+    let size = REGION_SIZE as usize;
+    let index = (local.z * size + local.y) * size + local.x;
+    ((region.x, region.y, region.z), index)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Entry {
+    offset: u64,
+    length: u32,
+    checksum: u32,
+}
+
+struct RegionFile {
+    file: File,
+    table: Vec<Entry>,
+    end: u64,
+    /// Unused byte ranges between stored chunks, as offset and length, sorted by offset.
+    free: Vec<(u64, u64)>,
+}
+
+impl RegionFile {
+    fn open(path: &Path) -> Result<Self, WorldFileError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let table_length = REGION_VOLUME * ENTRY_LENGTH;
+        let end = file.metadata()?.len();
+        if end == 0 {
+            let mut header = Vec::with_capacity(HEADER_LENGTH as usize + table_length);
+            header.extend_from_slice(MAGIC);
+            header.extend_from_slice(&REGION_VERSION.to_le_bytes());
+            header.push(REGION_SIZE as u8);
+            header.push(0);
+            header.resize(HEADER_LENGTH as usize + table_length, 0);
+            file.write_all(&header)?;
+            return Ok(Self {
+                file,
+                table: vec![Entry::default(); REGION_VOLUME],
+                end: header.len() as u64,
+                free: Vec::new(),
+            });
+        }
+
+        let mut header = [0; HEADER_LENGTH as usize];
+        file.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(WorldFileError::NotAWorld);
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version > REGION_VERSION {
+            return Err(WorldFileError::UnsupportedVersion(version));
+        }
+        if header[6] != REGION_SIZE as u8 {
+            return Err(WorldFileError::Corrupt(format!(
+                "unsupported region size {}",
+                header[6]
+            )));
+        }
+        let mut table = vec![0; table_length];
+        file.read_exact(&mut table)?;
+        let table = table
+            .chunks_exact(ENTRY_LENGTH)
+            .map(|entry| Entry {
+                offset: u64::from_le_bytes(entry[..8].try_into().unwrap()),
+                length: u32::from_le_bytes(entry[8..12].try_into().unwrap()),
+                checksum: u32::from_le_bytes(entry[12..].try_into().unwrap()),
+            })
+            .collect::<Vec<_>>();
+
+        // The gaps between the chunks still referenced are free.
+        let mut used: Vec<(u64, u64)> = table
+            .iter()
+            .filter(|entry| entry.offset != EMPTY_OFFSET && entry.length > 0)
+            .map(|entry| {
+                (
+                    entry.offset,
+                    entry.offset.saturating_add(entry.length as u64),
+                )
+            })
+            .collect();
+        used.sort();
+        let mut free = Vec::new();
+        let mut position = HEADER_LENGTH + table_length as u64;
+        for (start, stop) in used {
+            // Entries pointing past the end are reported when read, not freed.
+            let start = start.min(end);
+            if start > position {
+                free.push((position, start - position));
+            }
+            position = position.max(stop);
+        }
+        Ok(Self {
+            file,
+            table,
+            end,
+            free,
+        })
+    }
+
+    fn read(
+        &mut self,
+        index: usize,
+        position: ChunkPosition,
+        materials: &MaterialRegistry,
+    ) -> Result<Option<Chunk>, WorldFileError> {
+        let entry = self.table[index];
+        if entry == Entry::default() {
+            return Ok(None);
+        }
+        if entry.offset == EMPTY_OFFSET {
+            return Ok(Some(Chunk::new(position)));
+        }
+
+        let data_start = HEADER_LENGTH + (REGION_VOLUME * ENTRY_LENGTH) as u64;
+        let stop = entry.offset.checked_add(entry.length as u64);
+        if entry.offset < data_start || stop.is_none_or(|stop| stop > self.end) {
+            return Err(WorldFileError::Corrupt(format!(
+                "chunk {:?} is stored outside of its region file",
+                position
+            )));
+        }
+        let mut compressed = vec![0; entry.length as usize];
+        self.file.seek(SeekFrom::Start(entry.offset))?;
+        self.file.read_exact(&mut compressed)?;
+        if crc32fast::hash(&compressed) != entry.checksum {
+            return Err(WorldFileError::ChecksumMismatch(format!(
+                "chunk {:?}",
+                position
+            )));
+        }
+        let chunk =
+            decode_chunk_bytes(&inflate(&compressed, MAX_CHUNK_PAYLOAD)?, false, materials)?;
+        if chunk.position != position {
+            return Err(WorldFileError::Corrupt(format!(
+                "chunk {:?} is stored in place of {:?}",
+                chunk.position, position
+            )));
+        }
+        Ok(Some(chunk))
+    }
+
+    fn write(&mut self, index: usize, chunk: &Chunk) -> Result<(), WorldFileError> {
+        let entry = if chunk.is_empty() {
+            Entry {
+                offset: EMPTY_OFFSET,
+                length: 0,
+                checksum: 0,
+            }
+        } else {
+            let compressed = deflate(&encode_chunk(chunk, false))?;
+            let offset = self.allocate(compressed.len() as u64);
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.write_all(&compressed)?;
+            Entry {
+                offset,
+                length: compressed.len() as u32,
+                checksum: crc32fast::hash(&compressed),
+            }
+        };
+
+        let mut bytes = Vec::with_capacity(ENTRY_LENGTH);
+        bytes.extend_from_slice(&entry.offset.to_le_bytes());
+        bytes.extend_from_slice(&entry.length.to_le_bytes());
+        bytes.extend_from_slice(&entry.checksum.to_le_bytes());
+        self.file.seek(SeekFrom::Start(
+            HEADER_LENGTH + (index * ENTRY_LENGTH) as u64,
+        ))?;
+        self.file.write_all(&bytes)?;
+        let previous = std::mem::replace(&mut self.table[index], entry);
+        if previous.offset != EMPTY_OFFSET && previous.length > 0 {
+            self.release(previous.offset, previous.length as u64);
+        }
+        Ok(())
+    }
+
+    /// Finds room for `length` bytes, in the first gap large enough or at the end of the file.
+    fn allocate(&mut self, length: u64) -> u64 {
+        match self.free.iter().position(|(_, free)| *free >= length) {
+            Some(index) => {
+                let (offset, free) = self.free[index];
+                if free == length {
+                    self.free.remove(index);
+                } else {
+                    self.free[index] = (offset + length, free - length);
+                }
+                offset
+            }
+            None => {
+                let offset = self.end;
+                self.end += length;
+                offset
+            }
+        }
+    }
+
+    /// Returns the bytes of a chunk copy no entry points to anymore to the free ranges.
+    fn release(&mut self, offset: u64, length: u64) {
+        let index = self.free.partition_point(|(free, _)| *free < offset);
+        self.free.insert(index, (offset, length));
+        // Merges the range with the following one, then with the previous one.
+        for index in [index, index.saturating_sub(1)] {
+            if index + 1 < self.free.len() {
+                let (offset, length) = self.free[index];
+                let (next, next_length) = self.free[index + 1];
+                if offset + length == next {
+                    self.free[index] = (offset, length + next_length);
+                    self.free.remove(index + 1);
+                }
+            }
+        }
+    }
+}
+
+/// Tracks the chunks of a map backed by a chunk source: which were used least recently, and
+/// which were changed since they were loaded and must be written back.
+#[derive(Clone)]
+pub struct ChunkCache {
+    source: Arc<Mutex<dyn ChunkSource>>,
+    /// Most chunks with voxel storage kept in memory, the least recently used are evicted.
+    pub capacity: usize,
+    last_used: HashMap<ChunkPosition, u64>,
+    by_age: BTreeMap<u64, ChunkPosition>,
+    clock: u64,
+    modified: HashSet<ChunkPosition>,
+    /// Chunks the source does not have, or failed to load.
+    unavailable: HashSet<ChunkPosition>,
+    faulted: FaultedChunks,
+}
+
+impl ChunkCache {
+    fn touch(&mut self, position: ChunkPosition) {
+        self.clock += 1;
+        if let Some(previous) = self.last_used.insert(position, self.clock) {
+            self.by_age.remove(&previous);
+        }
+        self.by_age.insert(self.clock, position);
+    }
+}
+
+impl fmt::Debug for ChunkCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChunkCache")
+            .field("capacity", &self.capacity)
+            .field("resident", &self.last_used.len())
+            .field("modified", &self.modified.len())
+            .field("faulted", &self.faulted.len.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+impl PartialEq for ChunkCache {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.source, &other.source)
+            && self.capacity == other.capacity
+            && self.modified == other.modified
+    }
+}
+
+/// Chunks faulted in by readers holding a shared reference to the map. Entries are only
+/// added, to append-only lists, so every chunk keeps its address and can be lent out for as
+/// long as the map is borrowed. The next mutable access moves them into the map.
+struct FaultedChunks {
+    buckets: Box<[OnceLock<Box<FaultedChunk>>; FAULTED_BUCKETS]>,
+    len: AtomicUsize,
+}
+
+struct FaultedChunk {
+    position: ChunkPosition,
+    /// `None` when the source does not have the chunk or failed to load it.
+    chunk: Option<Chunk>,
+    next: OnceLock<Box<FaultedChunk>>,
+}
+
+impl Default for FaultedChunks {
+    fn default() -> Self {
+        Self {
+            buckets: Box::new(std::array::from_fn(|_| OnceLock::new())),
+            len: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl FaultedChunks {
+    fn bucket(&self, (x, y, z): ChunkPosition) -> &OnceLock<Box<FaultedChunk>> {
+        let hash =
+            (x.wrapping_mul(73_856_093) ^ y.wrapping_mul(19_349_663)) ^ z.wrapping_mul(83_492_791);
+        &self.buckets[hash as u32 as usize % FAULTED_BUCKETS]
+    }
+
+    /// The chunk recorded for the position, if any: `Some(None)` when it was not loaded.
+    fn get(&self, position: ChunkPosition) -> Option<Option<&Chunk>> {
+        let first = self.bucket(position).get();
+        std::iter::successors(first, |entry| entry.next.get())
+            .find(|entry| entry.position == position)
+            .map(|entry| entry.chunk.as_ref())
+    }
+
+    /// The chunk recorded for the position, loading it first if there is none. Readers racing
+    /// for the same chunk may both load it, only the first one to finish records it.
+    fn get_or_load(
+        &self,
+        position: ChunkPosition,
+        load: impl FnOnce() -> Option<Chunk>,
+    ) -> Option<&Chunk> {
+        let mut load = Some(load);
+        let mut loaded = None;
+        let mut slot = self.bucket(position);
+        loop {
+            if let Some(entry) = slot.get() {
+                if entry.position == position {
+                    return entry.chunk.as_ref();
+                }
+                slot = &entry.next;
+                continue;
+            }
+            let entry = loaded.take().unwrap_or_else(|| {
+                Box::new(FaultedChunk {
+                    position,
+                    chunk: load.take().unwrap()(),
+                    next: OnceLock::new(),
+                })
+            });
+            // Another reader may have appended to the list meanwhile, then walk on.
+            match slot.set(entry) {
+                Ok(()) => {
+                    self.len.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(entry) => loaded = Some(entry),
+            }
+        }
+    }
+
+    fn entries(&self) -> impl Iterator<Item = &FaultedChunk> {
+        self.buckets.iter().flat_map(|bucket| {
+            std::iter::successors(bucket.get(), |entry| entry.next.get()).map(|entry| &**entry)
+        })
+    }
+
+    fn into_chunks(self) -> Vec<(ChunkPosition, Option<Chunk>)> {
+        let mut chunks = Vec::with_capacity(self.len.into_inner());
+        for bucket in *self.buckets {
+            let mut next = bucket.into_inner();
+            while let Some(entry) = next {
+                let FaultedChunk {
+                    position,
+                    chunk,
+                    next: rest,
+                } = *entry;
+                chunks.push((position, chunk));
+                next = rest.into_inner();
+            }
+        }
+        chunks
+    }
+}
+
+impl Clone for FaultedChunks {
+    fn clone(&self) -> Self {
+        let clone = Self::default();
+        for entry in self.entries() {
+            clone.get_or_load(entry.position, || entry.chunk.clone());
+        }
+        clone
+    }
+}
+
+impl Map {
+    /// Backs the map with a chunk source. Chunks already in memory count as modified, they are
+    /// written to the source when evicted or flushed. The cache keeps at least one chunk, so
+    /// the chunk being accessed is never evicted before it is used.
+    ///
+    /// Every accessor faults missing chunks in from the source. The ones borrowing the map
+    /// immutably (`chunk_state`, `get`, `get_voxel`, `get_distance`), which the renderer
+    /// threads share, set the chunks they load aside without distance maps. Those join the
+    /// cache, and count towards its capacity, on the next mutable access. `fault_in` and
+    /// `load_area` also report load errors and compute distance maps right away.
+    pub fn set_chunk_source<S: ChunkSource + 'static>(&mut self, source: S, capacity: usize) {
+        let mut cache = ChunkCache {
+            source: Arc::new(Mutex::new(source)),
+            capacity: capacity.max(1),
+            last_used: HashMap::new(),
+            by_age: BTreeMap::new(),
+            clock: 0,
+            modified: HashSet::new(),
+            unavailable: HashSet::new(),
+            faulted: FaultedChunks::default(),
+        };
+        for position in self.chunks.keys().chain(&self.empty_chunks) {
+            cache.modified.insert(*position);
+        }
+        for position in self.chunks.keys() {
+            cache.touch(*position);
+        }
+        self.chunk_cache = Some(cache);
+        // Nothing can be lost, every chunk is written back on eviction.
+        let _ = self.evict_chunks();
+    }
+
+    /// Opens a world saved by `save_regions`, loading chunks lazily and keeping at most
+    /// `capacity` of them in memory.
+    pub fn open_regions(directory: &Path, capacity: usize) -> Result<Map, WorldFileError> {
+        let mut map = Map::load(&directory.join(LEVEL_FILE))?;
+        map.set_chunk_source(RegionStore::open(directory)?, capacity);
+        Ok(map)
+    }
+
+    /// Saves the map as region files in a directory. A map without a chunk source gets the
+    /// directory as its source first, a map with one writes its modified chunks back to it.
+    pub fn save_regions(&mut self, directory: &Path) -> Result<(), WorldFileError> {
+        if self.chunk_cache.is_none() {
+            self.set_chunk_source(RegionStore::open(directory)?, usize::MAX);
+        }
+        self.flush_chunks()?;
+        self.save_level(&directory.join(LEVEL_FILE))
+    }
+
+    /// Writes every chunk modified since it was loaded back to the chunk source.
+    pub fn flush_chunks(&mut self) -> Result<(), WorldFileError> {
+        let Some(cache) = &mut self.chunk_cache else {
+            return Ok(());
+        };
+        let mut modified: Vec<_> = cache.modified.iter().copied().collect();
+        modified.sort();
+        let mut source = cache.source.lock().unwrap();
+        for position in modified {
+            if let Some(chunk) = self.chunks.get(&position) {
+                source.store_chunk(chunk)?;
+            } else if self.empty_chunks.contains(&position) {
+                source.store_chunk(&Chunk::new(position))?;
+            }
+            cache.modified.remove(&position);
+        }
+        source.flush()
+    }
+
+    /// Loads the chunk containing the position from the chunk source if it is not in memory,
+    /// and marks it as recently used.
+    pub fn fault_in(&mut self, x: i32, y: i32, z: i32) -> Result<ChunkState<'_>, WorldFileError> {
+        let position = (x.div_euclid(16), y.div_euclid(16), z.div_euclid(16));
+        if let Some(cache) = &mut self.chunk_cache {
+            cache.unavailable.remove(&position);
+        }
+        self.try_fault_in_chunk(position)?;
+        self.refresh_distance_maps();
+        self.evict_chunks()?;
+        Ok(self.chunk_state(x, y, z))
+    }
+
+    /// Faults in every chunk overlapping the box between two voxels, both included.
+    pub fn load_area(
+        &mut self,
+        min: Vector3<i32>,
+        max: Vector3<i32>,
+    ) -> Result<(), WorldFileError> {
+        let first_chunk = min.inf(&max).map(|v| v.div_euclid(16));
+        let last_chunk = min.sup(&max).map(|v| v.div_euclid(16));
+        for z in first_chunk.z..=last_chunk.z {
+            for y in first_chunk.y..=last_chunk.y {
+                for x in first_chunk.x..=last_chunk.x {
+                    self.try_fault_in_chunk((x, y, z))?;
+                }
+            }
+        }
+        self.refresh_distance_maps();
+        self.evict_chunks()
+    }
+
+    /// Faults a chunk in on behalf of an accessor that cannot report errors. A chunk that
+    /// fails to load is treated as not stored until `fault_in` is called for it.
+    pub(crate) fn fault_in_chunk(&mut self, position: ChunkPosition) {
+        if self.try_fault_in_chunk(position).is_err() {
+            if let Some(cache) = &mut self.chunk_cache {
+                cache.unavailable.insert(position);
+            }
+        }
+    }
+
+    /// Records that a chunk was changed, so it gets written back to the chunk source.
+    pub(crate) fn mark_chunk_modified(&mut self, position: ChunkPosition) {
+        self.absorb_faulted_chunks();
+        if let Some(cache) = &mut self.chunk_cache {
+            cache.modified.insert(position);
+            cache.unavailable.remove(&position);
+            if self.chunks.contains_key(&position) {
+                cache.touch(position);
+            }
+        }
+    }
+
+    /// Writes back and drops the least recently used chunks while there are more than the
+    /// cache capacity. A chunk that cannot be written back stays in memory.
+    pub(crate) fn evict_chunks(&mut self) -> Result<(), WorldFileError> {
+        self.absorb_faulted_chunks();
+        let Some(cache) = &mut self.chunk_cache else {
+            return Ok(());
+        };
+        while self.chunks.len() > cache.capacity {
+            let Some((_, position)) = cache.by_age.pop_first() else {
+                break;
+            };
+            cache.last_used.remove(&position);
+            let Some(chunk) = self.chunks.get(&position) else {
+                continue;
+            };
+            if cache.modified.contains(&position) {
+                let stored = cache.source.lock().unwrap().store_chunk(chunk);
+                if let Err(e) = stored {
+                    cache.touch(position);
+                    return Err(e);
+                }
+                cache.modified.remove(&position);
+            }
+            self.chunks.remove(&position);
+        }
+        Ok(())
+    }
+
+    fn try_fault_in_chunk(&mut self, position: ChunkPosition) -> Result<(), WorldFileError> {
+        self.absorb_faulted_chunks();
+        let Some(cache) = &mut self.chunk_cache else {
+            return Ok(());
+        };
+        if self.chunks.contains_key(&position) {
+            cache.touch(position);
+            return Ok(());
+        }
+        if self.empty_chunks.contains(&position) || cache.unavailable.contains(&position) {
+            return Ok(());
+        }
+
+        let chunk = cache
+            .source
+            .lock()
+            .unwrap()
+            .load_chunk(position, &self.materials)?;
+        match chunk {
+            None => {
+                cache.unavailable.insert(position);
+            }
+            Some(chunk) => self.insert_loaded_chunk(chunk),
+        }
+        Ok(())
+    }
+
+    /// Faults a chunk that is neither resident nor known to be empty in on behalf of a reader
+    /// holding a shared reference. It is set aside until the next mutable access.
+    pub(crate) fn fault_in_shared(&self, position: ChunkPosition) -> ChunkState<'_> {
+        let Some(cache) = &self.chunk_cache else {
+            return ChunkState::Unloaded;
+        };
+        if cache.unavailable.contains(&position) {
+            return ChunkState::Unloaded;
+        }
+        let chunk = cache.faulted.get_or_load(position, || {
+            let mut source = cache.source.lock().unwrap();
+            source.load_chunk(position, &self.materials).ok().flatten()
+        });
+        match chunk {
+            None => ChunkState::Unloaded,
+            Some(chunk) if chunk.is_empty() => ChunkState::Empty,
+            Some(chunk) => ChunkState::Loaded(chunk),
+        }
+    }
+
+    /// State of a chunk as distance maps see it, without loading anything. Chunks shared
+    /// readers faulted in are used as they are. Other chunks that are not in memory are
+    /// `Unloaded` if the chunk source may have voxels for them, and `Empty` otherwise, as
+    /// they are in maps without a chunk source.
+    pub(crate) fn distance_chunk_state(&self, x: i32, y: i32, z: i32) -> ChunkState<'_> {
+        let state = self.resident_chunk_state(x, y, z);
+        let ChunkState::Unloaded = state else {
+            return state;
+        };
+        let Some(cache) = &self.chunk_cache else {
+            return ChunkState::Empty;
+        };
+        let position = (x.div_euclid(16), y.div_euclid(16), z.div_euclid(16));
+        if cache.unavailable.contains(&position) {
+            return ChunkState::Empty;
+        }
+        match cache.faulted.get(position) {
+            Some(Some(chunk)) if !chunk.is_empty() => ChunkState::Loaded(chunk),
+            Some(_) => ChunkState::Empty,
+            None if cache.source.lock().unwrap().may_hold_voxels(position) => ChunkState::Unloaded,
+            None => ChunkState::Empty,
+        }
+    }
+
+    /// Moves the chunks faulted in by shared readers into the map, as if a mutable accessor
+    /// had loaded them. Chunks that became resident meanwhile are newer and are kept.
+    fn absorb_faulted_chunks(&mut self) {
+        let Some(cache) = &mut self.chunk_cache else {
+            return;
+        };
+        if *cache.faulted.len.get_mut() == 0 {
+            return;
+        }
+        let faulted = std::mem::take(&mut cache.faulted);
+        for (position, chunk) in faulted.into_chunks() {
+            if self.chunks.contains_key(&position) || self.empty_chunks.contains(&position) {
+                continue;
+            }
+            match chunk {
+                Some(chunk) => self.insert_loaded_chunk(chunk),
+                None => {
+                    if let Some(cache) = &mut self.chunk_cache {
+                        cache.unavailable.insert(position);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Adds a chunk that was loaded or generated outside the map, as unmodified.
+    pub(crate) fn insert_loaded_chunk(&mut self, chunk: Chunk) {
+        let position = chunk.position;
+        if chunk.is_empty() {
+            self.chunks.remove(&position);
+            self.empty_chunks.insert(position);
+        } else {
+            if let Some(cache) = &mut self.chunk_cache {
+                cache.touch(position);
+            }
+            self.empty_chunks.remove(&position);
+            self.chunks.insert(position, chunk);
+        }
+        // Neighbors computed their distances without this chunk, or as if it were solid.
+        let origin = Vector3::new(position.0, position.1, position.2) * 16;
+        self.mark_distances_dirty(origin, origin + Vector3::repeat(15));
+    }
+
+    /// The chunk source backing the map, if any.
+    pub(crate) fn chunk_source(&self) -> Option<Arc<Mutex<dyn ChunkSource>>> {
+        self.chunk_cache
+            .as_ref()
+            .map(|cache| Arc::clone(&cache.source))
+    }
+
+    /// Drops a chunk from memory, writing it back to the chunk source first if it was
+    /// modified. Without a chunk source its changes are lost.
+    pub(crate) fn unload_chunk(&mut self, position: ChunkPosition) -> Result<(), WorldFileError> {
+        self.absorb_faulted_chunks();
+        if let Some(cache) = &mut self.chunk_cache {
+            if cache.modified.contains(&position) {
+                let mut source = cache.source.lock().unwrap();
+                if let Some(chunk) = self.chunks.get(&position) {
+                    source.store_chunk(chunk)?;
+                } else if self.empty_chunks.contains(&position) {
+                    source.store_chunk(&Chunk::new(position))?;
+                }
+                drop(source);
+                cache.modified.remove(&position);
+            }
+            if let Some(used) = cache.last_used.remove(&position) {
+                cache.by_age.remove(&used);
+            }
+            cache.unavailable.remove(&position);
+        }
+        self.chunks.remove(&position);
+        self.empty_chunks.remove(&position);
+        Ok(())
+    }
+}
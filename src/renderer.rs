@@ -1,14 +1,29 @@
-use crate::map::Map;
+use crate::map::{ChunkState, Map};
 use crate::voxel::Voxel;
+use image::RgbaImage;
 use nalgebra::Vector3;
-use pixels::Pixels;
 use rayon::scope;
 
+const MAX_STEPS: i32 = 256;
+const MAX_BOUNCES: u32 = 2;
+const SURFACE_BIAS: f32 = 1e-3;
+const AO_MIN_LIGHT: f32 = 0.35;
+
 pub struct Renderer {
     pub map: Map,
     pub width: u32,
     pub height: u32,
     pub num_threads: usize,
+    /// Unit vector pointing towards the sun.
+    pub sun_direction: Vector3<f32>,
+    /// Fraction of the albedo visible on faces turned away from the sun.
+    pub ambient: f32,
+    /// Casts a second ray towards the sun from every hit to darken occluded surfaces.
+    pub shadows: bool,
+    /// Darkens faces near concave edges and corners based on neighboring voxels.
+    pub ambient_occlusion: bool,
+    /// Color of rays that hit nothing, which distant surfaces fade into.
+    pub sky_color: Vector3<u8>,
 }
 
 impl Renderer {
@@ -18,6 +33,24 @@ impl Renderer {
             width,
             height,
             num_threads,
+            sun_direction: Vector3::new(0.4, 0.8, -0.45).normalize(),
+            ambient: 0.3,
+            shadows: false,
+            ambient_occlusion: false,
+            sky_color: Vector3::zeros(),
+        }
+    }
+
+    /// Points the sun along `direction`, which does not need to be normalized. Directions of
+    /// length zero, or with components that are not finite, are ignored. Returns whether the
+    /// direction was used.
+    pub fn set_sun_direction(&mut self, direction: Vector3<f32>) -> bool {
+        match direction.try_normalize(f32::EPSILON) {
+            Some(direction) if direction.iter().all(|v| v.is_finite()) => {
+                self.sun_direction = direction;
+                true
+            }
+            _ => false,
         }
     }
 
@@ -46,58 +79,48 @@ impl Renderer {
         rotation * ray_direction
     }
 
-    fn set_pixel_color(&self, frame: &mut [u8], x: u32, y: u32, width: u32, color: Vector3<u8>) {
-        let index = (x + y * width) as usize;
+    fn set_pixel_color(&self, row: &mut [u8], x: u32, color: Vector3<u8>) {
+        let index = x as usize;
 
-        frame[index * 4] = color.x;
-        frame[index * 4 + 1] = color.y;
-        frame[index * 4 + 2] = color.z;
-        frame[index * 4 + 3] = 255;
+        row[index * 4] = color.x;
+        row[index * 4 + 1] = color.y;
+        row[index * 4 + 2] = color.z;
+        row[index * 4 + 3] = 255;
     }
 
-    pub fn draw_frame(
+    /// Renders one frame into `buf`, a tightly packed RGBA8 buffer of `width * height` pixels.
+    pub fn render_to_buffer(
         &self,
-        pixels: &mut Pixels,
+        buf: &mut [u8],
         ray_origin: Vector3<f32>,
         rotation_angle: Vector3<f32>,
     ) {
-        let frame = pixels.frame_mut();
         let (width, height) = (self.width, self.height);
+        assert_eq!(
+            buf.len(),
+            width as usize * height as usize * 4,
+            "frame buffer does not match the renderer size"
+        );
         let fov: f32 = 60.0_f32.to_radians();
         let aspect_ratio = width as f32 / height as f32;
 
-        let frame = std::sync::Mutex::new(frame);
+        let num_threads = self.num_threads.max(1);
+        let mut thread_rows: Vec<Vec<(u32, &mut [u8])>> =
+            (0..num_threads).map(|_| Vec::new()).collect();
+        for (y, row) in buf.chunks_mut(width as usize * 4).enumerate() {
+            thread_rows[y % num_threads].push((y as u32, row));
+        }
 
         scope(|s| {
-            for i in 0..self.num_threads {
-                let frame = &frame;
-                let ray_origin = ray_origin.clone();
-                let rotation_angle = rotation_angle.clone();
-
+            for rows in thread_rows {
                 s.spawn(move |_| {
-                    for y in (i as u32..height).step_by(self.num_threads) {
+                    for (y, row) in rows {
                         for x in 0..width {
                             let ray_direction = self
                                 .calc_ray_direction(x, y, fov, aspect_ratio, rotation_angle)
                                 .normalize();
-
-                            let (step_count, voxel) = self.dda(&ray_origin, &ray_direction, 256);
-
-                            let color = {
-                                if let Some(voxel) = voxel
-                                {
-                                    Vector3::new(
-                                        voxel.color.x as f32 * (1.0 - step_count as f32 / 256.0),
-                                        voxel.color.y as f32 * (1.0 - step_count as f32 / 256.0),
-                                        voxel.color.z as f32 * (1.0 - step_count as f32 / 256.0)
-                                    ).map(|v| v as u8)
-                                } else {
-                                    Vector3::new(0, 0, 0)
-                                }
-                            };
-
-                            let mut frame = frame.lock().unwrap();
-                            self.set_pixel_color(&mut frame, x, y, width, color);
+                            let color = self.shade(&ray_origin, &ray_direction);
+                            self.set_pixel_color(row, x, color);
                         }
                     }
                 });
@@ -105,101 +128,330 @@ impl Renderer {
         });
     }
 
+    pub fn render_image(
+        &self,
+        ray_origin: Vector3<f32>,
+        rotation_angle: Vector3<f32>,
+    ) -> RgbaImage {
+        let mut image = RgbaImage::new(self.width, self.height);
+        self.render_to_buffer(&mut image, ray_origin, rotation_angle);
+        image
+    }
+
+    fn shade(&self, ray_origin: &Vector3<f32>, ray_direction: &Vector3<f32>) -> Vector3<u8> {
+        self.trace(ray_origin, ray_direction, MAX_BOUNCES)
+            .map(|v| v.clamp(0.0, 255.0) as u8)
+    }
+
+    /// Returns the color seen along the ray, following at most `bounces` reflected or
+    /// transmitted rays.
+    fn trace(
+        &self,
+        ray_origin: &Vector3<f32>,
+        ray_direction: &Vector3<f32>,
+        bounces: u32,
+    ) -> Vector3<f32> {
+        let sky = self.sky_color.map(|v| v as f32);
+        let Some(hit) = self.dda(ray_origin, ray_direction, MAX_STEPS) else {
+            return sky;
+        };
+        let Some(material) = self.map.materials.get(hit.voxel.material) else {
+            return Vector3::new(255.0, 0.0, 255.0);
+        };
+
+        let mut diffuse = hit.normal.dot(&self.sun_direction).max(0.0);
+        if diffuse > 0.0 && self.shadows && self.in_shadow(&hit) {
+            diffuse = 0.0;
+        }
+        let mut light = self.ambient + (1.0 - self.ambient) * diffuse;
+        if self.ambient_occlusion {
+            light *= AO_MIN_LIGHT + (1.0 - AO_MIN_LIGHT) * self.ambient_occlusion_at(&hit);
+        }
+        let mut color = material.albedo.map(|v| v as f32) * (light + material.emissive);
+
+        if bounces > 0 {
+            let reflectivity = material.reflectivity * (1.0 - material.roughness);
+            if reflectivity > 0.0 {
+                let direction = ray_direction - hit.normal * 2.0 * ray_direction.dot(&hit.normal);
+                let origin = hit.point + hit.normal * SURFACE_BIAS;
+                let reflected = self.trace(&origin, &direction, bounces - 1);
+                color = color.lerp(&reflected, reflectivity);
+            }
+            if material.opacity < 1.0 {
+                let origin = exit_point(&hit, ray_direction);
+                let behind = self.trace(&origin, ray_direction, bounces - 1);
+                color = color.lerp(&behind, 1.0 - material.opacity);
+            }
+        }
+
+        let fog = 1.0 - (hit.distance / MAX_STEPS as f32).min(1.0);
+        sky.lerp(&color, fog)
+    }
+
+    /// Returns whether the sun is occluded as seen from the hit point.
+    pub fn in_shadow(&self, hit: &RayHit) -> bool {
+        // Start just outside the face that was hit so the shadow ray begins in the empty cell
+        // in front of the surface instead of inside the voxel it is leaving.
+        let origin = hit.point + hit.normal * SURFACE_BIAS;
+        self.dda(&origin, &self.sun_direction, MAX_STEPS).is_some()
+    }
+
+    /// Minecraft-style vertex ambient occlusion for the hit face: each face corner is occluded by
+    /// the two side neighbors and the diagonal neighbor in front of the face, and the four corner
+    /// values are interpolated across the face. Returns 1.0 for a fully open face and 0.0 for a
+    /// fully occluded one.
+    pub fn ambient_occlusion_at(&self, hit: &RayHit) -> f32 {
+        let axis = match hit.normal.iamax() {
+            axis if hit.normal[axis] != 0.0 => axis,
+            _ => return 1.0,
+        };
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut front = hit.position;
+        front[axis] += hit.normal[axis] as i32;
+
+        let is_solid = |du: i32, dv: i32| {
+            let mut cell = front;
+            cell[u] += du;
+            cell[v] += dv;
+            self.map
+                .get_voxel(cell.x, cell.y, cell.z)
+                .is_some_and(|voxel| !voxel.is_empty())
+        };
+        let corner = |du: i32, dv: i32| {
+            let side_u = is_solid(du, 0);
+            let side_v = is_solid(0, dv);
+            if side_u && side_v {
+                0.0
+            } else {
+                let occluders = side_u as u8 + side_v as u8 + is_solid(du, dv) as u8;
+                (3 - occluders) as f32 / 3.0
+            }
+        };
+
+        let fu = (hit.point[u] - hit.position[u] as f32).clamp(0.0, 1.0);
+        let fv = (hit.point[v] - hit.position[v] as f32).clamp(0.0, 1.0);
+        let low_v = corner(-1, -1) * (1.0 - fu) + corner(1, -1) * fu;
+        let high_v = corner(-1, 1) * (1.0 - fu) + corner(1, 1) * fu;
+        low_v * (1.0 - fv) + high_v * fv
+    }
+
+    /// Walks the voxel grid along the ray and returns the first solid voxel it enters, skipping
+    /// ahead using the distance maps where possible.
     pub fn dda(
         &self,
         ray_origin: &Vector3<f32>,
         ray_direction: &Vector3<f32>,
         max_step: i32,
-    ) -> (i32, Option<&Voxel>) {
-        let mut grid_pos = ray_origin.map(|v| v.floor());
+    ) -> Option<RayHit<'_>> {
+        let mut traversal = Traversal::new(ray_origin, ray_direction);
+
+        let mut step_count = 0;
+        while step_count < max_step {
+            let (x, y, z) = traversal.cell();
+            // Chunks that are not loaded yet are seen through, rays end in the sky.
+            let chunk = match self.map.chunk_state(x, y, z) {
+                ChunkState::Unloaded | ChunkState::Empty => {
+                    // A ray without a direction never leaves the chunk.
+                    step_count += traversal.skip_chunk()?;
+                    continue;
+                }
+                ChunkState::Loaded(chunk) => chunk,
+            };
+            let (local_x, local_y, local_z) = (
+                x.rem_euclid(16) as u8,
+                y.rem_euclid(16) as u8,
+                z.rem_euclid(16) as u8,
+            );
+
+            let distance = chunk.get_distance(local_x, local_y, local_z) as f32;
+            if distance > 1.0 {
+                let step = (distance / ray_direction.magnitude()).floor() as i32;
+                for _ in 0..step {
+                    traversal.step();
+                    step_count += 1;
+                    if step_count >= max_step {
+                        break;
+                    }
+                }
+            } else {
+                if let Some(voxel) = chunk.get_voxel(local_x, local_y, local_z) {
+                    if !voxel.is_empty() {
+                        return Some(RayHit {
+                            voxel,
+                            position: Vector3::new(x, y, z),
+                            point: ray_origin + ray_direction * traversal.t,
+                            normal: traversal.normal(ray_direction),
+                            distance: traversal.t,
+                            step_count,
+                        });
+                    }
+                }
+
+                traversal.step();
+                step_count += 1;
+            }
+        }
+
+        None
+    }
+}
+
+/// Point just past the far side of the hit voxel along the ray.
+fn exit_point(hit: &RayHit, ray_direction: &Vector3<f32>) -> Vector3<f32> {
+    let mut t_exit = f32::INFINITY;
+    for axis in 0..3 {
+        let bound = if ray_direction[axis] > 0.0 {
+            hit.position[axis] as f32 + 1.0
+        } else if ray_direction[axis] < 0.0 {
+            hit.position[axis] as f32
+        } else {
+            continue;
+        };
+        t_exit = t_exit.min((bound - hit.point[axis]) / ray_direction[axis]);
+    }
+    hit.point + ray_direction * (t_exit + SURFACE_BIAS)
+}
+
+/// The first solid voxel found along a ray.
+#[derive(Debug, Clone, Copy)]
+pub struct RayHit<'a> {
+    pub voxel: &'a Voxel,
+    /// World coordinates of the voxel that was hit.
+    pub position: Vector3<i32>,
+    /// Point where the ray entered the voxel.
+    pub point: Vector3<f32>,
+    /// Normal of the face the ray entered through.
+    pub normal: Vector3<f32>,
+    /// Distance along the (normalized) ray to the entry point.
+    pub distance: f32,
+    pub step_count: i32,
+}
+
+/// Incremental state of an Amanatides & Woo grid traversal.
+struct Traversal {
+    grid_pos: Vector3<f32>,
+    grid_step: Vector3<f32>,
+    t_max: Vector3<f32>,
+    t_delta: Vector3<f32>,
+    /// Axis crossed by the last step, `None` while still in the starting cell.
+    axis: Option<usize>,
+    /// Ray parameter at which the current cell was entered.
+    t: f32,
+}
+
+impl Traversal {
+    fn new(ray_origin: &Vector3<f32>, ray_direction: &Vector3<f32>) -> Self {
+        let grid_pos = ray_origin.map(|v| v.floor());
         let grid_step = ray_direction.map(|v| v.signum());
-        let mut t_max = Vector3::new(
+        let t_max = Vector3::new(
             if ray_direction.x > 0.0 {
                 ((grid_pos.x + 1.0) - ray_origin.x) / ray_direction.x
-            } else {
+            } else if ray_direction.x < 0.0 {
                 (grid_pos.x - ray_origin.x) / ray_direction.x
+            } else {
+                f32::INFINITY
             },
             if ray_direction.y > 0.0 {
                 ((grid_pos.y + 1.0) - ray_origin.y) / ray_direction.y
-            } else {
+            } else if ray_direction.y < 0.0 {
                 (grid_pos.y - ray_origin.y) / ray_direction.y
+            } else {
+                f32::INFINITY
             },
             if ray_direction.z > 0.0 {
                 ((grid_pos.z + 1.0) - ray_origin.z) / ray_direction.z
-            } else {
+            } else if ray_direction.z < 0.0 {
                 (grid_pos.z - ray_origin.z) / ray_direction.z
+            } else {
+                f32::INFINITY
             },
         );
         let t_delta = grid_step.component_div(ray_direction).map(|v| v.abs());
 
-        let mut step_count = 0;
-        while step_count < max_step {
-            if !self
-                .map
-                .is_within_bounds(grid_pos.x as i32, grid_pos.y as i32, grid_pos.z as i32)
-            {
-                break;
-            }
+        Self {
+            grid_pos,
+            grid_step,
+            t_max,
+            t_delta,
+            axis: None,
+            t: 0.0,
+        }
+    }
 
-            let distance =
-                self.map
-                    .get_distance(grid_pos.x as i32, grid_pos.y as i32, grid_pos.z as i32)
-                    as f32;
-            if distance > 1.0 {
-                let step = (distance / ray_direction.magnitude()).floor() as i32;
-                for _ in 0..step {
-                    let step_axis = t_max.imin();
-                    match step_axis {
-                        0 => {
-                            t_max.x += t_delta.x;
-                            grid_pos.x += grid_step.x;
-                        }
-                        1 => {
-                            t_max.y += t_delta.y;
-                            grid_pos.y += grid_step.y;
-                        }
-                        2 => {
-                            t_max.z += t_delta.z;
-                            grid_pos.z += grid_step.z;
-                        }
-                        _ => unreachable!(),
-                    }
-                    step_count += 1;
-                    if step_count >= max_step {
-                        break;
-                    }
-                }
-            } else {
-                if let Some(voxel) =
-                    self.map
-                        .get_voxel(grid_pos.x as i32, grid_pos.y as i32, grid_pos.z as i32)
-                {
-                    if !voxel.is_empty {
-                        return (step_count, Some(voxel));
-                    }
-                }
+    fn cell(&self) -> (i32, i32, i32) {
+        (
+            self.grid_pos.x as i32,
+            self.grid_pos.y as i32,
+            self.grid_pos.z as i32,
+        )
+    }
 
-                let step_axis = t_max.imin();
-                match step_axis {
-                    0 => {
-                        t_max.x += t_delta.x;
-                        grid_pos.x += grid_step.x;
-                    }
-                    1 => {
-                        t_max.y += t_delta.y;
-                        grid_pos.y += grid_step.y;
-                    }
-                    2 => {
-                        t_max.z += t_delta.z;
-                        grid_pos.z += grid_step.z;
-                    }
-                    _ => unreachable!(),
-                }
+    fn step(&mut self) {
+        let axis = self.t_max.imin();
+        self.t = self.t_max[axis];
+        self.t_max[axis] += self.t_delta[axis];
+        self.grid_pos[axis] += self.grid_step[axis];
+        self.axis = Some(axis);
+    }
 
-                step_count += 1;
+    /// Moves to the first cell outside the current chunk in a single jump, as if `step` had
+    /// been called for every cell in between. Returns the number of cells crossed, or `None`
+    /// if the ray never leaves the chunk.
+    fn skip_chunk(&mut self) -> Option<i32> {
+        let mut cells_left = Vector3::<i32>::zeros();
+        let mut exit_axis = 0;
+        let mut t_exit = f32::INFINITY;
+        for axis in 0..3 {
+            if self.t_delta[axis].is_infinite() {
+                continue;
+            }
+            let local = (self.grid_pos[axis] as i32).rem_euclid(16);
+            cells_left[axis] = if self.grid_step[axis] > 0.0 {
+                15 - local
+            } else {
+                local
+            };
+            let t = self.t_max[axis] + cells_left[axis] as f32 * self.t_delta[axis];
+            if t < t_exit {
+                t_exit = t;
+                exit_axis = axis;
+            }
+        }
+        if !t_exit.is_finite() {
+            return None;
+        }
+
+        let mut crossed = 0;
+        for axis in 0..3 {
+            // The ray never crosses this axis, and infinity times zero steps would be NaN.
+            if self.t_delta[axis].is_infinite() {
+                continue;
             }
+            let steps = if axis == exit_axis {
+                cells_left[axis] + 1
+            } else if self.t_max[axis] > t_exit {
+                0
+            } else {
+                let steps = ((t_exit - self.t_max[axis]) / self.t_delta[axis]) as i32 + 1;
+                steps.min(cells_left[axis])
+            };
+            self.t_max[axis] += steps as f32 * self.t_delta[axis];
+            self.grid_pos[axis] += steps as f32 * self.grid_step[axis];
+            crossed += steps;
         }
+        self.t = t_exit;
+        self.axis = Some(exit_axis);
+        Some(crossed)
+    }
 
-        (step_count, None)
+    fn normal(&self, ray_direction: &Vector3<f32>) -> Vector3<f32> {
+        match self.axis {
+            Some(axis) => {
+                let mut normal = Vector3::zeros();
+                normal[axis] = -self.grid_step[axis];
+                normal
+            }
+            None => -ray_direction.normalize(),
+        }
     }
 }
@@ -0,0 +1,77 @@
+use rand::{thread_rng, RngCore};
+use std::fmt;
+use std::str::FromStr;
+
+/// The single source of randomness for a world. Every generator derives its own sub-seed from
+/// it by name, so the same `WorldSeed` always recreates the same world.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WorldSeed(pub u64);
+
+impl WorldSeed {
+    pub fn new(value: u64) -> Self {
+        Self(value)
+    }
+
+    pub fn random() -> Self {
+        Self(thread_rng().next_u64())
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+
+    /// Derives an independent seed for the named consumer, e.g. `"caves"`. Streams with
+    /// different names are uncorrelated, and adding a new one never changes existing ones.
+    pub fn derive(&self, stream: &str) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in stream.bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        splitmix64(self.0 ^ hash)
+    }
+
+    pub fn derive_u32(&self, stream: &str) -> u32 {
+        (self.derive(stream) >> 32) as u32
+    }
+
+    /// Hashes a position within the named stream. The value only depends on the seed, the
+    /// stream and the position, so it can decide what goes where in any generation order.
+    pub fn hash_position(&self, stream: &str, position: (i32, i32, i32)) -> u64 {
+        let mut hash = self.derive(stream);
+        for coordinate in [position.0, position.1, position.2] {
+            hash = splitmix64(hash ^ coordinate as u32 as u64);
+        }
+        hash
+    }
+
+    /// Like `hash_position`, as a number uniformly distributed in [0, 1).
+    pub fn random_at(&self, stream: &str, position: (i32, i32, i32)) -> f64 {
+        (self.hash_position(stream, position) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl fmt::Display for WorldSeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for WorldSeed {
+    type Err = std::convert::Infallible;
+
+    /// Numbers are used as-is, any other text is hashed so worlds can be named.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().parse::<u64>() {
+            Ok(value) => Ok(Self(value)),
+            Err(_) => Ok(Self(WorldSeed(0).derive(s))),
+        }
+    }
+}
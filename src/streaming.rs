@@ -0,0 +1,228 @@
+use crate::chunk::{Chunk, ChunkPosition};
+use crate::generator::{PerlinCaves, WorldGenerator};
+use crate::map::Map;
+use crate::seed::WorldSeed;
+use crate::world_file::WorldFileError;
+use nalgebra::Vector3;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::collections::HashSet;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+
+/// Default radius, in chunks, of the area kept loaded around the point of view.
+pub const DEFAULT_VIEW_RADIUS: i32 = 4;
+
+/// What a call to `ChunkStreamer::update` did.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StreamStatus {
+    /// Chunks handed to the workers.
+    pub requested: usize,
+    /// Chunks added to the map.
+    pub arrived: usize,
+    pub evicted: usize,
+    /// Chunks still being loaded or generated.
+    pub pending: usize,
+    /// Chunks within the view radius that are not in the map yet.
+    pub missing: usize,
+}
+
+/// Keeps the chunks around a moving point of view in the map. Missing chunks are loaded
+/// from the map's chunk source, or generated, on a thread pool of the streamer, and added to
+/// the map by `update` once they are ready, so rendering never waits for them. The renderer
+/// runs on the global rayon pool, where a backlog of chunks would hold frames up. Chunks that get too
+/// far are evicted. Modified chunks are written back to the chunk source first, without one
+/// their changes are lost. Voxel edits into chunks that have not arrived yet are dropped, see
+/// `Map::set_voxels`.
+///
+/// The distance maps around an arriving chunk are updated as it is added, so they account
+/// for every neighbor that is already there.
+pub struct ChunkStreamer {
+    /// Chunks whose center is within this many chunks of the point of view are loaded.
+    pub view_radius: i32,
+    /// Chunks further than this many chunks are evicted. Larger than the view radius so
+    /// chunks at the edge are not reloaded over and over.
+    pub unload_radius: i32,
+    /// Radius of the distance maps of a map streamed without any.
+    pub distance_radius: i32,
+    /// Most chunks being loaded or generated at once.
+    pub max_pending: usize,
+    /// Most chunks added to the map by one update, which bounds the time spent updating
+    /// distance maps.
+    pub max_arrivals: usize,
+    pending: HashSet<ChunkPosition>,
+    arrived: Vec<Chunk>,
+    sender: Sender<Chunk>,
+    receiver: Receiver<Chunk>,
+    workers: ThreadPool,
+    generator: Option<Arc<dyn WorldGenerator>>,
+    /// Caves of the map seed, used when no generator is set.
+    caves: Option<(WorldSeed, Arc<dyn WorldGenerator>)>,
+}
+
+impl ChunkStreamer {
+    pub fn new(view_radius: i32) -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            view_radius,
+            unload_radius: view_radius + 2,
+            distance_radius: 4,
+            max_pending: 2 * num_cpus::get(),
+            max_arrivals: 32,
+            pending: HashSet::new(),
+            arrived: Vec::new(),
+            sender,
+            receiver,
+            workers: ThreadPoolBuilder::new()
+                .num_threads(num_cpus::get())
+                .thread_name(|index| format!("chunk-streamer-{}", index))
+                .build()
+                .expect("chunk streamer threads can be spawned"),
+            generator: None,
+            caves: None,
+        }
+    }
+
+    /// Generates the missing chunks with `generator` instead of the Perlin caves of the map
+    /// seed. Chunks already in the map are kept.
+    pub fn set_generator(&mut self, generator: Arc<dyn WorldGenerator>) {
+        self.generator = Some(generator);
+    }
+
+    /// Adds the chunks that are ready, evicts the ones that are too far from `center` and
+    /// requests the closest missing ones. Returns without waiting for any chunk.
+    pub fn update(
+        &mut self,
+        map: &mut Map,
+        center: Vector3<f32>,
+    ) -> Result<StreamStatus, WorldFileError> {
+        let center = center.map(|v| (v.floor() as i32).div_euclid(16));
+        let center = (center.x, center.y, center.z);
+        let mut status = StreamStatus::default();
+        if map.distance_radius <= 0 {
+            map.distance_radius = self.distance_radius;
+        }
+        map.streamed = true;
+
+        self.arrived.extend(self.receiver.try_iter());
+        let count = self.arrived.len().min(self.max_arrivals);
+        for chunk in self.arrived.drain(..count) {
+            let position = chunk.position;
+            self.pending.remove(&position);
+            if distance_squared(position, center) > self.unload_radius.pow(2)
+                || map.is_within_bounds(position.0 * 16, position.1 * 16, position.2 * 16)
+            {
+                continue;
+            }
+            map.insert_loaded_chunk(chunk);
+            status.arrived += 1;
+        }
+        map.refresh_distance_maps();
+
+        let mut result = Ok(());
+        let far: Vec<_> = map
+            .chunks
+            .keys()
+            .chain(&map.empty_chunks)
+            .filter(|position| distance_squared(**position, center) > self.unload_radius.pow(2))
+            .copied()
+            .collect();
+        for position in far {
+            match map.unload_chunk(position) {
+                Ok(()) => status.evicted += 1,
+                Err(e) => result = result.and(Err(e)),
+            }
+        }
+
+        let missing = self.missing_chunks(map, center);
+        status.missing = missing.len();
+        let free = self.max_pending.saturating_sub(self.pending.len());
+        let requests: Vec<_> = missing
+            .into_iter()
+            .filter(|position| !self.pending.contains(position))
+            .take(free)
+            .collect();
+        if !requests.is_empty() {
+            let generator = self.generator(map);
+            let materials = Arc::new(map.materials.clone());
+            let source = map.chunk_source();
+            for position in requests {
+                let (generator, materials) = (Arc::clone(&generator), Arc::clone(&materials));
+                let (source, sender) = (source.clone(), self.sender.clone());
+                self.workers.spawn(move || {
+                    // A chunk that fails to load is treated as not stored, like `fault_in`.
+                    let stored = source
+                        .and_then(|source| {
+                            source.lock().unwrap().load_chunk(position, &materials).ok()
+                        })
+                        .flatten();
+                    let chunk = stored.unwrap_or_else(|| {
+                        let mut chunk = Chunk::new(position);
+                        generator.generate_chunk(position, &mut chunk);
+                        chunk
+                    });
+                    // The streamer may be gone, the chunk is not needed then.
+                    let _ = sender.send(chunk);
+                });
+                self.pending.insert(position);
+                status.requested += 1;
+            }
+        }
+        status.pending = self.pending.len();
+        result.map(|()| status)
+    }
+
+    /// Updates until every chunk within the view radius of `center` is in the map, blocking
+    /// while they are loaded or generated.
+    pub fn load_all(&mut self, map: &mut Map, center: Vector3<f32>) -> Result<(), WorldFileError> {
+        loop {
+            let status = self.update(map, center)?;
+            if status.missing == 0 {
+                return Ok(());
+            }
+            if self.arrived.is_empty() && !self.pending.is_empty() {
+                if let Ok(chunk) = self.receiver.recv() {
+                    self.arrived.push(chunk);
+                }
+            }
+        }
+    }
+
+    /// Chunks within the view radius that are not in the map, closest first.
+    fn missing_chunks(&self, map: &Map, center: ChunkPosition) -> Vec<ChunkPosition> {
+        let radius = self.view_radius.max(0);
+        let mut missing = Vec::new();
+        for z in center.2 - radius..=center.2 + radius {
+            for y in center.1 - radius..=center.1 + radius {
+                for x in center.0 - radius..=center.0 + radius {
+                    let position = (x, y, z);
+                    if distance_squared(position, center) <= radius.pow(2)
+                        && !map.is_within_bounds(x * 16, y * 16, z * 16)
+                    {
+                        missing.push(position);
+                    }
+                }
+            }
+        }
+        missing.sort_by_key(|position| distance_squared(*position, center));
+        missing
+    }
+
+    fn generator(&mut self, map: &Map) -> Arc<dyn WorldGenerator> {
+        if let Some(generator) = &self.generator {
+            return Arc::clone(generator);
+        }
+        match &self.caves {
+            Some((seed, caves)) if *seed == map.seed => Arc::clone(caves),
+            _ => {
+                let caves: Arc<dyn WorldGenerator> =
+                    Arc::new(PerlinCaves::new(map.seed, &map.materials));
+                self.caves = Some((map.seed, Arc::clone(&caves)));
+                caves
+            }
+        }
+    }
+}
+
+fn distance_squared(a: ChunkPosition, b: ChunkPosition) -> i32 {
+    (a.0 - b.0).pow(2) + (a.1 - b.1).pow(2) + (a.2 - b.2).pow(2)
+}
@@ -0,0 +1,760 @@
+//! MagicaVoxel `.vox` files.
+//!
+//! MagicaVoxel is z-up while the map is y-up: a voxel at `(x, y, z)` in a `.vox` scene lands at
+//! `(x, z, -1 - y)` in the map, a rotation that keeps models from being mirrored.
+
+use crate::map::{Map, VoxelPosition};
+use crate::material::{Material, MaterialError, MaterialId};
+use crate::voxel::Voxel;
+use nalgebra::{Matrix3, Vector3};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Nesting limit of scene nodes.
+const MAX_SCENE_DEPTH: usize = 64;
+
+/// Most nodes visited while flattening a scene graph. Nodes referenced several times are
+/// visited once per reference, so without a limit a few groups listing the same child twice
+/// expand exponentially.
+const MAX_SCENE_NODES: usize = 1 << 16;
+
+/// Largest model MagicaVoxel accepts along each axis.
+pub const MAX_MODEL_SIZE: i32 = 256;
+
+/// A palette color, as red, green, blue and alpha.
+pub type VoxColor = [u8; 4];
+
+#[derive(Debug)]
+pub enum VoxError {
+    Io(std::io::Error),
+    Format(String),
+    Material(MaterialError),
+}
+
+impl fmt::Display for VoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VoxError::Io(e) => write!(f, "could not access vox file: {}", e),
+            VoxError::Format(reason) => write!(f, "invalid vox file: {}", reason),
+            VoxError::Material(e) => write!(f, "could not register vox color: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for VoxError {}
+
+impl From<std::io::Error> for VoxError {
+    fn from(e: std::io::Error) -> Self {
+        VoxError::Io(e)
+    }
+}
+
+impl From<MaterialError> for VoxError {
+    fn from(e: MaterialError) -> Self {
+        VoxError::Material(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoxModel {
+    pub size: Vector3<u32>,
+    /// Position in the model and palette index, from 1 to 255, of every solid voxel.
+    pub voxels: Vec<(Vector3<u8>, u8)>,
+}
+
+/// A model placed in the scene. A model voxel `v` is at `rotation * (v - size / 2) +
+/// translation`, the division rounding down like MagicaVoxel does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoxInstance {
+    pub model: usize,
+    pub rotation: Matrix3<i32>,
+    pub translation: Vector3<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoxFile {
+    pub models: Vec<VoxModel>,
+    /// Colors by palette index. Index 0 is unused, it stands for empty voxels.
+    pub palette: [VoxColor; 256],
+    pub instances: Vec<VoxInstance>,
+}
+
+impl VoxFile {
+    pub fn load(path: &Path) -> Result<Self, VoxError> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, VoxError> {
+        let mut reader = Reader { bytes };
+        if reader.take(4)? != b"VOX " {
+            return Err(VoxError::Format("missing VOX header".to_string()));
+        }
+        reader.i32()?;
+        let (id, _, mut children) = reader.chunk()?;
+        if id != b"MAIN" {
+            return Err(VoxError::Format("missing MAIN chunk".to_string()));
+        }
+
+        let mut file = VoxFile {
+            models: Vec::new(),
+            palette: default_palette(),
+            instances: Vec::new(),
+        };
+        let mut size = None;
+        let mut nodes = HashMap::new();
+        while !children.bytes.is_empty() {
+            let (id, mut content, _) = children.chunk()?;
+            match id {
+                b"SIZE" => {
+                    size = Some(Vector3::new(content.u32()?, content.u32()?, content.u32()?))
+                }
+                b"XYZI" => {
+                    let size = size
+                        .take()
+                        .ok_or_else(|| VoxError::Format("XYZI chunk without SIZE".to_string()))?;
+                    let count = content.u32()? as usize;
+                    let mut voxels = Vec::with_capacity(count.min(content.bytes.len() / 4));
+                    for _ in 0..count {
+                        let voxel = content.take(4)?;
+                        let position = Vector3::new(voxel[0], voxel[1], voxel[2]);
+                        if (0..3).any(|axis| position[axis] as u32 >= size[axis]) {
+                            return Err(VoxError::Format(format!(
+                                "voxel {:?} outside of model of size {:?}",
+                                position, size
+                            )));
+                        }
+                        if voxel[3] != 0 {
+                            voxels.push((position, voxel[3]));
+                        }
+                    }
+                    file.models.push(VoxModel { size, voxels });
+                }
+                b"RGBA" => {
+                    // The chunk lists the colors of indices 1 to 255, then an unused entry.
+                    for index in 1..256 {
+                        let color = content.take(4)?;
+                        file.palette[index] = [color[0], color[1], color[2], color[3]];
+                    }
+                }
+                b"nTRN" => {
+                    let id = content.i32()?;
+                    content.dictionary()?;
+                    let child = content.i32()?;
+                    content.i32()?;
+                    content.i32()?;
+                    let frames = content.i32()?;
+                    let mut rotation = Matrix3::identity();
+                    let mut translation = Vector3::zeros();
+                    if frames > 0 {
+                        let frame = content.dictionary()?;
+                        if let Some(value) = frame.get("_r") {
+                            rotation = parse_rotation(value)?;
+                        }
+                        if let Some(value) = frame.get("_t") {
+                            translation = parse_translation(value)?;
+                        }
+                    }
+                    nodes.insert(
+                        id,
+                        Node::Transform {
+                            child,
+                            rotation,
+                            translation,
+                        },
+                    );
+                }
+                b"nGRP" => {
+                    let id = content.i32()?;
+                    content.dictionary()?;
+                    let count = content.u32()?;
+                    let children = (0..count)
+                        .map(|_| content.i32())
+                        .collect::<Result<_, _>>()?;
+                    nodes.insert(id, Node::Group { children });
+                }
+                b"nSHP" => {
+                    let id = content.i32()?;
+                    content.dictionary()?;
+                    let count = content.u32()?;
+                    let mut models = Vec::new();
+                    for _ in 0..count {
+                        models.push(content.i32()?);
+                        content.dictionary()?;
+                    }
+                    nodes.insert(id, Node::Shape { models });
+                }
+                // Materials, layers, cameras and notes do not affect the voxels.
+                _ => {}
+            }
+        }
+
+        if nodes.is_empty() {
+            // Files without a scene graph place every model at the origin.
+            for (index, model) in file.models.iter().enumerate() {
+                file.instances.push(VoxInstance {
+                    model: index,
+                    rotation: Matrix3::identity(),
+                    translation: model.size.map(|v| (v / 2) as i32),
+                });
+            }
+        } else {
+            let root = (0, Matrix3::identity(), Vector3::zeros(), 0);
+            let mut stack = vec![root];
+            // Nodes from the root to the current one. The walk is depth first, so the
+            // ancestors of a node are the last nodes visited at every lower depth.
+            let mut path = Vec::new();
+            let mut visited = 0;
+            while let Some((id, rotation, translation, depth)) = stack.pop() {
+                if depth > MAX_SCENE_DEPTH {
+                    return Err(VoxError::Format("scene graph is too deep".to_string()));
+                }
+                path.truncate(depth);
+                if path.contains(&id) {
+                    return Err(VoxError::Format(format!(
+                        "scene node {} contains itself",
+                        id
+                    )));
+                }
+                path.push(id);
+                visited += 1;
+                if visited > MAX_SCENE_NODES || file.instances.len() > MAX_SCENE_NODES {
+                    return Err(VoxError::Format("scene graph is too large".to_string()));
+                }
+                match nodes.get(&id) {
+                    Some(Node::Transform {
+                        child,
+                        rotation: local_rotation,
+                        translation: local_translation,
+                    }) => stack.push((
+                        *child,
+                        rotation * local_rotation,
+                        rotation * local_translation + translation,
+                        depth + 1,
+                    )),
+                    Some(Node::Group { children }) => {
+                        for child in children.iter().rev() {
+                            stack.push((*child, rotation, translation, depth + 1));
+                        }
+                    }
+                    Some(Node::Shape { models }) => {
+                        for model in models {
+                            if *model < 0 || *model as usize >= file.models.len() {
+                                return Err(VoxError::Format(format!("unknown model {}", model)));
+                            }
+                            file.instances.push(VoxInstance {
+                                model: *model as usize,
+                                rotation,
+                                translation,
+                            });
+                        }
+                    }
+                    None => return Err(VoxError::Format(format!("unknown scene node {}", id))),
+                }
+            }
+        }
+        Ok(file)
+    }
+
+    /// Scene position of a voxel of an instance, in MagicaVoxel coordinates.
+    pub fn scene_position(&self, instance: &VoxInstance, voxel: Vector3<u8>) -> Vector3<i32> {
+        let pivot = self.models[instance.model].size.map(|v| (v / 2) as i32);
+        instance.rotation * (voxel.map(i32::from) - pivot) + instance.translation
+    }
+
+    /// Inclusive bounds of every instance box, in map coordinates.
+    fn map_bounds(&self) -> Option<(Vector3<i32>, Vector3<i32>)> {
+        let mut bounds: Option<(Vector3<i32>, Vector3<i32>)> = None;
+        for instance in &self.instances {
+            let size = self.models[instance.model].size;
+            if size.iter().any(|v| *v == 0) {
+                continue;
+            }
+            let last = size.map(|v| (v - 1).min(255) as u8);
+            for corner in 0..8 {
+                let voxel = Vector3::new(
+                    if corner & 1 == 0 { 0 } else { last.x },
+                    if corner & 2 == 0 { 0 } else { last.y },
+                    if corner & 4 == 0 { 0 } else { last.z },
+                );
+                let position = vox_to_map(self.scene_position(instance, voxel));
+                bounds = Some(match bounds {
+                    Some((min, max)) => (min.inf(&position), max.sup(&position)),
+                    None => (position, position),
+                });
+            }
+        }
+        bounds
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), VoxError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the file in version 200 of the format, placing every instance with a transform
+    /// node under a single group.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), VoxError> {
+        let mut children = Vec::new();
+        for model in &self.models {
+            let mut size = Vec::new();
+            for value in model.size.iter() {
+                size.extend_from_slice(&value.to_le_bytes());
+            }
+            write_chunk(&mut children, b"SIZE", &size);
+            let mut voxels = Vec::with_capacity(4 + model.voxels.len() * 4);
+            voxels.extend_from_slice(&(model.voxels.len() as u32).to_le_bytes());
+            for (position, index) in &model.voxels {
+                voxels.extend_from_slice(&[position.x, position.y, position.z, *index]);
+            }
+            write_chunk(&mut children, b"XYZI", &voxels);
+        }
+
+        let group_children: Vec<i32> = (0..self.instances.len() as i32)
+            .map(|i| 2 + 2 * i)
+            .collect();
+        write_chunk(&mut children, b"nTRN", &transform_node(0, 1, -1, &[]));
+        let mut group = Vec::new();
+        group.extend_from_slice(&1i32.to_le_bytes());
+        write_dictionary(&mut group, &[]);
+        group.extend_from_slice(&(group_children.len() as u32).to_le_bytes());
+        for child in &group_children {
+            group.extend_from_slice(&child.to_le_bytes());
+        }
+        write_chunk(&mut children, b"nGRP", &group);
+        for (instance, id) in self.instances.iter().zip(group_children) {
+            let translation = instance.translation;
+            let translation = format!("{} {} {}", translation.x, translation.y, translation.z);
+            let mut frame = vec![("_t", translation)];
+            if instance.rotation != Matrix3::identity() {
+                let rotation = encode_rotation(&instance.rotation).ok_or_else(|| {
+                    VoxError::Format(format!("invalid rotation {}", instance.rotation))
+                })?;
+                frame.push(("_r", rotation.to_string()));
+            }
+            write_chunk(
+                &mut children,
+                b"nTRN",
+                &transform_node(id, id + 1, 0, &frame),
+            );
+            let mut shape = Vec::new();
+            shape.extend_from_slice(&(id + 1).to_le_bytes());
+            write_dictionary(&mut shape, &[]);
+            shape.extend_from_slice(&1u32.to_le_bytes());
+            shape.extend_from_slice(&(instance.model as i32).to_le_bytes());
+            write_dictionary(&mut shape, &[]);
+            write_chunk(&mut children, b"nSHP", &shape);
+        }
+        let mut layer = Vec::new();
+        layer.extend_from_slice(&0i32.to_le_bytes());
+        write_dictionary(&mut layer, &[]);
+        layer.extend_from_slice(&(-1i32).to_le_bytes());
+        write_chunk(&mut children, b"LAYR", &layer);
+
+        let mut palette = Vec::with_capacity(256 * 4);
+        for color in &self.palette[1..] {
+            palette.extend_from_slice(color);
+        }
+        palette.extend_from_slice(&[0; 4]);
+        write_chunk(&mut children, b"RGBA", &palette);
+
+        writer.write_all(b"VOX ")?;
+        writer.write_all(&200i32.to_le_bytes())?;
+        writer.write_all(b"MAIN")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&(children.len() as u32).to_le_bytes())?;
+        writer.write_all(&children)?;
+        Ok(())
+    }
+}
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(content.len() as u32).to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(content);
+}
+
+fn write_dictionary(out: &mut Vec<u8>, entries: &[(&str, String)]) {
+    out.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    for (key, value) in entries {
+        for string in [*key, value.as_str()] {
+            out.extend_from_slice(&(string.len() as u32).to_le_bytes());
+            out.extend_from_slice(string.as_bytes());
+        }
+    }
+}
+
+fn transform_node(id: i32, child: i32, layer: i32, frame: &[(&str, String)]) -> Vec<u8> {
+    let mut node = Vec::new();
+    node.extend_from_slice(&id.to_le_bytes());
+    write_dictionary(&mut node, &[]);
+    for value in [child, -1, layer, 1] {
+        node.extend_from_slice(&value.to_le_bytes());
+    }
+    write_dictionary(&mut node, frame);
+    node
+}
+
+enum Node {
+    Transform {
+        child: i32,
+        rotation: Matrix3<i32>,
+        translation: Vector3<i32>,
+    },
+    Group {
+        children: Vec<i32>,
+    },
+    Shape {
+        models: Vec<i32>,
+    },
+}
+
+pub fn vox_to_map(position: Vector3<i32>) -> Vector3<i32> {
+    Vector3::new(position.x, position.z, -1 - position.y)
+}
+
+pub fn map_to_vox(position: Vector3<i32>) -> Vector3<i32> {
+    Vector3::new(position.x, -1 - position.z, position.y)
+}
+
+impl Map {
+    /// Stamps the scene of a `.vox` file into the map, so that the corner of its bounding box
+    /// with the smallest coordinates is at `offset`. Empty voxels leave the map untouched.
+    /// Each palette color used becomes the material with the same albedo, or a new material
+    /// named `vox_RRGGBB`. The edits form one undoable transaction. Returns the number of
+    /// voxels that changed.
+    pub fn import_vox(&mut self, path: &Path, offset: Vector3<i32>) -> Result<usize, VoxError> {
+        let file = VoxFile::load(path)?;
+        self.stamp_vox(&file, offset)
+    }
+
+    pub fn stamp_vox(&mut self, file: &VoxFile, offset: Vector3<i32>) -> Result<usize, VoxError> {
+        let Some((min, _)) = file.map_bounds() else {
+            return Ok(0);
+        };
+        let mut materials: HashMap<u8, MaterialId> = HashMap::new();
+        let mut edits: Vec<(VoxelPosition, Voxel)> = Vec::new();
+        for instance in &file.instances {
+            for (voxel, index) in &file.models[instance.model].voxels {
+                let material = match materials.get(index) {
+                    Some(material) => *material,
+                    None => {
+                        let material = self.vox_material(file.palette[*index as usize])?;
+                        materials.insert(*index, material);
+                        material
+                    }
+                };
+                let position = vox_to_map(file.scene_position(instance, *voxel)) - min + offset;
+                edits.push(((position.x, position.y, position.z), Voxel::new(material)));
+            }
+        }
+        Ok(self.set_voxels(edits))
+    }
+
+    /// Exports the voxels between `min` and `max`, both included, to a `.vox` file, see
+    /// `to_vox`.
+    pub fn export_vox(
+        &self,
+        path: &Path,
+        min: Vector3<i32>,
+        max: Vector3<i32>,
+    ) -> Result<(), VoxError> {
+        self.to_vox(min, max).save(path)
+    }
+
+    /// Builds a `.vox` scene of the voxels between `min` and `max`, both included. The region
+    /// is split into models of at most 256 voxels per axis and the material albedos are
+    /// quantized to the 255 colors of a palette. Importing the scene at `min` gives the region
+    /// back. Chunks that are not in memory are read from the chunk source, if the map has one.
+    pub fn to_vox(&self, min: Vector3<i32>, max: Vector3<i32>) -> VoxFile {
+        let (min, max) = (min.inf(&max), min.sup(&max));
+
+        // Solid voxels in scene coordinates, which start at zero like the region does.
+        let scene_min = map_to_vox(min).inf(&map_to_vox(max));
+        let scene_size = map_to_vox(max).sup(&map_to_vox(min)) - scene_min + Vector3::repeat(1);
+        let mut voxels = Vec::new();
+        let mut counts: HashMap<MaterialId, usize> = HashMap::new();
+        for z in min.z..=max.z {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    let Some(voxel) = self.get_voxel(x, y, z) else {
+                        continue;
+                    };
+                    if !voxel.is_empty() {
+                        let position = map_to_vox(Vector3::new(x, y, z)) - scene_min;
+                        voxels.push((position, voxel.material));
+                        *counts.entry(voxel.material).or_default() += 1;
+                    }
+                }
+            }
+        }
+
+        let mut materials: Vec<_> = counts.into_iter().collect();
+        materials.sort();
+        let colors: Vec<_> = materials
+            .iter()
+            .map(|(id, count)| {
+                let albedo = self
+                    .materials
+                    .get(*id)
+                    .map_or(Vector3::zeros(), |m| m.albedo);
+                (albedo, *count)
+            })
+            .collect();
+        let (colors, indices) = quantize(&colors, 255);
+        let mut palette = default_palette();
+        for (index, color) in colors.iter().enumerate() {
+            palette[index + 1] = [color.x, color.y, color.z, 0xff];
+        }
+        let palette_index: HashMap<MaterialId, u8> = materials
+            .iter()
+            .zip(indices)
+            .map(|((id, _), index)| (*id, index as u8 + 1))
+            .collect();
+
+        // Every tile becomes a model, even an empty one, so the scene keeps the region bounds.
+        let tiles = scene_size.map(|v| (v + MAX_MODEL_SIZE - 1) / MAX_MODEL_SIZE);
+        let tile_index =
+            |tile: Vector3<i32>| ((tile.z * tiles.y + tile.y) * tiles.x + tile.x) as usize;
+        let mut file = VoxFile {
+            models: Vec::new(),
+            palette,
+            instances: Vec::new(),
+        };
+        for z in 0..tiles.z {
+            for y in 0..tiles.y {
+                for x in 0..tiles.x {
+                    let origin = Vector3::new(x, y, z) * MAX_MODEL_SIZE;
+                    let size = (scene_size - origin).map(|v| v.min(MAX_MODEL_SIZE));
+                    file.instances.push(VoxInstance {
+                        model: file.models.len(),
+                        rotation: Matrix3::identity(),
+                        translation: origin + size.map(|v| v / 2),
+                    });
+                    file.models.push(VoxModel {
+                        size: size.map(|v| v as u32),
+                        voxels: Vec::new(),
+                    });
+                }
+            }
+        }
+        for (position, material) in voxels {
+            let tile = position.map(|v| v / MAX_MODEL_SIZE);
+            let local = (position - tile * MAX_MODEL_SIZE).map(|v| v as u8);
+            file.models[tile_index(tile)]
+                .voxels
+                .push((local, palette_index[&material]));
+        }
+        file
+    }
+
+    /// Material for a palette color: the first material with this albedo, or a new one.
+    fn vox_material(&mut self, color: VoxColor) -> Result<MaterialId, VoxError> {
+        let albedo = Vector3::new(color[0], color[1], color[2]);
+        let existing = self
+            .materials
+            .iter()
+            .skip(1)
+            .find(|(_, material)| material.albedo == albedo);
+        if let Some((id, _)) = existing {
+            return Ok(id);
+        }
+        let name = format!("vox_{:02x}{:02x}{:02x}", color[0], color[1], color[2]);
+        Ok(self.materials.register(Material::new(&name, albedo))?)
+    }
+}
+
+/// Parses a `_r` rotation: bits 0-1 and 2-3 hold the column of the non-zero entry of the
+/// first and second rows, bits 4 to 6 make the entries of each row negative.
+fn parse_rotation(value: &str) -> Result<Matrix3<i32>, VoxError> {
+    let bits: u8 = value
+        .trim()
+        .parse()
+        .map_err(|_| VoxError::Format(format!("invalid rotation {}", value)))?;
+    let first = (bits & 3) as usize;
+    let second = ((bits >> 2) & 3) as usize;
+    if first > 2 || second > 2 || first == second {
+        return Err(VoxError::Format(format!("invalid rotation {}", value)));
+    }
+    let third = 3 - first - second;
+    let mut rotation = Matrix3::zeros();
+    for (row, column) in [first, second, third].into_iter().enumerate() {
+        rotation[(row, column)] = if bits & (1 << (4 + row)) == 0 { 1 } else { -1 };
+    }
+    Ok(rotation)
+}
+
+fn encode_rotation(rotation: &Matrix3<i32>) -> Option<u8> {
+    let mut bits = 0;
+    let mut columns = [0; 3];
+    for (row, column) in columns.iter_mut().enumerate() {
+        let entries = rotation.row(row);
+        *column = (0..3).find(|column| entries[*column] != 0)?;
+        if entries.iter().filter(|v| **v != 0).count() != 1 || entries[*column].abs() != 1 {
+            return None;
+        }
+        if entries[*column] < 0 {
+            bits |= 1 << (4 + row);
+        }
+    }
+    if columns[0] == columns[1] || columns[0] == columns[2] || columns[1] == columns[2] {
+        return None;
+    }
+    Some(bits | columns[0] as u8 | (columns[1] as u8) << 2)
+}
+
+/// Reduces weighted colors to at most `max` colors with a median cut: the box of colors with
+/// the widest channel range is split at its weighted median until there are enough boxes.
+/// Returns the palette and the palette index of every input color.
+fn quantize(colors: &[(Vector3<u8>, usize)], max: usize) -> (Vec<Vector3<u8>>, Vec<usize>) {
+    let mut boxes: Vec<Vec<usize>> = vec![(0..colors.len()).collect()];
+    if colors.is_empty() {
+        boxes.clear();
+    }
+    let range = |members: &[usize], channel: usize| {
+        let values = members.iter().map(|i| colors[*i].0[channel]);
+        values.clone().max().unwrap_or(0) - values.min().unwrap_or(0)
+    };
+    while boxes.len() < max {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, members)| members.len() > 1)
+            .map(|(index, members)| {
+                let channel = (0..3).max_by_key(|c| range(members, *c)).unwrap();
+                (range(members, channel), index, channel)
+            })
+            .max();
+        let Some((_, index, channel)) = widest else {
+            break;
+        };
+        let mut members = boxes.swap_remove(index);
+        members.sort_by_key(|i| colors[*i].0[channel]);
+        let total: usize = members.iter().map(|i| colors[*i].1).sum();
+        let mut weight = 0;
+        let mut split = 1;
+        for (position, i) in members.iter().enumerate().take(members.len() - 1) {
+            weight += colors[*i].1;
+            split = position + 1;
+            if weight * 2 >= total {
+                break;
+            }
+        }
+        let upper = members.split_off(split);
+        boxes.push(members);
+        boxes.push(upper);
+    }
+
+    let mut palette = Vec::with_capacity(boxes.len());
+    let mut indices = vec![0; colors.len()];
+    for members in boxes {
+        let total: usize = members.iter().map(|i| colors[*i].1).sum();
+        let mut sum = Vector3::<usize>::zeros();
+        for i in &members {
+            sum += colors[*i].0.map(usize::from) * colors[*i].1;
+            indices[*i] = palette.len();
+        }
+        palette.push(sum.map(|v| ((v + total / 2) / total.max(1)) as u8));
+    }
+    (palette, indices)
+}
+
+fn parse_translation(value: &str) -> Result<Vector3<i32>, VoxError> {
+    let components = value
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<Vec<i32>, _>>()
+        .map_err(|_| VoxError::Format(format!("invalid translation {}", value)))?;
+    match components[..] {
+        [x, y, z] => Ok(Vector3::new(x, y, z)),
+        _ => Err(VoxError::Format(format!("invalid translation {}", value))),
+    }
+}
+
+/// The palette MagicaVoxel uses for files without an `RGBA` chunk: a 6x6x6 color cube without
+/// black, followed by ramps of blue, green, red and gray.
+pub fn default_palette() -> [VoxColor; 256] {
+    let mut palette = [[0; 4]; 256];
+    let cube = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    let ramp = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+    let mut index = 1;
+    for red in cube {
+        for green in cube {
+            for blue in cube {
+                if index < 216 {
+                    palette[index] = [red, green, blue, 0xff];
+                    index += 1;
+                }
+            }
+        }
+    }
+    for channel in [2, 1, 0] {
+        for value in ramp {
+            palette[index][channel] = value;
+            palette[index][3] = 0xff;
+            index += 1;
+        }
+    }
+    for value in ramp {
+        palette[index] = [value, value, value, 0xff];
+        index += 1;
+    }
+    palette
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], VoxError> {
+        if self.bytes.len() < length {
+            return Err(VoxError::Format("unexpected end of file".to_string()));
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn i32(&mut self) -> Result<i32, VoxError> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, VoxError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, VoxError> {
+        let length = self.u32()? as usize;
+        Ok(String::from_utf8_lossy(self.take(length)?).into_owned())
+    }
+
+    fn dictionary(&mut self) -> Result<HashMap<String, String>, VoxError> {
+        let count = self.u32()?;
+        let mut dictionary = HashMap::new();
+        for _ in 0..count {
+            let key = self.string()?;
+            dictionary.insert(key, self.string()?);
+        }
+        Ok(dictionary)
+    }
+
+    /// Reads a chunk header and returns its id, content and children.
+    fn chunk(&mut self) -> Result<(&'a [u8], Reader<'a>, Reader<'a>), VoxError> {
+        let id = self.take(4)?;
+        let content = self.u32()? as usize;
+        let children = self.u32()? as usize;
+        let content = Reader {
+            bytes: self.take(content)?,
+        };
+        let children = Reader {
+            bytes: self.take(children)?,
+        };
+        Ok((id, content, children))
+    }
+}
@@ -1,23 +1,20 @@
-use nalgebra::Vector3;
+use crate::material::{MaterialId, AIR};
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Voxel {
-    pub color: Vector3<u8>,
-    pub is_empty: bool,
+    pub material: MaterialId,
 }
 
 impl Voxel {
-    pub fn new(color: Vector3<u8>) -> Self {
-        Self {
-            color,
-            is_empty: false,
-        }
+    pub fn new(material: MaterialId) -> Self {
+        Self { material }
     }
 
     pub fn empty() -> Self {
-        Self {
-            color: Vector3::new(0, 0, 0),
-            is_empty: true,
-        }
+        Self { material: AIR }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.material == AIR
     }
 }
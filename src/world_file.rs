@@ -0,0 +1,565 @@
+//! On-disk format of a `Map`.
+//!
+//! A world file starts with a fixed header, protected by its own CRC-32:
+//!
+//! | bytes | content                                     |
+//! |-------|---------------------------------------------|
+//! | 4     | magic `TRSW`                                |
+//! | 2     | format version                              |
+//! | 1     | chunk size, always 16                       |
+//! | 1     | flags, bit 0 set when distance maps follow  |
+//! | 8     | world seed                                  |
+//! | 4     | distance radius                             |
+//! | 4     | CRC-32 of the previous bytes                |
+//!
+//! It is followed by sections made of a 4 byte tag, then the length and CRC-32 of the
+//! deflate-compressed payload, then the compressed payload itself. Sections are `MATS` (material
+//! registry), `HIST` (edit history), `EMPT` (known-empty chunks), one `CHNK` per chunk holding
+//! its material ids and optionally its distance map, and a final empty `END ` section. Every
+//! number is little-endian. Level files written by `Map::save_level` have no `EMPT` and `CHNK`
+//! sections, their chunks are stored in region files.
+//!
+//! A `CHNK` payload never inflates past the size of a chunk with its distance map, other
+//! payloads past 256 MiB, larger ones are reported as corrupt rather than read.
+
+use crate::chunk::Chunk;
+use crate::history::{History, Transaction, VoxelChange};
+use crate::map::Map;
+use crate::material::{Material, MaterialError, MaterialRegistry};
+use crate::seed::WorldSeed;
+use crate::voxel::Voxel;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use nalgebra::Vector3;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+/// Version written by `Map::save`. Files with a newer version are rejected.
+pub const FORMAT_VERSION: u16 = 1;
+
+const MAGIC: &[u8; 4] = b"TRSW";
+const CHUNK_SIZE: u8 = 16;
+const CHUNK_VOLUME: usize = 16 * 16 * 16;
+const HAS_DISTANCE_MAPS: u8 = 1;
+/// Position, material ids and distances of a chunk.
+pub(crate) const MAX_CHUNK_PAYLOAD: usize = 12 + CHUNK_VOLUME * 3;
+const MAX_SECTION_PAYLOAD: usize = 256 << 20;
+
+#[derive(Debug)]
+pub enum WorldFileError {
+    Io(std::io::Error),
+    NotAWorld,
+    UnsupportedVersion(u16),
+    UnsupportedChunkSize(u8),
+    /// The named section, or the header, does not match its checksum.
+    ChecksumMismatch(String),
+    Corrupt(String),
+    Material(MaterialError),
+}
+
+impl fmt::Display for WorldFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorldFileError::Io(e) => write!(f, "could not access world file: {}", e),
+            WorldFileError::NotAWorld => write!(f, "not a world file"),
+            WorldFileError::UnsupportedVersion(version) => {
+                write!(f, "unsupported world file version {}", version)
+            }
+            WorldFileError::UnsupportedChunkSize(size) => {
+                write!(f, "unsupported chunk size {}", size)
+            }
+            WorldFileError::ChecksumMismatch(section) => {
+                write!(f, "world file is corrupted, bad checksum in {}", section)
+            }
+            WorldFileError::Corrupt(reason) => write!(f, "world file is corrupted: {}", reason),
+            WorldFileError::Material(e) => write!(f, "invalid material in world file: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for WorldFileError {}
+
+impl From<std::io::Error> for WorldFileError {
+    fn from(e: std::io::Error) -> Self {
+        WorldFileError::Io(e)
+    }
+}
+
+impl From<MaterialError> for WorldFileError {
+    fn from(e: MaterialError) -> Self {
+        WorldFileError::Material(e)
+    }
+}
+
+impl Map {
+    /// Writes the map to a file, distance maps included when there are any. The file is
+    /// written next to its destination first, so a failed save never leaves a truncated world.
+    /// Only chunks in memory are written, maps backed by a chunk source use `save_regions`.
+    pub fn save(&self, path: &Path) -> Result<(), WorldFileError> {
+        let temporary = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        self.write_to(&mut writer, self.distance_radius > 0)?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    /// Reads a map written by `save`. Distance maps missing from the file are regenerated.
+    pub fn load(path: &Path) -> Result<Map, WorldFileError> {
+        Map::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// Writes everything about the map but its chunks, which live in a chunk source, see
+    /// `Map::save_regions`.
+    pub fn save_level(&self, path: &Path) -> Result<(), WorldFileError> {
+        let temporary = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        self.write_world(&mut writer, false, false)?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    pub fn write_to<W: Write>(
+        &self,
+        writer: &mut W,
+        distance_maps: bool,
+    ) -> Result<(), WorldFileError> {
+        self.write_world(writer, distance_maps, true)
+    }
+
+    fn write_world<W: Write>(
+        &self,
+        writer: &mut W,
+        distance_maps: bool,
+        chunks: bool,
+    ) -> Result<(), WorldFileError> {
+        let mut header = Vec::new();
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        header.push(CHUNK_SIZE);
+        header.push(if distance_maps { HAS_DISTANCE_MAPS } else { 0 });
+        header.extend_from_slice(&self.seed.value().to_le_bytes());
+        header.extend_from_slice(&self.distance_radius.to_le_bytes());
+        header.extend_from_slice(&crc32fast::hash(&header).to_le_bytes());
+        writer.write_all(&header)?;
+
+        write_section(writer, b"MATS", &encode_materials(&self.materials))?;
+        write_section(writer, b"HIST", &encode_history(&self.history))?;
+        if chunks {
+            self.write_chunks(writer, distance_maps)?;
+        }
+        write_section(writer, b"END ", &[])?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn write_chunks<W: Write>(
+        &self,
+        writer: &mut W,
+        distance_maps: bool,
+    ) -> Result<(), WorldFileError> {
+        let mut empty_chunks: Vec<_> = self.empty_chunks.iter().collect();
+        empty_chunks.sort();
+        let mut payload = Encoder::default();
+        payload.u32(empty_chunks.len() as u32);
+        for position in empty_chunks {
+            payload.position(*position);
+        }
+        write_section(writer, b"EMPT", &payload.bytes)?;
+
+        let mut chunks: Vec<_> = self.chunks.values().collect();
+        chunks.sort_by_key(|chunk| chunk.position);
+        for chunk in chunks {
+            write_section(writer, b"CHNK", &encode_chunk(chunk, distance_maps))?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Map, WorldFileError> {
+        let mut header = [0; 24];
+        reader.read_exact(&mut header[..4])?;
+        if &header[..4] != MAGIC {
+            return Err(WorldFileError::NotAWorld);
+        }
+        reader.read_exact(&mut header[4..])?;
+        let mut decoder = Decoder::new(&header[4..]);
+        let version = decoder.u16()?;
+        let chunk_size = decoder.u8()?;
+        let flags = decoder.u8()?;
+        let seed = decoder.u64()?;
+        let distance_radius = decoder.i32()?;
+        let checksum = decoder.u32()?;
+        if crc32fast::hash(&header[..20]) != checksum {
+            return Err(WorldFileError::ChecksumMismatch("header".to_string()));
+        }
+        if version > FORMAT_VERSION {
+            return Err(WorldFileError::UnsupportedVersion(version));
+        }
+        if chunk_size != CHUNK_SIZE {
+            return Err(WorldFileError::UnsupportedChunkSize(chunk_size));
+        }
+        let distance_maps = flags & HAS_DISTANCE_MAPS != 0;
+
+        let mut map = Map::new();
+        map.seed = WorldSeed::new(seed);
+        map.distance_radius = distance_radius;
+        loop {
+            let (tag, payload) = read_section(reader)?;
+            let mut decoder = Decoder::new(&payload);
+            match &tag {
+                b"MATS" => map.materials = decode_materials(&mut decoder)?,
+                b"HIST" => map.history = decode_history(&mut decoder)?,
+                b"EMPT" => {
+                    for _ in 0..decoder.u32()? {
+                        map.empty_chunks.insert(decoder.position()?);
+                    }
+                }
+                b"CHNK" => {
+                    let chunk = decode_chunk(&mut decoder, distance_maps, &map.materials)?;
+                    map.chunks.insert(chunk.position, chunk);
+                }
+                b"END " => break,
+                _ => {
+                    return Err(WorldFileError::Corrupt(format!(
+                        "unknown section {}",
+                        String::from_utf8_lossy(&tag)
+                    )))
+                }
+            }
+            decoder.finish(&tag)?;
+        }
+
+        if !distance_maps && distance_radius > 0 && !map.chunks.is_empty() {
+            map.rebuild_distance_maps(distance_radius);
+        }
+        Ok(map)
+    }
+}
+
+fn write_section<W: Write>(
+    writer: &mut W,
+    tag: &[u8; 4],
+    payload: &[u8],
+) -> Result<(), WorldFileError> {
+    let compressed = deflate(payload)?;
+    writer.write_all(tag)?;
+    writer.write_all(&(compressed.len() as u32).to_le_bytes())?;
+    writer.write_all(&crc32fast::hash(&compressed).to_le_bytes())?;
+    writer.write_all(&compressed)?;
+    Ok(())
+}
+
+fn read_section<R: Read>(reader: &mut R) -> Result<([u8; 4], Vec<u8>), WorldFileError> {
+    let mut header = [0; 12];
+    reader.read_exact(&mut header).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => WorldFileError::Corrupt("unexpected end of file".to_string()),
+        _ => WorldFileError::Io(e),
+    })?;
+    let mut decoder = Decoder::new(&header[4..]);
+    let tag = [header[0], header[1], header[2], header[3]];
+    let length = decoder.u32()? as usize;
+    let checksum = decoder.u32()?;
+
+    let mut compressed = Vec::new();
+    reader.take(length as u64).read_to_end(&mut compressed)?;
+    if compressed.len() != length {
+        return Err(WorldFileError::Corrupt(
+            "unexpected end of file".to_string(),
+        ));
+    }
+    if crc32fast::hash(&compressed) != checksum {
+        return Err(WorldFileError::ChecksumMismatch(
+            String::from_utf8_lossy(&tag).trim_end().to_string(),
+        ));
+    }
+    let limit = match &tag {
+        b"CHNK" => MAX_CHUNK_PAYLOAD,
+        _ => MAX_SECTION_PAYLOAD,
+    };
+    Ok((tag, inflate(&compressed, limit)?))
+}
+
+pub(crate) fn deflate(payload: &[u8]) -> Result<Vec<u8>, WorldFileError> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(payload)?;
+    Ok(encoder.finish()?)
+}
+
+/// Inflates a payload, which is corrupt if it holds more than `limit` bytes. Deflate packs
+/// long runs over a thousand times, so the compressed length alone does not bound it.
+pub(crate) fn inflate(compressed: &[u8], limit: usize) -> Result<Vec<u8>, WorldFileError> {
+    let mut payload = Vec::new();
+    DeflateDecoder::new(compressed)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut payload)
+        .map_err(|e| WorldFileError::Corrupt(e.to_string()))?;
+    if payload.len() > limit {
+        return Err(WorldFileError::Corrupt(format!(
+            "payload inflates past {} bytes",
+            limit
+        )));
+    }
+    Ok(payload)
+}
+
+fn encode_materials(materials: &MaterialRegistry) -> Vec<u8> {
+    let mut encoder = Encoder::default();
+    // Air is implicit, the others are registered again in id order so ids are preserved.
+    encoder.u16(materials.len() as u16 - 1);
+    for (_, material) in materials.iter().skip(1) {
+        encoder.string(&material.name);
+        encoder.bytes(material.albedo.as_slice());
+        encoder.f32(material.emissive);
+        encoder.f32(material.opacity);
+        encoder.f32(material.reflectivity);
+        encoder.f32(material.roughness);
+    }
+    encoder.bytes
+}
+
+fn decode_materials(decoder: &mut Decoder) -> Result<MaterialRegistry, WorldFileError> {
+    let mut materials = MaterialRegistry::new();
+    for expected_id in 1..=decoder.u16()? {
+        let name = decoder.string()?;
+        let albedo = Vector3::new(decoder.u8()?, decoder.u8()?, decoder.u8()?);
+        let material = Material {
+            emissive: decoder.f32()?,
+            opacity: decoder.f32()?,
+            reflectivity: decoder.f32()?,
+            roughness: decoder.f32()?,
+            ..Material::new(&name, albedo)
+        };
+        if materials.register(material)? != expected_id {
+            return Err(WorldFileError::Corrupt(format!(
+                "material {} is listed twice",
+                name
+            )));
+        }
+    }
+    Ok(materials)
+}
+
+fn encode_history(history: &History) -> Vec<u8> {
+    let mut encoder = Encoder::default();
+    encoder.u64(history.budget as u64);
+    for transactions in [
+        history.undo_transactions().collect::<Vec<_>>(),
+        history.redo_transactions().collect(),
+    ] {
+        encoder.u32(transactions.len() as u32);
+        for transaction in transactions {
+            encoder.u32(transaction.changes.len() as u32);
+            for change in &transaction.changes {
+                encoder.position(change.position);
+                encoder.u16(change.before.material);
+                encoder.u16(change.after.material);
+            }
+        }
+    }
+    encoder.bytes
+}
+
+fn decode_history(decoder: &mut Decoder) -> Result<History, WorldFileError> {
+    let budget = decoder.u64()? as usize;
+    let mut stacks = [Vec::new(), Vec::new()];
+    for transactions in &mut stacks {
+        for _ in 0..decoder.u32()? {
+            let mut transaction = Transaction::default();
+            for _ in 0..decoder.u32()? {
+                transaction.changes.push(VoxelChange {
+                    position: decoder.position()?,
+                    before: Voxel::new(decoder.u16()?),
+                    after: Voxel::new(decoder.u16()?),
+                });
+            }
+            transactions.push(transaction);
+        }
+    }
+    let [undo, redo] = stacks;
+    Ok(History::from_transactions(budget, undo, redo))
+}
+
+pub(crate) fn encode_chunk(chunk: &Chunk, distance_maps: bool) -> Vec<u8> {
+    let mut encoder = Encoder::default();
+    encoder.position(chunk.position);
+    for_each_voxel(|x, y, z| encoder.u16(chunk.get_voxel(x, y, z).unwrap().material));
+    if distance_maps {
+        for_each_voxel(|x, y, z| encoder.u8(chunk.get_distance(x, y, z)));
+    }
+    encoder.bytes
+}
+
+/// Decodes a chunk encoded alone by `encode_chunk`.
+pub(crate) fn decode_chunk_bytes(
+    bytes: &[u8],
+    distance_maps: bool,
+    materials: &MaterialRegistry,
+) -> Result<Chunk, WorldFileError> {
+    let mut decoder = Decoder::new(bytes);
+    let chunk = decode_chunk(&mut decoder, distance_maps, materials)?;
+    decoder.finish(b"CHNK")?;
+    Ok(chunk)
+}
+
+fn decode_chunk(
+    decoder: &mut Decoder,
+    distance_maps: bool,
+    materials: &MaterialRegistry,
+) -> Result<Chunk, WorldFileError> {
+    let mut chunk = Chunk::new(decoder.position()?);
+    let ids = decoder.take(CHUNK_VOLUME * 2)?;
+    let mut ids = ids
+        .chunks_exact(2)
+        .map(|id| u16::from_le_bytes([id[0], id[1]]));
+    let mut result = Ok(());
+    for_each_voxel(|x, y, z| {
+        let id = ids.next().unwrap();
+        if materials.get(id).is_none() {
+            result = Err(WorldFileError::Corrupt(format!(
+                "unknown material id {}",
+                id
+            )));
+        }
+        chunk.set(x, y, z, Voxel::new(id));
+    });
+    result?;
+
+    if distance_maps {
+        let mut distances = decoder.take(CHUNK_VOLUME)?.iter();
+        for_each_voxel(|x, y, z| chunk.set_distance(x, y, z, *distances.next().unwrap()));
+    }
+    chunk.compact();
+    Ok(chunk)
+}
+
+/// Calls `f` for every voxel of a chunk, in storage order.
+fn for_each_voxel(mut f: impl FnMut(u8, u8, u8)) {
+    for z in 0..16 {
+        for y in 0..16 {
+            for x in 0..16 {
+                f(x, y, z);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u16(value.len() as u16);
+        self.bytes(value.as_bytes());
+    }
+
+    fn position(&mut self, (x, y, z): (i32, i32, i32)) {
+        for value in [x, y, z] {
+            self.bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], WorldFileError> {
+        if self.bytes.len() < length {
+            return Err(WorldFileError::Corrupt("section is truncated".to_string()));
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], WorldFileError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, WorldFileError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, WorldFileError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, WorldFileError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn i32(&mut self) -> Result<i32, WorldFileError> {
+        Ok(i32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, WorldFileError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn f32(&mut self) -> Result<f32, WorldFileError> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> Result<String, WorldFileError> {
+        let length = self.u16()? as usize;
+        String::from_utf8(self.take(length)?.to_vec())
+            .map_err(|_| WorldFileError::Corrupt("invalid material name".to_string()))
+    }
+
+    fn position(&mut self) -> Result<(i32, i32, i32), WorldFileError> {
+        Ok((self.i32()?, self.i32()?, self.i32()?))
+    }
+
+    /// Fails if the section has bytes left that nothing read.
+    fn finish(&self, tag: &[u8; 4]) -> Result<(), WorldFileError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(WorldFileError::Corrupt(format!(
+                "trailing data in section {}",
+                String::from_utf8_lossy(tag).trim_end()
+            )))
+        }
+    }
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use common::voxel;
+use torus::biome::{BiomeError, BiomeTable};
+use torus::generator::BiomeGenerator;
+use torus::map::Map;
+use torus::material::MaterialRegistry;
+use torus::seed::WorldSeed;
+
+const TWO_BIOMES: &str = r#"
+[[biome]]
+name = "cold"
+temperature = -0.5
+humidity = 0.0
+surface = "snow"
+subsurface = "dirt"
+height = 0
+amplitude = 0
+decoration = "ice"
+decoration_density = 1.0
+decoration_height = 2
+
+[[biome]]
+name = "hot"
+temperature = 0.5
+humidity = 0.0
+surface = "sand"
+subsurface = "sandstone"
+height = 20
+amplitude = 0
+"#;
+
+#[test]
+fn biome_tables_are_data() {
+    let materials = MaterialRegistry::builtin();
+    let builtin = BiomeTable::builtin(&materials).unwrap();
+    for name in ["desert", "plains", "forest", "tundra", "mountains"] {
+        assert!(builtin.get(name).is_some(), "{}", name);
+    }
+    assert_eq!(builtin.get("desert").unwrap().surface, voxel("sand"));
+
+    let table = BiomeTable::from_toml(TWO_BIOMES, &materials).unwrap();
+    assert_eq!(table.biomes.len(), 2);
+    assert!(table.get("hot").unwrap().decoration.is_empty());
+    assert_eq!(table.get("cold").unwrap().decoration_height, 2);
+
+    let unknown = TWO_BIOMES.replace("\"sandstone\"", "\"marble\"");
+    assert!(matches!(
+        BiomeTable::from_toml(&unknown, &materials),
+        Err(BiomeError::UnknownMaterial { biome, material }) if biome == "hot" && material == "marble"
+    ));
+    assert!(matches!(
+        BiomeTable::from_toml("", &materials),
+        Err(BiomeError::NoBiomes)
+    ));
+}
+
+#[test]
+fn climate_selects_and_blends_biomes() {
+    let mut map = Map::new();
+    let table = BiomeTable::from_toml(TWO_BIOMES, &map.materials).unwrap();
+    let mut generator = BiomeGenerator::new(WorldSeed::new(4), table, &map.materials);
+    generator.climate_scale = 48.0;
+    generator.terrain.cave_threshold = 2.0;
+    generator.terrain.snow_level = 100;
+    map.generate_region_with(&generator, (-4, -1, -4), (3, 1, 3));
+
+    let [snow, ice, dirt, sand, sandstone] =
+        ["snow", "ice", "dirt", "sand", "sandstone"].map(voxel);
+    let (mut cold, mut hot, mut between) = (0, 0, 0);
+    for z in -64..64 {
+        for x in -64..64 {
+            let weights = generator.weights_at(x, z);
+            assert!((weights.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+            let height = generator.height_at(x, z);
+            assert!((0..=20).contains(&height));
+            match height {
+                0 => cold += 1,
+                20 => hot += 1,
+                _ => between += 1,
+            }
+
+            let surface = *map.get_voxel(x, height - 1, z).unwrap();
+            let above = *map.get_voxel(x, height, z).unwrap();
+            if surface == snow {
+                // Every cold column carries a decoration.
+                assert_eq!(above, ice);
+                assert_eq!(map.get_voxel(x, height + 1, z), Some(&ice));
+                assert_eq!(map.get_voxel(x, height - 2, z), Some(&dirt));
+            } else {
+                assert_eq!(surface, sand);
+                assert!(above.is_empty());
+                assert_eq!(map.get_voxel(x, height - 2, z), Some(&sandstone));
+            }
+        }
+    }
+    assert!(cold > 0 && hot > 0 && between > 0);
+    let strongest = generator.biome_at(-64, -64).name.clone();
+    assert!(strongest == "cold" || strongest == "hot");
+}
+
+#[test]
+fn zero_blend_gives_hard_borders() {
+    let materials = MaterialRegistry::builtin();
+    let table = BiomeTable::from_toml(TWO_BIOMES, &materials).unwrap();
+    let mut generator = BiomeGenerator::new(WorldSeed::new(4), table, &materials);
+    generator.climate_scale = 48.0;
+    for blend in [0.0, 1e-300, f64::NAN] {
+        generator.blend = blend;
+        for x in -64..64 {
+            let weights = generator.weights_at(x, 3 * x);
+            assert!(
+                weights == [1.0, 0.0] || weights == [0.0, 1.0],
+                "{:?}",
+                weights
+            );
+            assert!([0, 20].contains(&generator.height_at(x, 3 * x)));
+        }
+    }
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use common::voxel;
+use nalgebra::Vector3;
+use std::collections::HashSet;
+use torus::brush::{line, CsgOperation, Shape};
+use torus::map::Map;
+
+fn solid_count(map: &Map) -> usize {
+    map.chunks
+        .values()
+        .map(|chunk| {
+            let mut count = 0;
+            for z in 0..16 {
+                for y in 0..16 {
+                    for x in 0..16 {
+                        if !chunk.get_voxel(x, y, z).unwrap().is_empty() {
+                            count += 1;
+                        }
+                    }
+                }
+            }
+            count
+        })
+        .sum()
+}
+
+#[test]
+fn fill_box_reports_touched_chunks() {
+    let mut map = Map::new();
+    let shape = Shape::Box {
+        min: Vector3::new(-2, 0, 0),
+        max: Vector3::new(17, 3, 3),
+    };
+    let touched = map.fill_shape(&shape, voxel("stone"));
+    assert_eq!(touched, HashSet::from([(-1, 0, 0), (0, 0, 0), (1, 0, 0)]));
+    assert_eq!(solid_count(&map), 20 * 4 * 4);
+
+    // Filling again changes nothing.
+    assert!(map.fill_shape(&shape, voxel("stone")).is_empty());
+
+    let touched = map.clear_shape(&Shape::Box {
+        min: Vector3::new(0, 0, 0),
+        max: Vector3::new(15, 3, 3),
+    });
+    assert_eq!(touched, HashSet::from([(0, 0, 0)]));
+    assert_eq!(solid_count(&map), 4 * 4 * 4);
+}
+
+#[test]
+fn round_shapes_are_symmetric() {
+    let shapes = [
+        Shape::Sphere {
+            center: Vector3::repeat(0.0),
+            radius: 5.0,
+        },
+        Shape::Ellipsoid {
+            center: Vector3::repeat(0.0),
+            radii: Vector3::new(6.0, 3.0, 4.0),
+        },
+        Shape::Cylinder {
+            base: Vector3::new(0.0, -4.0, 0.0),
+            top: Vector3::new(0.0, 4.0, 0.0),
+            radius: 3.0,
+        },
+    ];
+    for shape in shapes {
+        let mut map = Map::new();
+        map.fill_shape(&shape, voxel("sand"));
+        assert!(solid_count(&map) > 0);
+        let (min, max) = shape.bounds();
+        for z in min.z..=max.z {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    let solid = |x: i32, y: i32, z: i32| {
+                        map.get_voxel(x, y, z).is_some_and(|v| !v.is_empty())
+                    };
+                    assert_eq!(solid(x, y, z), solid(-1 - x, -1 - y, -1 - z), "{:?}", shape);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn lines_are_connected() {
+    let from = Vector3::new(-3, 20, 7);
+    let to = Vector3::new(12, -5, 1);
+    let voxels = line(from, to);
+    assert_eq!(voxels.first(), Some(&from));
+    assert_eq!(voxels.last(), Some(&to));
+    assert_eq!(voxels.len(), 26);
+    for pair in voxels.windows(2) {
+        assert!((pair[1] - pair[0]).abs().max() == 1);
+    }
+
+    let mut map = Map::new();
+    let touched = map.fill_line(from, to, voxel("dirt"));
+    assert_eq!(solid_count(&map), voxels.len());
+    assert!(touched.contains(&(-1, 1, 0)) && touched.contains(&(0, -1, 0)));
+}
+
+#[test]
+fn sdf_operations_combine_with_existing_voxels() {
+    let mut map = Map::new();
+    let (min, max) = (Vector3::repeat(0), Vector3::repeat(15));
+    map.fill_shape(&Shape::Box { min, max }, voxel("stone"));
+
+    // Keep only the lower half of the chunk.
+    let touched = map.apply_sdf(min, max, CsgOperation::Intersect, |p| p.y - 8.0);
+    assert_eq!(touched, HashSet::from([(0, 0, 0)]));
+    assert_eq!(solid_count(&map), 16 * 16 * 8);
+
+    // Carve a ball out of it and fill its top with sand.
+    let ball = |p: Vector3<f32>| (p - Vector3::repeat(8.0)).norm() - 4.0;
+    map.apply_sdf(min, max, CsgOperation::Subtract, ball);
+    assert!(map.get_voxel(8, 7, 8).unwrap().is_empty());
+    map.apply_sdf(min, max, CsgOperation::Union(voxel("sand")), |p| {
+        ball(p).max(8.0 - p.y)
+    });
+    assert_eq!(map.get_voxel(8, 10, 8), Some(&voxel("sand")));
+    assert!(map.get_voxel(8, 7, 8).unwrap().is_empty());
+}
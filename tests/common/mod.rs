@@ -0,0 +1,96 @@
+#![allow(dead_code)]
+
+use image::{Rgba, RgbaImage};
+use std::path::PathBuf;
+use torus::map::Map;
+use torus::material::MaterialRegistry;
+use torus::seed::WorldSeed;
+use torus::voxel::Voxel;
+
+/// Maximum difference allowed on any channel of a pixel before it counts as a mismatch.
+pub const PIXEL_TOLERANCE: u8 = 2;
+
+/// Builds a 3x3x3-chunk cave map around the origin, small enough to generate quickly in debug.
+pub fn test_map(seed: u64) -> Map {
+    let mut map = Map::new();
+    map.generate_region(WorldSeed::new(seed), (-1, -1, -1), (1, 1, 1));
+    map
+}
+
+/// A voxel of the named built-in material.
+pub fn voxel(name: &str) -> Voxel {
+    MaterialRegistry::builtin()
+        .voxel(name)
+        .unwrap_or_else(|| panic!("no built-in material {}", name))
+}
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn output_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/golden")
+}
+
+/// Compares `actual` against `tests/golden/<name>.png`.
+///
+/// Run with `TORUS_BLESS=1` to (re)write the reference image instead. On failure the actual
+/// frame and a diff image highlighting mismatching pixels are written to `target/golden`.
+pub fn assert_golden(name: &str, actual: &RgbaImage) {
+    let reference_path = golden_dir().join(format!("{}.png", name));
+    if std::env::var_os("TORUS_BLESS").is_some() {
+        actual.save(&reference_path).unwrap();
+        return;
+    }
+
+    let expected = image::open(&reference_path)
+        .unwrap_or_else(|e| {
+            panic!(
+                "missing reference {} ({}), run with TORUS_BLESS=1 to create it",
+                reference_path.display(),
+                e
+            )
+        })
+        .to_rgba8();
+    assert_eq!(
+        expected.dimensions(),
+        actual.dimensions(),
+        "{}: image size differs from reference",
+        name
+    );
+
+    let mut diff = RgbaImage::new(actual.width(), actual.height());
+    let mut mismatches = 0;
+    for (x, y, pixel) in actual.enumerate_pixels() {
+        let reference = expected.get_pixel(x, y);
+        let matches = pixel
+            .0
+            .iter()
+            .zip(reference.0.iter())
+            .all(|(a, b)| a.abs_diff(*b) <= PIXEL_TOLERANCE);
+        if matches {
+            let luma = (pixel[0] as u32 + pixel[1] as u32 + pixel[2] as u32) / 12;
+            diff.put_pixel(x, y, Rgba([luma as u8, luma as u8, luma as u8, 255]));
+        } else {
+            mismatches += 1;
+            diff.put_pixel(x, y, Rgba([255, 0, 255, 255]));
+        }
+    }
+
+    if mismatches > 0 {
+        let dir = output_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let actual_path = dir.join(format!("{}-actual.png", name));
+        let diff_path = dir.join(format!("{}-diff.png", name));
+        actual.save(&actual_path).unwrap();
+        diff.save(&diff_path).unwrap();
+        panic!(
+            "{}: {} pixels differ from reference by more than {}, see {} and {}",
+            name,
+            mismatches,
+            PIXEL_TOLERANCE,
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
@@ -0,0 +1,160 @@
+mod common;
+
+use common::{test_map, voxel};
+use nalgebra::Vector3;
+use torus::chunk::Chunk;
+use torus::map::Map;
+use torus::renderer::Renderer;
+
+/// Exact distance from a voxel to the nearest solid voxel within `limit`, by brute force.
+fn true_distance(map: &Map, x: i32, y: i32, z: i32, limit: i32) -> f32 {
+    let mut best = f32::INFINITY;
+    for dz in -limit..=limit {
+        for dy in -limit..=limit {
+            for dx in -limit..=limit {
+                if map
+                    .get_voxel(x + dx, y + dy, z + dz)
+                    .is_some_and(|voxel| !voxel.is_empty())
+                {
+                    best = best.min(((dx * dx + dy * dy + dz * dz) as f32).sqrt());
+                }
+            }
+        }
+    }
+    best
+}
+
+#[test]
+fn distance_maps_never_overestimate() {
+    let radius = 4;
+    let mut map = test_map(3);
+    map.generate_all_distance_maps(radius);
+
+    // The center chunk is surrounded by loaded chunks, the corner one borders unloaded space.
+    for position in [(0, 0, 0), (1, -1, 1)] {
+        let chunk = &map.chunks[&position];
+        for z in 0..16 {
+            for y in 0..16 {
+                for x in 0..16 {
+                    let world = (
+                        position.0 * 16 + x as i32,
+                        position.1 * 16 + y as i32,
+                        position.2 * 16 + z as i32,
+                    );
+                    let stored = chunk.get_distance(x, y, z);
+                    let exact = true_distance(&map, world.0, world.1, world.2, radius);
+                    assert!(
+                        stored as f32 <= exact,
+                        "distance {} at {:?} overestimates the true distance {}",
+                        stored,
+                        world,
+                        exact
+                    );
+                    assert_eq!(stored, exact.min(radius as f32) as u8, "at {:?}", world);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn distance_maps_see_solids_in_neighbor_chunks() {
+    let mut map = Map::new();
+    let mut chunk = Chunk::new((1, 0, 0));
+    chunk.set(0, 8, 8, voxel("stone"));
+    map.set(16, 0, 0, chunk);
+    let mut chunk = Chunk::new((0, 0, 0));
+    chunk.set(0, 0, 0, voxel("stone"));
+    map.set(0, 0, 0, chunk);
+    map.generate_all_distance_maps(6);
+
+    let chunk = map.get(0, 0, 0).unwrap();
+    assert_eq!(chunk.get_distance(0, 0, 0), 0);
+    assert_eq!(chunk.get_distance(15, 8, 8), 1);
+    assert_eq!(chunk.get_distance(13, 8, 8), 3);
+    assert_eq!(chunk.get_distance(13, 10, 8), 3);
+    assert_eq!(chunk.get_distance(8, 8, 8), 6);
+}
+
+/// Checks every distance map against a regeneration from scratch.
+fn assert_distances_match_regenerated(map: &Map) {
+    let mut expected = map.clone();
+    expected.generate_all_distance_maps(map.distance_radius);
+    for (position, chunk) in &map.chunks {
+        let reference = &expected.chunks[position];
+        for z in 0..16 {
+            for y in 0..16 {
+                for x in 0..16 {
+                    assert_eq!(
+                        chunk.get_distance(x, y, z),
+                        reference.get_distance(x, y, z),
+                        "stale distance in chunk {:?} at ({}, {}, {})",
+                        position,
+                        x,
+                        y,
+                        z
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn set_voxel_updates_distance_maps_incrementally() {
+    let mut map = test_map(5);
+    map.generate_all_distance_maps(4);
+
+    // Place next to a chunk corner so several neighbors are affected, then carve one out.
+    map.set_voxel(15, 15, 15, voxel("stone"));
+    map.set_voxel(-3, 7, 2, voxel("dirt"));
+    assert_distances_match_regenerated(&map);
+
+    let solid = (-16..32)
+        .flat_map(|x| (-16..32).map(move |y| (x, y)))
+        .find(|&(x, y)| !map.get_voxel(x, y, 0).unwrap().is_empty())
+        .unwrap();
+    assert!(!map.remove_voxel(solid.0, solid.1, 0).is_empty());
+    assert_distances_match_regenerated(&map);
+}
+
+#[test]
+fn batch_edits_update_distance_maps() {
+    let mut map = test_map(5);
+    map.generate_all_distance_maps(3);
+
+    // A wall crossing chunk borders plus a voxel in a chunk that does not exist yet.
+    let wall = (-10..20).flat_map(|y| (-10..20).map(move |z| ((4, y, z), voxel("stone"))));
+    let changed = map.set_voxels(wall.chain([((40, 0, 0), voxel("sand"))]));
+    assert!(changed > 0);
+    assert!(map.get(40, 0, 0).is_some());
+    assert_distances_match_regenerated(&map);
+
+    map.remove_voxels((-10..20).map(|y| (4, y, 3)));
+    assert_distances_match_regenerated(&map);
+}
+
+#[test]
+fn rays_never_skip_newly_placed_voxels() {
+    let mut map = Map::new();
+    let mut chunk = Chunk::new((0, 0, 0));
+    chunk.set(15, 15, 15, voxel("stone"));
+    map.set(0, 0, 0, chunk);
+    map.generate_all_distance_maps(8);
+    assert_eq!(map.get_distance(2, 8, 8), 8);
+
+    let mut renderer = Renderer::new(map, 1, 1, 1);
+    let origin = Vector3::new(0.5, 8.5, 8.5);
+    let direction = Vector3::new(1.0, 0.0, 0.0);
+    assert!(renderer.dda(&origin, &direction, 64).is_none());
+
+    renderer.map.set_voxel(6, 8, 8, voxel("stone"));
+    let hit = renderer.dda(&origin, &direction, 64).unwrap();
+    assert_eq!(hit.position, Vector3::new(6, 8, 8));
+
+    // Same through the voxel guard, closer to the origin.
+    *renderer.map.get_voxel_mut(3, 8, 8).unwrap() = voxel("stone");
+    let hit = renderer.dda(&origin, &direction, 64).unwrap();
+    assert_eq!(hit.position, Vector3::new(3, 8, 8));
+    assert_distances_match_regenerated(&renderer.map);
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use common::voxel;
+use std::sync::Arc;
+use torus::feature::{FeatureError, FeatureSet};
+use torus::generator::{FeatureGenerator, FlatGenerator};
+use torus::map::Map;
+use torus::material::MaterialRegistry;
+use torus::seed::WorldSeed;
+
+// A post with a flag reaching 3 voxels along x, and gold underground.
+const FLAGS: &str = r#"
+[[structure]]
+name = "flag"
+palette = { "|" = "log", "*" = "leaves" }
+layers = [
+    ["|..."],
+    ["****"],
+]
+
+[[feature]]
+structure = "flag"
+on = ["grass"]
+density = 0.05
+
+[[vein]]
+material = "gold_ore"
+replaces = ["stone"]
+size = 12
+count = 3
+max_y = -4
+"#;
+
+fn flag_world() -> Map {
+    let mut map = Map::new();
+    let features = FeatureSet::from_toml(FLAGS, &map.materials).unwrap();
+    let flat = Arc::new(FlatGenerator::new(&map.materials));
+    let generator = FeatureGenerator::new(WorldSeed::new(5), flat, features);
+    map.generate_region_with(&generator, (-2, -2, -2), (1, 0, 1));
+    map
+}
+
+#[test]
+fn feature_sets_are_data() {
+    let materials = MaterialRegistry::builtin();
+    let builtin = FeatureSet::builtin(&materials).unwrap();
+    for name in ["oak", "spruce", "boulder"] {
+        assert!(builtin.structure(name).is_some(), "{}", name);
+    }
+    assert!(!builtin.veins.is_empty());
+
+    let set = FeatureSet::from_toml(FLAGS, &materials).unwrap();
+    let flag = set.structure("flag").unwrap();
+    assert_eq!(flag.voxels.len(), 5);
+    assert!(flag.voxels.contains(&((0, 0, 0), voxel("log"))));
+    assert!(flag.voxels.contains(&((3, 1, 0), voxel("leaves"))));
+    assert_eq!(flag.reach(), 3);
+    assert_eq!(flag.vertical_extent(), (0, 1));
+    assert!(flag
+        .rotated(1)
+        .any(|placed| placed == ((0, 1, 3), voxel("leaves"))));
+    assert_eq!(set.features[0].on, vec![voxel("grass")]);
+
+    let typo = FLAGS.replace("\"****\"", "\"**x*\"");
+    assert!(matches!(
+        FeatureSet::from_toml(&typo, &materials),
+        Err(FeatureError::InvalidTemplate { structure, character: 'x' }) if structure == "flag"
+    ));
+    let missing = FLAGS.replace("structure = \"flag\"", "structure = \"banner\"");
+    assert!(matches!(
+        FeatureSet::from_toml(&missing, &materials),
+        Err(FeatureError::UnknownStructure(name)) if name == "banner"
+    ));
+}
+
+#[test]
+fn structures_span_chunk_boundaries() {
+    let map = flag_world();
+    let (log, leaves) = (voxel("log"), voxel("leaves"));
+    let mut crossing = 0;
+    for z in -32..32 {
+        for x in -32..32 {
+            if map.get_voxel(x, 0, z) == Some(&log) {
+                for flag_x in x..x + 4 {
+                    if flag_x < 32 {
+                        assert_eq!(map.get_voxel(flag_x, 1, z), Some(&leaves));
+                    }
+                }
+                if x.rem_euclid(16) >= 13 {
+                    crossing += 1;
+                }
+            }
+            if map.get_voxel(x, 1, z) == Some(&leaves) {
+                // Flags of posts outside the region can reach into it.
+                let posts = (x - 3..=x).filter(|x| map.get_voxel(*x, 0, z) == Some(&log));
+                assert!(posts.count() > 0 || x < -29, "({}, {})", x, z);
+            }
+        }
+    }
+    assert!(crossing > 0);
+}
+
+#[test]
+fn veins_only_replace_their_materials() {
+    let map = flag_world();
+    let [gold, grass, dirt, stone] = ["gold_ore", "grass", "dirt", "stone"].map(voxel);
+    let mut veins = 0;
+    for z in -32..32 {
+        for y in -32..0 {
+            for x in -32..32 {
+                let current = *map.get_voxel(x, y, z).unwrap();
+                match y {
+                    -1 => assert_eq!(current, grass),
+                    -4..=-2 => assert_eq!(current, dirt),
+                    _ if current == gold => veins += 1,
+                    _ => assert_eq!(current, stone),
+                }
+            }
+        }
+    }
+    assert!(veins > 0);
+}
@@ -0,0 +1,143 @@
+mod common;
+
+use common::{assert_golden, voxel};
+use nalgebra::Vector3;
+use noise::NoiseFn;
+use std::sync::Arc;
+use torus::chunk::Chunk;
+use torus::generator::{
+    DebugGenerator, FlatGenerator, PerlinCaves, TerrainGenerator, WorldGenerator,
+};
+use torus::map::Map;
+use torus::material::MaterialRegistry;
+use torus::perlin::PerlinGenerator;
+use torus::renderer::Renderer;
+use torus::seed::WorldSeed;
+use torus::streaming::ChunkStreamer;
+
+#[test]
+fn chunks_only_depend_on_their_position() {
+    let seed = WorldSeed::new(11);
+    let mut map = Map::new();
+    map.generate_region(seed, (-1, -1, -1), (1, 1, 1));
+
+    let caves = PerlinCaves::new(seed, &MaterialRegistry::builtin());
+    for position in [(-1, 0, 1), (1, 1, 1), (0, -1, 0)] {
+        let mut chunk = Chunk::new(position);
+        caves.generate_chunk(position, &mut chunk);
+        chunk.compact();
+        let expected = map.get(position.0 * 16, position.1 * 16, position.2 * 16);
+        assert_eq!(expected.is_none_or(Chunk::is_empty), chunk.is_empty());
+        if let Some(expected) = expected {
+            for z in 0..16 {
+                for y in 0..16 {
+                    for x in 0..16 {
+                        assert_eq!(chunk.get_voxel(x, y, z), expected.get_voxel(x, y, z));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn flat_worlds_stack_layers() {
+    let mut map = Map::new();
+    let flat = FlatGenerator::new(&map.materials);
+    map.generate_region_with(&flat, (-1, -2, -1), (0, 1, 0));
+
+    assert!(map.get_voxel(5, 0, -3).unwrap().is_empty());
+    assert_eq!(map.get_voxel(5, -1, -3), Some(&voxel("grass")));
+    for y in -4..=-2 {
+        assert_eq!(map.get_voxel(-7, y, 9), Some(&voxel("dirt")));
+    }
+    assert_eq!(map.get_voxel(0, -5, 0), Some(&voxel("stone")));
+    assert_eq!(map.get_voxel(-16, -32, -16), Some(&voxel("stone")));
+    // Chunks above the ground are known to be empty.
+    assert!(map.empty_chunks.contains(&(0, 1, 0)));
+    assert!(map.empty_chunks.contains(&(-1, 0, -1)));
+}
+
+#[test]
+fn debug_world_marks_axes() {
+    let mut map = Map::new();
+    let debug = DebugGenerator::new(&map.materials);
+    map.generate_region_with(&debug, (-1, -1, -1), (1, 1, 1));
+
+    assert_eq!(map.get_voxel(0, -1, 0), Some(&voxel("stone")));
+    assert_eq!(map.get_voxel(4, -1, 0), Some(&voxel("snow")));
+    assert_eq!(map.get_voxel(-1, -1, 0), Some(&voxel("snow")));
+    assert_eq!(map.get_voxel(8, 0, 0), Some(&voxel("lava")));
+    assert_eq!(map.get_voxel(0, 3, 0), Some(&voxel("grass")));
+    assert_eq!(map.get_voxel(0, 0, 1), Some(&voxel("gold_ore")));
+    assert!(map.get_voxel(0, 0, 0).unwrap().is_empty());
+    assert!(map.get_voxel(9, 0, 0).unwrap().is_empty());
+    assert!(map.get_voxel(-1, 0, 0).unwrap().is_empty());
+
+    map.generate_all_distance_maps(3);
+    let renderer = Renderer::new(map, 96, 72, 4);
+    let image = renderer.render_image(Vector3::new(-6.0, 5.0, -6.0), Vector3::new(0.5, 0.8, 0.0));
+    assert_golden("debug_world", &image);
+}
+
+#[test]
+fn streamers_use_the_given_generator() {
+    let mut map = Map::new();
+    let mut streamer = ChunkStreamer::new(1);
+    streamer.set_generator(Arc::new(FlatGenerator::new(&map.materials)));
+    streamer
+        .load_all(&mut map, Vector3::new(8.0, 8.0, 8.0))
+        .unwrap();
+    assert_eq!(map.get_voxel(3, -1, 3), Some(&voxel("grass")));
+    assert!(map.get_voxel(3, 0, 3).unwrap().is_empty());
+}
+
+#[test]
+fn fbm_sums_scaled_octaves() {
+    let perlin = PerlinGenerator::new(5);
+    let noise = |x: f64, z: f64| perlin.noise.get([x, z]);
+    for i in 0..50 {
+        let (x, z) = (i as f64 * 0.37, 1.3 - i as f64 * 0.11);
+        assert!((perlin.fbm_2d(x, z, 1, 2.0, 0.5) - noise(x, z)).abs() < 1e-12);
+        let expected = (noise(x, z) + 0.4 * noise(3.0 * x, 3.0 * z)) / 1.4;
+        assert!((perlin.fbm_2d(x, z, 2, 3.0, 0.4) - expected).abs() < 1e-12);
+        assert!((-1.0..=1.0).contains(&perlin.fbm_2d(x, z, 6, 2.0, 0.5)));
+    }
+    assert_eq!(perlin.fbm_2d(0.3, 0.7, 0, 2.0, 0.5), 0.0);
+}
+
+#[test]
+fn terrain_columns_are_layered() {
+    let mut map = Map::new();
+    let mut terrain = TerrainGenerator::new(WorldSeed::new(8), &map.materials);
+    terrain.amplitude = 12.0;
+    terrain.sand_level = -3;
+    terrain.snow_level = 4;
+    map.generate_region_with(&terrain, (-2, -2, -2), (1, 1, 1));
+
+    let mut surfaces = std::collections::HashSet::new();
+    let mut caves = 0;
+    for z in -32..32 {
+        for x in -32..32 {
+            let height = terrain.height_at(x, z);
+            assert!(map.get_voxel(x, height, z).unwrap().is_empty());
+            let surface = *map.get_voxel(x, height - 1, z).unwrap();
+            assert_eq!(surface, terrain.layer_at(height, 0));
+            surfaces.insert(surface);
+            for depth in 1..=terrain.subsurface_depth {
+                let expected = terrain.layer_at(height, depth);
+                assert_eq!(map.get_voxel(x, height - 1 - depth, z), Some(&expected));
+            }
+            caves += (-32..height - 1 - terrain.cave_depth)
+                .filter(|y| map.get_voxel(x, *y, z).unwrap().is_empty())
+                .count();
+        }
+    }
+    assert_eq!(
+        surfaces,
+        ["sand", "grass", "snow"].map(voxel).into_iter().collect()
+    );
+    assert!(caves > 0);
+    assert_eq!(terrain.layer_at(0, 40), voxel("stone"));
+    assert_eq!(terrain.layer_at(-5, 2), voxel("sandstone"));
+}
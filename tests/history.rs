@@ -0,0 +1,189 @@
+mod common;
+
+use common::{test_map, voxel};
+use nalgebra::Vector3;
+use torus::brush::Shape;
+use torus::chunk::Chunk;
+use torus::history::{History, Transaction, VoxelChange};
+use torus::map::Map;
+
+#[test]
+fn undo_and_redo_single_edits() {
+    let mut map = Map::new();
+    map.set_voxel(1, 2, 3, voxel("stone"));
+    map.set_voxel(1, 2, 3, voxel("sand"));
+    map.remove_voxel(1, 2, 3);
+
+    assert!(map.undo());
+    assert_eq!(map.get_voxel(1, 2, 3), Some(&voxel("sand")));
+    assert!(map.undo());
+    assert_eq!(map.get_voxel(1, 2, 3), Some(&voxel("stone")));
+    assert!(map.redo());
+    assert_eq!(map.get_voxel(1, 2, 3), Some(&voxel("sand")));
+
+    // A new edit forgets what could be redone.
+    map.set_voxel(0, 0, 0, voxel("dirt"));
+    assert!(!map.redo());
+    assert!(map.undo() && map.undo() && map.undo());
+    assert!(!map.undo());
+    assert!(map.get_voxel(1, 2, 3).unwrap().is_empty());
+}
+
+#[test]
+fn transactions_group_edits() {
+    let mut map = Map::new();
+    map.begin_transaction();
+    map.set_voxel(0, 0, 0, voxel("stone"));
+    map.fill_shape(
+        &Shape::Sphere {
+            center: Vector3::repeat(20.0),
+            radius: 3.0,
+        },
+        voxel("sand"),
+    );
+    map.begin_transaction();
+    map.set_voxel(0, 0, 0, voxel("dirt"));
+    map.commit_transaction();
+    map.commit_transaction();
+
+    assert_eq!(map.history.undo_transactions().count(), 1);
+    assert!(map.undo());
+    assert!(map.get_voxel(0, 0, 0).unwrap().is_empty());
+    assert!(map.get_voxel(20, 20, 20).unwrap().is_empty());
+    assert!(map.redo());
+    assert_eq!(map.get_voxel(0, 0, 0), Some(&voxel("dirt")));
+    assert_eq!(map.get_voxel(20, 20, 20), Some(&voxel("sand")));
+}
+
+#[test]
+fn chunk_and_guard_writes_are_journaled() {
+    let mut map = test_map(3);
+    map.generate_all_distance_maps(3);
+    assert!(!map.history.can_undo());
+    let original = map.clone();
+
+    let mut chunk = Chunk::new((0, 0, 0));
+    chunk.set(4, 4, 4, voxel("gold_ore"));
+    map.set(0, 0, 0, chunk);
+    *map.get_voxel_mut(-5, 7, 9).unwrap() = voxel("lava");
+    map.get_mut(20, 3, 3).unwrap().set(1, 2, 3, voxel("stone"));
+    map.entry_chunk(-10, 20, 0).set(0, 0, 0, voxel("sand"));
+    assert_eq!(map.history.undo_transactions().count(), 4);
+    // Only reading through the guard changes nothing.
+    assert!(map.get_mut(0, 0, 0).unwrap().get_voxel(4, 4, 4).is_some());
+    assert_eq!(map.history.undo_transactions().count(), 4);
+
+    for _ in 0..4 {
+        assert!(map.undo());
+    }
+    for z in -16..32 {
+        for y in -16..32 {
+            for x in -16..32 {
+                assert_eq!(map.get_voxel(x, y, z), original.get_voxel(x, y, z));
+                assert_eq!(map.get_distance(x, y, z), original.get_distance(x, y, z));
+            }
+        }
+    }
+}
+
+#[test]
+fn history_stays_within_budget() {
+    let change = VoxelChange {
+        position: (0, 0, 0),
+        before: voxel("air"),
+        after: voxel("stone"),
+    };
+    let transaction = Transaction {
+        changes: vec![change; 100],
+    };
+    let budget = transaction.memory_usage() * 3;
+    let mut history = History::from_transactions(budget, vec![transaction; 5], Vec::new());
+    assert_eq!(history.undo_transactions().count(), 3);
+
+    history.record(vec![change; 100]);
+    assert_eq!(history.undo_transactions().count(), 3);
+    assert!(history.memory_usage() <= budget);
+}
+
+#[test]
+fn oversized_transactions_stay_undoable() {
+    let mut map = Map::new();
+    map.history = History::new(64);
+    map.set_voxel(0, 0, 0, voxel("dirt"));
+    let edits: Vec<_> = (0..100).map(|x| ((x, 1, 0), voxel("stone"))).collect();
+    map.set_voxels(edits);
+    assert_eq!(map.history.undo_transactions().count(), 1);
+
+    assert!(map.undo());
+    assert!(map.get_voxel(50, 1, 0).unwrap().is_empty());
+    // The older edit made room for it.
+    assert!(!map.undo());
+    assert_eq!(map.get_voxel(0, 0, 0), Some(&voxel("dirt")));
+}
+
+#[test]
+fn open_transactions_count_towards_the_budget() {
+    let change = VoxelChange {
+        position: (0, 0, 0),
+        before: voxel("air"),
+        after: voxel("stone"),
+    };
+    let transaction = Transaction {
+        changes: vec![change; 100],
+    };
+    let budget = transaction.memory_usage() * 3;
+    let mut history = History::from_transactions(budget, vec![transaction; 3], Vec::new());
+
+    history.begin();
+    history.record(vec![change; 150]);
+    assert!(history.memory_usage() <= budget);
+    assert_eq!(history.undo_transactions().count(), 1);
+    history.record(vec![change; 300]);
+    assert_eq!(history.undo_transactions().count(), 0);
+    assert!(history.can_undo());
+
+    history.commit();
+    assert_eq!(history.undo_transactions().count(), 1);
+    assert_eq!(
+        history.undo_transactions().next().unwrap().changes.len(),
+        450
+    );
+}
+
+#[test]
+fn memory_usage_follows_every_operation() {
+    let change = VoxelChange {
+        position: (0, 0, 0),
+        before: voxel("air"),
+        after: voxel("stone"),
+    };
+    let mut history = History::new(usize::MAX);
+    let recount = |history: &History| -> usize {
+        history
+            .undo_transactions()
+            .chain(history.redo_transactions())
+            .map(Transaction::memory_usage)
+            .sum()
+    };
+    for round in 1..20 {
+        history.record(vec![change; round]);
+        history.begin();
+        history.record(vec![change; 3]);
+        history.record(vec![change; round * 2]);
+        history.commit();
+        history.begin();
+        history.commit();
+        assert_eq!(history.memory_usage(), recount(&history));
+
+        for _ in 0..round % 3 {
+            let transaction = history.pop_undo().unwrap();
+            history.push_redo(transaction);
+        }
+        if let Some(transaction) = history.pop_redo() {
+            history.push_undo(transaction);
+        }
+        assert_eq!(history.memory_usage(), recount(&history));
+    }
+    history.clear();
+    assert_eq!(history.memory_usage(), 0);
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use common::voxel;
+use nalgebra::Vector3;
+use torus::chunk::Chunk;
+use torus::map::{ChunkState, Map};
+use torus::renderer::Renderer;
+
+#[test]
+fn empty_chunks_are_known_but_not_stored() {
+    let mut map = Map::new();
+    map.set(0, 0, 0, Chunk::new((0, 0, 0)));
+
+    assert!(map.chunks.is_empty());
+    assert!(map.is_within_bounds(5, 5, 5));
+    assert!(matches!(map.chunk_state(5, 5, 5), ChunkState::Empty));
+    assert!(matches!(map.chunk_state(16, 5, 5), ChunkState::Unloaded));
+    assert!(map.get_voxel(5, 5, 5).unwrap().is_empty());
+    assert!(map.get_voxel(16, 5, 5).is_none());
+
+    *map.get_voxel_mut(5, 5, 5).unwrap() = voxel("stone");
+    assert!(matches!(map.chunk_state(5, 5, 5), ChunkState::Loaded(_)));
+    assert_eq!(map.get_voxel(5, 5, 5), Some(&voxel("stone")));
+
+    *map.get_voxel_mut(5, 5, 5).unwrap() = voxel("air");
+    map.release_empty_chunks();
+    assert!(map.chunks.is_empty());
+    assert!(map.empty_chunks.contains(&(0, 0, 0)));
+}
+
+#[test]
+fn voxel_edits_create_chunks_on_demand() {
+    let mut map = Map::new();
+    assert!(map.set_voxel(-1, 40, 7, voxel("stone")).is_empty());
+    assert_eq!(map.chunks.len(), 1);
+    assert!(map.get(-1, 40, 7).is_some());
+    assert_eq!(map.get_voxel(-1, 40, 7), Some(&voxel("stone")));
+
+    assert_eq!(map.remove_voxel(-1, 40, 7), voxel("stone"));
+    assert!(map.get_voxel(-1, 40, 7).unwrap().is_empty());
+
+    // Clearing voxels of a chunk nobody created only records it as empty.
+    map.remove_voxel(100, 0, 0);
+    assert!(matches!(map.chunk_state(100, 0, 0), ChunkState::Empty));
+
+    map.entry_chunk(200, 0, 0).set(1, 1, 1, voxel("dirt"));
+    assert_eq!(map.get_voxel(193, 1, 1), Some(&voxel("dirt")));
+}
+
+#[test]
+fn batch_edits_span_chunks() {
+    let mut map = Map::new();
+    let line = (-20..20).map(|x| ((x, 3, 3), voxel("sand")));
+    assert_eq!(map.set_voxels(line), 40);
+    assert_eq!(map.chunks.len(), 4);
+    // Writing the same voxels again changes nothing.
+    assert_eq!(
+        map.set_voxels((-20..20).map(|x| ((x, 3, 3), voxel("sand")))),
+        0
+    );
+    assert_eq!(map.remove_voxels((-20..0).map(|x| (x, 3, 3))), 20);
+    assert!(map.get_voxel(-5, 3, 3).unwrap().is_empty());
+    assert_eq!(map.get_voxel(5, 3, 3), Some(&voxel("sand")));
+}
+
+/// Builds a row of chunks along x with a target voxel in the last one. Unless `sparse`, every
+/// chunk on the way holds a voxel in a corner away from the rays so none of them is empty.
+fn row_map(sparse: bool) -> Map {
+    let mut map = Map::new();
+    for x in 0..4 {
+        let mut chunk = Chunk::new((x, 0, 0));
+        if !sparse {
+            chunk.set(0, 15, 15, voxel("dirt"));
+        }
+        map.set(x * 16, 0, 0, chunk);
+    }
+    let mut target = Chunk::new((3, 0, 0));
+    for y in 0..16 {
+        for z in 0..16 {
+            target.set(9, y, z, voxel("stone"));
+        }
+    }
+    target.set(0, 15, 15, voxel("dirt"));
+    map.set(48, 0, 0, target);
+    map
+}
+
+#[test]
+fn dda_skips_empty_chunks_in_one_step() {
+    let sparse = Renderer::new(row_map(true), 1, 1, 1);
+    let dense = Renderer::new(row_map(false), 1, 1, 1);
+    assert_eq!(sparse.map.chunks.len(), 1);
+    assert_eq!(sparse.map.empty_chunks.len(), 3);
+
+    let directions = [
+        Vector3::new(1.0, 0.0, 0.0),
+        Vector3::new(1.0, 0.1, 0.05),
+        Vector3::new(1.0, -0.12, 0.13),
+        Vector3::new(0.8, 0.11, -0.09),
+    ];
+    for direction in directions {
+        let direction = direction.normalize();
+        let origin = Vector3::new(0.5, 7.5, 7.5);
+        let expected = dense.dda(&origin, &direction, 256).unwrap();
+        let actual = sparse.dda(&origin, &direction, 256).unwrap();
+        assert_eq!(actual.position, expected.position);
+        assert_eq!(actual.normal, expected.normal);
+        assert_eq!(actual.step_count, expected.step_count);
+        assert!((actual.distance - expected.distance).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use common::voxel;
+use nalgebra::Vector3;
+use torus::chunk::Chunk;
+use torus::map::Map;
+use torus::material::{Material, MaterialError, MaterialRegistry, AIR};
+use torus::renderer::Renderer;
+use torus::voxel::Voxel;
+
+#[test]
+fn registry_loads_materials_from_toml() {
+    let registry = MaterialRegistry::from_toml(
+        r#"
+        [[material]]
+        name = "glow"
+        albedo = [10, 20, 30]
+        emissive = 2.0
+
+        [[material]]
+        name = "glass"
+        albedo = [200, 220, 255]
+        opacity = 0.25
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(registry.len(), 3);
+    assert_eq!(registry.id("air"), Some(AIR));
+    let glow = registry.get(registry.id("glow").unwrap()).unwrap();
+    assert_eq!(glow.albedo, Vector3::new(10, 20, 30));
+    assert_eq!(glow.emissive, 2.0);
+    assert_eq!(glow.opacity, 1.0);
+    let glass = registry.get(registry.id("glass").unwrap()).unwrap();
+    assert_eq!(glass.opacity, 0.25);
+    assert_eq!(glass.roughness, 1.0);
+}
+
+#[test]
+fn registering_an_existing_name_keeps_its_id() {
+    let mut registry = MaterialRegistry::builtin();
+    let stone = registry.id("stone").unwrap();
+    let id = registry
+        .register(Material::new("stone", Vector3::new(1, 2, 3)))
+        .unwrap();
+    assert_eq!(id, stone);
+    assert_eq!(registry.get(stone).unwrap().albedo, Vector3::new(1, 2, 3));
+
+    assert!(matches!(
+        registry.register(Material::new("air", Vector3::new(0, 0, 0))),
+        Err(MaterialError::ReservedName(_))
+    ));
+}
+
+#[test]
+fn renderer_reads_material_properties_at_hit() {
+    let mut map = Map::new();
+    let dark = map
+        .materials
+        .register(Material::new("dark", Vector3::new(100, 100, 100)))
+        .unwrap();
+    let glowing = map
+        .materials
+        .register(Material {
+            emissive: 1.0,
+            ..Material::new("glowing", Vector3::new(100, 100, 100))
+        })
+        .unwrap();
+    let mut chunk = Chunk::new((0, 0, 0));
+    for y in 0..16 {
+        for x in 0..8 {
+            chunk.set(x, y, 8, Voxel::new(dark));
+        }
+        for x in 8..16 {
+            chunk.set(x, y, 8, Voxel::new(glowing));
+        }
+    }
+    map.set(0, 0, 0, chunk);
+
+    let renderer = Renderer::new(map, 2, 1, 1);
+    let frame = renderer.render_image(Vector3::new(8.0, 8.0, 7.5), Vector3::new(0.0, 0.0, 0.0));
+    let (dark_pixel, glowing_pixel) = (frame.get_pixel(0, 0), frame.get_pixel(1, 0));
+    assert!(glowing_pixel[0] > dark_pixel[0] + 50);
+}
+
+#[test]
+fn translucent_materials_show_what_is_behind() {
+    let mut chunk = Chunk::new((0, 0, 0));
+    chunk.set(8, 8, 8, voxel("water"));
+    chunk.set(8, 8, 10, voxel("sand"));
+    let mut map = Map::new();
+    map.set(0, 0, 0, chunk.clone());
+    let with_sand = Renderer::new(map, 1, 1, 1)
+        .render_image(Vector3::new(8.5, 8.5, 0.5), Vector3::new(0.0, 0.0, 0.0));
+
+    chunk.set(8, 8, 10, Voxel::empty());
+    let mut map = Map::new();
+    map.set(0, 0, 0, chunk);
+    let without_sand = Renderer::new(map, 1, 1, 1)
+        .render_image(Vector3::new(8.5, 8.5, 0.5), Vector3::new(0.0, 0.0, 0.0));
+
+    assert_ne!(with_sand.get_pixel(0, 0), without_sand.get_pixel(0, 0));
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use common::{test_map, voxel};
+use nalgebra::Vector3;
+use torus::brush::Shape;
+use torus::map::Map;
+
+#[test]
+fn boxes_merge_into_six_quads() {
+    let mut map = Map::new();
+    map.set_voxel(3, 4, 5, voxel("stone"));
+    assert_eq!(map.mesh_all().quads.len(), 6);
+
+    map.fill_shape(
+        &Shape::Box {
+            min: Vector3::new(1, 1, 1),
+            max: Vector3::new(4, 6, 12),
+        },
+        voxel("stone"),
+    );
+    let mesh = map.mesh_all();
+    assert_eq!(mesh.quads.len(), 6);
+    let area: f32 = mesh
+        .quads
+        .iter()
+        .map(|q| {
+            (q.corners[1] - q.corners[0])
+                .cross(&(q.corners[3] - q.corners[0]))
+                .norm()
+        })
+        .sum();
+    assert_eq!(area, 2.0 * (4.0 * 6.0 + 6.0 * 12.0 + 4.0 * 12.0));
+
+    // Corners wind counter-clockwise around the normal.
+    for quad in &mesh.quads {
+        let winding =
+            (quad.corners[1] - quad.corners[0]).cross(&(quad.corners[2] - quad.corners[0]));
+        assert!(winding.normalize().dot(&quad.normal) > 0.99);
+    }
+}
+
+#[test]
+fn faces_are_culled_across_chunks() {
+    let mut map = Map::new();
+    map.set_voxel(15, 0, 0, voxel("stone"));
+    map.set_voxel(16, 0, 0, voxel("stone"));
+    // Each chunk keeps the five faces that are not against the other voxel.
+    assert_eq!(map.mesh_chunk((0, 0, 0)).quads.len(), 5);
+    assert_eq!(map.mesh_chunk((1, 0, 0)).quads.len(), 5);
+
+    // Water is only meshed where it meets air, and stone still shows through it.
+    let mut map = Map::new();
+    for x in 0..4 {
+        map.set_voxel(x, 0, 0, voxel("water"));
+    }
+    map.set_voxel(4, 0, 0, voxel("stone"));
+    let mesh = map.mesh_all();
+    assert_eq!(mesh.quads.len(), 5 + 6);
+    assert_eq!(
+        mesh.quads
+            .iter()
+            .filter(|q| q.material == voxel("water").material)
+            .count(),
+        5
+    );
+}
+
+#[test]
+fn faces_towards_missing_chunks_are_kept() {
+    let directory = std::path::PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("mesh_regions");
+    let _ = std::fs::remove_dir_all(&directory);
+    let mut map = Map::new();
+    map.set_voxel(15, 0, 0, voxel("stone"));
+    map.set_voxel(16, 0, 0, voxel("stone"));
+    map.save_regions(&directory).unwrap();
+
+    let mut map = Map::open_regions(&directory, 1).unwrap();
+    map.fault_in(0, 0, 0).unwrap();
+    assert_eq!(map.mesh_chunk((0, 0, 0)).quads.len(), 6);
+}
+
+#[test]
+fn writers_describe_every_face() {
+    let map = test_map(2);
+    let mesh = map.mesh_all();
+    assert!(!mesh.quads.is_empty());
+
+    let mut obj = Vec::new();
+    mesh.write_obj(&mut obj).unwrap();
+    let obj = String::from_utf8(obj).unwrap();
+    assert_eq!(
+        obj.lines().filter(|l| l.starts_with("v ")).count(),
+        mesh.vertex_count()
+    );
+    assert_eq!(
+        obj.lines().filter(|l| l.starts_with("f ")).count(),
+        mesh.quads.len()
+    );
+
+    let mut ply = Vec::new();
+    mesh.write_ply(&mut ply).unwrap();
+    let header = b"end_header\n";
+    let end = ply.windows(header.len()).position(|w| w == header).unwrap() + header.len();
+    let text = std::str::from_utf8(&ply[..end]).unwrap();
+    assert!(text.contains(&format!("element face {}\n", mesh.quads.len())));
+    assert_eq!(
+        ply.len() - end,
+        mesh.vertex_count() * 27 + mesh.quads.len() * 17
+    );
+
+    let path = std::path::PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("mesh.gltf");
+    mesh.save(&path).unwrap();
+    let gltf = std::fs::read_to_string(&path).unwrap();
+    assert!(gltf.contains(&format!(
+        "\"count\":{},\"type\":\"SCALAR\"",
+        mesh.triangle_count() * 3
+    )));
+    assert!(mesh.save(&path.with_extension("stl")).is_err());
+}
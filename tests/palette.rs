@@ -0,0 +1,105 @@
+mod common;
+
+use common::voxel;
+use torus::chunk::Chunk;
+use torus::palette::PalettedArray;
+use torus::voxel::Voxel;
+
+#[test]
+fn paletted_array_round_trips_values() {
+    let mut array = PalettedArray::new(4096, 0u16);
+    assert_eq!(array.uniform(), Some(&0));
+
+    for index in 0..4096 {
+        array.set(index, (index % 300) as u16);
+    }
+    assert_eq!(array.uniform(), None);
+    assert_eq!(array.palette().len(), 300);
+    for index in 0..4096 {
+        assert_eq!(*array.get(index), (index % 300) as u16);
+    }
+}
+
+#[test]
+fn compact_collapses_uniform_arrays() {
+    let mut array = PalettedArray::new(4096, 0u8);
+    let uniform_size = array.memory_usage();
+    for index in 0..4096 {
+        array.set(index, 7);
+    }
+    array.compact();
+    assert_eq!(array.uniform(), Some(&7));
+    assert!(array.memory_usage() <= uniform_size);
+
+    array.set(10, 1);
+    array.set(11, 2);
+    array.set(11, 7);
+    array.compact();
+    assert_eq!(array.palette().len(), 2);
+    assert_eq!(*array.get(10), 1);
+    assert_eq!(*array.get(11), 7);
+}
+
+#[test]
+fn set_reuses_unreferenced_entries() {
+    // More distinct values over time than the widest indices can address.
+    let mut array = PalettedArray::new(64, 0u32);
+    for value in 1..70_000 {
+        array.set(value as usize % 2, value);
+    }
+    assert!(array.palette().len() <= 4);
+    assert_eq!(*array.get(0), 69_998);
+    assert_eq!(*array.get(1), 69_999);
+    assert_eq!(*array.get(2), 0);
+}
+
+#[test]
+fn unused_entries_are_tracked_without_compacting() {
+    let mut array = PalettedArray::new(4096, 0u8);
+    array.set(5, 1);
+    array.set(6, 2);
+    assert!(!array.can_shrink());
+
+    // 0 and 1 are left, which one bit per index can tell apart.
+    array.set(6, 0);
+    assert!(array.can_shrink());
+    array.set(5, 0);
+    assert_eq!(array.uniform(), Some(&0));
+
+    array.compact();
+    assert!(!array.can_shrink());
+    assert_eq!(array.palette(), &[0]);
+}
+
+#[test]
+fn chunk_storage_is_compressed() {
+    let empty = Chunk::new((0, 0, 0));
+    assert!(empty.is_empty());
+    assert!(empty.memory_usage() < 512);
+
+    let mut chunk = Chunk::new((0, 0, 0));
+    for x in 0..16 {
+        for z in 0..16 {
+            chunk.set(x, 0, z, voxel("stone"));
+            chunk.set(x, 1, z, voxel("dirt"));
+        }
+    }
+    assert!(!chunk.is_empty());
+    // Three materials fit in two bits per voxel.
+    assert!(chunk.memory_usage() < 4096 * 2 / 8 + 512);
+    assert_eq!(chunk.get_voxel(3, 1, 3), Some(&voxel("dirt")));
+}
+
+#[test]
+fn voxel_mut_writes_back_on_drop() {
+    let mut chunk = Chunk::new((0, 0, 0));
+    *chunk.get_voxel_mut(1, 2, 3).unwrap() = voxel("sand");
+    assert_eq!(chunk.get_voxel(1, 2, 3), Some(&voxel("sand")));
+
+    let mut guard = chunk.get_voxel_mut(1, 2, 3).unwrap();
+    *guard = Voxel::empty();
+    drop(guard);
+    chunk.compact();
+    assert!(chunk.is_empty());
+    assert!(chunk.get_voxel_mut(16, 0, 0).is_none());
+}
@@ -0,0 +1,233 @@
+mod common;
+
+use common::{test_map, voxel};
+use nalgebra::Vector3;
+use rayon::prelude::*;
+use std::path::PathBuf;
+use torus::chunk::Chunk;
+use torus::map::{ChunkState, Map};
+use torus::region::LEVEL_FILE;
+use torus::renderer::Renderer;
+use torus::world_file::WorldFileError;
+
+fn region_directory(name: &str) -> PathBuf {
+    let directory = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&directory);
+    directory
+}
+
+#[test]
+fn regions_load_chunks_lazily() {
+    let directory = region_directory("lazy_regions");
+    let mut original = test_map(21);
+    original.generate_all_distance_maps(3);
+    original.clone().save_regions(&directory).unwrap();
+
+    let mut map = Map::open_regions(&directory, 4).unwrap();
+    assert!(map.chunks.is_empty());
+    assert_eq!(map.seed, original.seed);
+    for z in -16..32 {
+        for x in -16..32 {
+            map.fault_in(x, 5, z).unwrap();
+            assert_eq!(map.get_voxel(x, 5, z), original.get_voxel(x, 5, z));
+            assert!(map.chunks.len() <= 4);
+        }
+    }
+    assert!(matches!(
+        map.fault_in(100, 0, 0).unwrap(),
+        ChunkState::Unloaded
+    ));
+}
+
+#[test]
+fn shared_readers_fault_chunks_in() {
+    let directory = region_directory("shared_regions");
+    let original = test_map(25);
+    original.clone().save_regions(&directory).unwrap();
+
+    let mut map = Map::open_regions(&directory, 4).unwrap();
+    let shared = &map;
+    (-16..32).into_par_iter().for_each(|z| {
+        for y in -16..32 {
+            for x in -16..32 {
+                assert_eq!(shared.get_voxel(x, y, z), original.get_voxel(x, y, z));
+            }
+        }
+    });
+    assert!(shared.get_voxel(100, 0, 0).is_none());
+    assert!(map.chunks.is_empty());
+
+    // The next mutable access takes them into the cache, which evicts down to its capacity.
+    map.fault_in(0, 0, 0).unwrap();
+    assert!(map.chunks.len() <= 4);
+    assert_eq!(map.get_voxel(-16, 5, 31), original.get_voxel(-16, 5, 31));
+}
+
+#[test]
+fn faulted_chunks_get_exact_distance_maps() {
+    let directory = region_directory("distance_regions");
+    let mut original = test_map(22);
+    original.generate_all_distance_maps(3);
+    original.clone().save_regions(&directory).unwrap();
+
+    let mut map = Map::open_regions(&directory, usize::MAX).unwrap();
+    map.load_area(Vector3::repeat(-16), Vector3::repeat(31))
+        .unwrap();
+    assert_eq!(map.chunks.len(), original.chunks.len());
+    for z in -16..32 {
+        for y in -16..32 {
+            for x in -16..32 {
+                assert_eq!(map.get_distance(x, y, z), original.get_distance(x, y, z));
+            }
+        }
+    }
+}
+
+#[test]
+fn caches_keep_the_chunk_in_use() {
+    let directory = region_directory("tiny_cache_regions");
+    let original = test_map(26);
+    original.clone().save_regions(&directory).unwrap();
+
+    let mut map = Map::open_regions(&directory, 0).unwrap();
+    assert!(matches!(
+        map.fault_in(0, 0, 0).unwrap(),
+        ChunkState::Loaded(_)
+    ));
+    map.entry_chunk(0, 0, 0).set(1, 1, 1, voxel("gold_ore"));
+    map.get_mut(-16, 0, 0)
+        .unwrap()
+        .set(1, 1, 1, voxel("gold_ore"));
+    for (x, y, z) in [(0, 0, 0), (5, 3, 7), (-16, 0, 0), (-10, 3, 7)] {
+        assert_eq!(map.get_voxel(x, y, z), original.get_voxel(x, y, z));
+    }
+    assert_eq!(map.get_voxel(1, 1, 1), Some(&voxel("gold_ore")));
+    assert_eq!(map.get_voxel(-15, 1, 1), Some(&voxel("gold_ore")));
+    assert_eq!(map.chunks.len(), 1);
+}
+
+#[test]
+fn edits_next_to_evicted_chunks_render_like_resident_ones() {
+    let directory = region_directory("evicted_neighbor_regions");
+    let stone = voxel("stone");
+    let mut original = Map::new();
+    let mut chunk = Chunk::new((0, 0, 0));
+    chunk.set(8, 8, 8, stone);
+    original.set(0, 0, 0, chunk);
+    let mut wall = Chunk::new((0, 0, 1));
+    for y in 0..16 {
+        for x in 0..16 {
+            wall.set(x, y, 0, stone);
+        }
+    }
+    original.set(0, 0, 16, wall);
+    original.generate_all_distance_maps(8);
+    original.clone().save_regions(&directory).unwrap();
+
+    let mut map = Map::open_regions(&directory, 1).unwrap();
+    map.fault_in(0, 0, 16).unwrap();
+    map.fault_in(0, 0, 0).unwrap();
+    assert!(!map.chunks.contains_key(&(0, 0, 1)));
+    map.set_voxel(2, 2, 12, stone);
+    original.set_voxel(2, 2, 12, stone);
+
+    let origin = Vector3::new(8.5, 4.5, 1.5);
+    let streamed = Renderer::new(map, 16, 16, 1).render_image(origin, Vector3::zeros());
+    let resident = Renderer::new(original, 16, 16, 1).render_image(origin, Vector3::zeros());
+    assert!(streamed == resident);
+}
+
+#[test]
+fn evicted_edits_are_written_back() {
+    let directory = region_directory("edited_regions");
+    test_map(23).save_regions(&directory).unwrap();
+
+    let mut map = Map::open_regions(&directory, 2).unwrap();
+    let edits: Vec<_> = (-16..32)
+        .step_by(8)
+        .map(|v| ((v, v, -v), voxel("gold_ore")))
+        .collect();
+    for (position, voxel) in &edits {
+        map.set_voxel(position.0, position.1, position.2, *voxel);
+        assert!(map.chunks.len() <= 2);
+    }
+    for ((x, y, z), voxel) in &edits {
+        map.fault_in(*x, *y, *z).unwrap();
+        assert_eq!(map.get_voxel(*x, *y, *z), Some(voxel));
+    }
+
+    map.save_regions(&directory).unwrap();
+    let mut reopened = Map::open_regions(&directory, 2).unwrap();
+    for ((x, y, z), voxel) in &edits {
+        reopened.fault_in(*x, *y, *z).unwrap();
+        assert_eq!(reopened.get_voxel(*x, *y, *z), Some(voxel));
+    }
+    assert_eq!(reopened.history.undo_transactions().count(), edits.len());
+}
+
+#[test]
+fn corrupted_regions_are_reported() {
+    let directory = region_directory("corrupted_regions");
+    test_map(24).save_regions(&directory).unwrap();
+    let region = directory.join("r.0.0.0.region");
+    let mut bytes = std::fs::read(&region).unwrap();
+    let last = bytes.len() - 10;
+    bytes[last] ^= 0xff;
+    std::fs::write(&region, bytes).unwrap();
+    assert!(directory.join(LEVEL_FILE).exists());
+
+    let mut map = Map::open_regions(&directory, 16).unwrap();
+    let mut errors = 0;
+    for z in 0..2 {
+        for y in 0..2 {
+            for x in 0..2 {
+                if let Err(e) = map.fault_in(x * 16, y * 16, z * 16) {
+                    assert!(matches!(e, WorldFileError::ChecksumMismatch(_)));
+                    errors += 1;
+                }
+            }
+        }
+    }
+    assert_eq!(errors, 1);
+}
+
+#[test]
+fn rewritten_chunks_reuse_freed_space() {
+    let directory = region_directory("rewritten_regions");
+    let mut map = test_map(25);
+    map.save_regions(&directory).unwrap();
+    let region = directory.join("r.0.0.0.region");
+    let saved = std::fs::metadata(&region).unwrap().len();
+
+    for round in 0..40 {
+        let material = if round % 2 == 0 { "gold_ore" } else { "lava" };
+        for x in 0..16 {
+            map.set_voxel(x, 3, round % 16, voxel(material));
+        }
+        map.save_regions(&directory).unwrap();
+    }
+    // Appending every copy of the chunk, a few hundred bytes each, would add several kB.
+    let rewritten = std::fs::metadata(&region).unwrap().len();
+    assert!(rewritten < saved + 2048, "{} grew to {}", saved, rewritten);
+
+    let mut reopened = Map::open_regions(&directory, 16).unwrap();
+    reopened.fault_in(0, 3, 7).unwrap();
+    assert_eq!(reopened.get_voxel(5, 3, 7), Some(&voxel("lava")));
+}
+
+#[test]
+fn entries_outside_the_file_are_reported() {
+    let directory = region_directory("truncated_regions");
+    test_map(26).save_regions(&directory).unwrap();
+    let region = directory.join("r.0.0.0.region");
+    let mut bytes = std::fs::read(&region).unwrap();
+    // The length of the entry of chunk (0, 0, 0), right after the 8 byte header.
+    bytes[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+    std::fs::write(&region, bytes).unwrap();
+
+    let mut map = Map::open_regions(&directory, 16).unwrap();
+    assert!(matches!(
+        map.fault_in(0, 0, 0),
+        Err(WorldFileError::Corrupt(_))
+    ));
+}
@@ -0,0 +1,210 @@
+mod common;
+
+use common::{assert_golden, test_map, voxel};
+use nalgebra::Vector3;
+use torus::chunk::Chunk;
+use torus::map::Map;
+use torus::renderer::Renderer;
+
+const WIDTH: u32 = 96;
+const HEIGHT: u32 = 72;
+const SEED: u64 = 7;
+const RADIUS: i32 = 2;
+
+const POSES: [(&str, [f32; 3], [f32; 3]); 3] = [
+    ("origin_forward", [0.0, 0.0, 0.0], [0.0, 0.0, 0.0]),
+    ("origin_turned", [0.0, 0.0, 0.0], [0.3, 2.2, 0.0]),
+    ("corner_looking_in", [-14.0, 12.0, -14.0], [0.5, 0.8, 0.0]),
+];
+
+fn render(map: Map, position: [f32; 3], rotation: [f32; 3]) -> image::RgbaImage {
+    let renderer = Renderer::new(map, WIDTH, HEIGHT, 4);
+    renderer.render_image(Vector3::from(position), Vector3::from(rotation))
+}
+
+#[test]
+fn dda_hits_first_solid_voxel() {
+    let mut chunk = Chunk::new((0, 0, 0));
+    chunk.set(8, 2, 2, voxel("dirt"));
+    chunk.set(12, 2, 2, voxel("sand"));
+    let mut map = Map::new();
+    map.set(0, 0, 0, chunk);
+    let renderer = Renderer::new(map, 1, 1, 1);
+
+    let origin = Vector3::new(0.5, 2.5, 2.5);
+    let hit = renderer
+        .dda(&origin, &Vector3::new(1.0, 0.0, 0.0), 256)
+        .unwrap();
+    assert_eq!(hit.step_count, 8);
+    assert_eq!(*hit.voxel, voxel("dirt"));
+    assert_eq!(hit.position, Vector3::new(8, 2, 2));
+    assert_eq!(hit.normal, Vector3::new(-1.0, 0.0, 0.0));
+    assert!((hit.distance - 7.5).abs() < 1e-5);
+
+    assert!(renderer
+        .dda(&origin, &Vector3::new(-1.0, 0.0, 0.0), 256)
+        .is_none());
+}
+
+#[test]
+fn dda_stops_without_a_direction() {
+    let mut map = Map::new();
+    map.set(0, 0, 0, Chunk::new((0, 0, 0)));
+    let renderer = Renderer::new(map, 1, 1, 1);
+
+    for origin in [Vector3::new(4.5, 2.5, 2.5), Vector3::new(40.5, 2.5, 2.5)] {
+        for direction in [Vector3::zeros(), Vector3::repeat(f32::NAN)] {
+            assert!(renderer.dda(&origin, &direction, 256).is_none());
+        }
+    }
+}
+
+#[test]
+fn dda_reports_entry_face() {
+    let mut chunk = Chunk::new((0, 0, 0));
+    chunk.set(4, 4, 4, voxel("stone"));
+    let mut map = Map::new();
+    map.set(0, 0, 0, chunk);
+    let renderer = Renderer::new(map, 1, 1, 1);
+
+    let from_above = renderer
+        .dda(
+            &Vector3::new(4.5, 10.5, 4.5),
+            &Vector3::new(0.0, -1.0, 0.0),
+            256,
+        )
+        .unwrap();
+    assert_eq!(from_above.normal, Vector3::new(0.0, 1.0, 0.0));
+    assert!((from_above.distance - 5.5).abs() < 1e-5);
+
+    let direction = Vector3::new(-1.0, -0.2, 0.1).normalize();
+    let origin = Vector3::new(4.5, 4.5, 4.5) - direction * 6.0;
+    let diagonal = renderer.dda(&origin, &direction, 256).unwrap();
+    assert_eq!(diagonal.normal, Vector3::new(1.0, 0.0, 0.0));
+    assert!(diagonal.distance > 5.0 && diagonal.distance < 6.0);
+}
+
+#[test]
+fn shadow_rays_detect_occluders() {
+    let mut chunk = Chunk::new((0, 0, 0));
+    for x in 0..16 {
+        for z in 0..16 {
+            chunk.set(x, 0, z, voxel("stone"));
+        }
+    }
+    chunk.set(4, 6, 4, voxel("stone"));
+    let mut map = Map::new();
+    map.set(0, 0, 0, chunk);
+    map.generate_all_distance_maps(RADIUS);
+    let mut renderer = Renderer::new(map, 1, 1, 1);
+    assert!(renderer.set_sun_direction(Vector3::new(0.0, 2.0, 0.0)));
+    assert!(!renderer.set_sun_direction(Vector3::zeros()));
+    assert!(!renderer.set_sun_direction(Vector3::new(f32::NAN, 1.0, 0.0)));
+    assert_eq!(renderer.sun_direction, Vector3::new(0.0, 1.0, 0.0));
+
+    let down = Vector3::new(0.0, -1.0, 0.0);
+    let under_block = renderer
+        .dda(&Vector3::new(4.5, 3.5, 4.5), &down, 256)
+        .unwrap();
+    assert_eq!(under_block.position, Vector3::new(4, 0, 4));
+    assert!(renderer.in_shadow(&under_block));
+
+    let open_floor = renderer
+        .dda(&Vector3::new(10.5, 3.5, 10.5), &down, 256)
+        .unwrap();
+    assert!(!renderer.in_shadow(&open_floor));
+
+    // The top of the block itself must not shadow itself.
+    let block_top = renderer
+        .dda(&Vector3::new(4.5, 12.5, 4.5), &down, 256)
+        .unwrap();
+    assert_eq!(block_top.position, Vector3::new(4, 6, 4));
+    assert!(!renderer.in_shadow(&block_top));
+}
+
+#[test]
+fn ambient_occlusion_darkens_inner_corners() {
+    let mut chunk = Chunk::new((0, 0, 0));
+    for x in 0..16 {
+        for z in 0..16 {
+            chunk.set(x, 0, z, voxel("stone"));
+        }
+    }
+    for z in 0..16 {
+        chunk.set(8, 1, z, voxel("stone"));
+    }
+    let mut map = Map::new();
+    map.set(0, 0, 0, chunk);
+    let renderer = Renderer::new(map, 1, 1, 1);
+
+    let down = Vector3::new(0.0, -1.0, 0.0);
+    let open_floor = renderer
+        .dda(&Vector3::new(3.5, 5.5, 3.5), &down, 256)
+        .unwrap();
+    assert_eq!(renderer.ambient_occlusion_at(&open_floor), 1.0);
+
+    // Floor voxel right next to the wall: the edge touching the wall is occluded.
+    let against_wall = renderer
+        .dda(&Vector3::new(7.9, 5.5, 3.5), &down, 256)
+        .unwrap();
+    assert_eq!(against_wall.position, Vector3::new(7, 0, 3));
+    let near_edge = renderer.ambient_occlusion_at(&against_wall);
+    let far_side = renderer
+        .dda(&Vector3::new(7.1, 5.5, 3.5), &down, 256)
+        .unwrap();
+    let far_edge = renderer.ambient_occlusion_at(&far_side);
+    assert!(near_edge < far_edge && far_edge < 1.0);
+}
+
+#[test]
+fn distance_map_skipping_does_not_change_output() {
+    let plain = test_map(SEED);
+    let mut skipping = plain.clone();
+    skipping.generate_all_distance_maps(RADIUS);
+
+    for (name, position, rotation) in POSES {
+        let expected = render(plain.clone(), position, rotation);
+        let actual = render(skipping.clone(), position, rotation);
+        assert!(
+            expected == actual,
+            "{}: distance map skipping changed the rendered frame",
+            name
+        );
+    }
+}
+
+#[test]
+fn golden_frames() {
+    let mut map = test_map(SEED);
+    map.generate_all_distance_maps(RADIUS);
+
+    for (name, position, rotation) in POSES {
+        assert_golden(name, &render(map.clone(), position, rotation));
+    }
+}
+
+#[test]
+fn golden_frames_with_shadows() {
+    let mut map = test_map(SEED);
+    map.generate_all_distance_maps(RADIUS);
+
+    for (name, position, rotation) in POSES {
+        let mut renderer = Renderer::new(map.clone(), WIDTH, HEIGHT, 4);
+        renderer.shadows = true;
+        let frame = renderer.render_image(Vector3::from(position), Vector3::from(rotation));
+        assert_golden(&format!("{}_shadows", name), &frame);
+    }
+}
+
+#[test]
+fn golden_frames_with_ambient_occlusion() {
+    let mut map = test_map(SEED);
+    map.generate_all_distance_maps(RADIUS);
+
+    for (name, position, rotation) in POSES {
+        let mut renderer = Renderer::new(map.clone(), WIDTH, HEIGHT, 4);
+        renderer.ambient_occlusion = true;
+        let frame = renderer.render_image(Vector3::from(position), Vector3::from(rotation));
+        assert_golden(&format!("{}_ao", name), &frame);
+    }
+}
@@ -0,0 +1,38 @@
+use torus::map::Map;
+use torus::seed::WorldSeed;
+
+#[test]
+fn same_seed_generates_identical_maps() {
+    let seed: WorldSeed = "reproducible".parse().unwrap();
+
+    let mut first = Map::new();
+    first.generate(seed);
+    let mut second = Map::new();
+    second.generate(seed);
+
+    assert_eq!(first.seed, seed);
+    assert!(first == second, "maps generated from {} differ", seed);
+}
+
+#[test]
+fn different_seeds_generate_different_maps() {
+    let mut first = Map::new();
+    first.generate_region(WorldSeed::new(1), (0, 0, 0), (1, 1, 1));
+    let mut second = Map::new();
+    second.generate_region(WorldSeed::new(2), (0, 0, 0), (1, 1, 1));
+
+    assert!(first.chunks != second.chunks);
+}
+
+#[test]
+fn seeds_parse_from_numbers_and_text() {
+    assert_eq!("42".parse::<WorldSeed>().unwrap(), WorldSeed::new(42));
+    assert_eq!(
+        "torus".parse::<WorldSeed>().unwrap(),
+        "torus".parse::<WorldSeed>().unwrap()
+    );
+    assert_ne!(
+        WorldSeed::new(42).derive("caves"),
+        WorldSeed::new(42).derive("ores")
+    );
+}
@@ -0,0 +1,196 @@
+mod common;
+
+use common::voxel;
+use nalgebra::Vector3;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use torus::chunk::{Chunk, ChunkPosition};
+use torus::generator::WorldGenerator;
+use torus::map::Map;
+use torus::region::RegionStore;
+use torus::renderer::Renderer;
+use torus::seed::WorldSeed;
+use torus::streaming::ChunkStreamer;
+
+fn streamed_map(seed: u64) -> Map {
+    let mut map = Map::new();
+    map.seed = WorldSeed::new(seed);
+    map
+}
+
+#[test]
+fn streamed_chunks_match_generated_ones() {
+    let mut map = streamed_map(31);
+    let mut streamer = ChunkStreamer::new(2);
+    streamer.distance_radius = 3;
+    streamer
+        .load_all(&mut map, Vector3::new(8.0, 8.0, 8.0))
+        .unwrap();
+    // Every chunk whose center is within two chunks of the camera chunk.
+    assert_eq!(map.chunks.len() + map.empty_chunks.len(), 33);
+
+    let mut generated = Map::new();
+    generated.generate_region(WorldSeed::new(31), (-2, -2, -2), (2, 2, 2));
+    for (position, chunk) in &map.chunks {
+        assert_eq!(
+            Some(chunk.position),
+            generated
+                .get(position.0 * 16, position.1 * 16, position.2 * 16)
+                .map(|c| c.position)
+        );
+        for z in 0..16 {
+            for y in 0..16 {
+                for x in 0..16 {
+                    let world = (
+                        position.0 * 16 + x,
+                        position.1 * 16 + y,
+                        position.2 * 16 + z,
+                    );
+                    assert_eq!(
+                        map.get_voxel(world.0, world.1, world.2),
+                        generated.get_voxel(world.0, world.1, world.2)
+                    );
+                }
+            }
+        }
+    }
+
+    // Distance maps built as chunks arrived match ones built for the whole set at once.
+    let mut expected = map.clone();
+    expected.generate_all_distance_maps(3);
+    for z in -40..56 {
+        for y in -40..56 {
+            for x in -40..56 {
+                assert_eq!(map.get_distance(x, y, z), expected.get_distance(x, y, z));
+            }
+        }
+    }
+}
+
+#[test]
+fn far_chunks_are_evicted_and_edits_written_back() {
+    let directory = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("streamed_regions");
+    let _ = std::fs::remove_dir_all(&directory);
+    let mut map = streamed_map(32);
+    map.set_chunk_source(RegionStore::open(&directory).unwrap(), usize::MAX);
+    let mut streamer = ChunkStreamer::new(1);
+    streamer.unload_radius = 2;
+
+    let origin = Vector3::new(8.0, 8.0, 8.0);
+    streamer.load_all(&mut map, origin).unwrap();
+    map.set_voxel(3, 4, 5, voxel("gold_ore"));
+
+    let far = Vector3::new(8.0 + 16.0 * 10.0, 8.0, 8.0);
+    streamer.load_all(&mut map, far).unwrap();
+    let status = streamer.update(&mut map, far).unwrap();
+    assert_eq!(status.missing, 0);
+    assert!(!map.is_within_bounds(3, 4, 5));
+    assert!(map.chunks.keys().all(|position| position.0 >= 8));
+
+    streamer.load_all(&mut map, origin).unwrap();
+    assert_eq!(map.get_voxel(3, 4, 5), Some(&voxel("gold_ore")));
+}
+
+#[test]
+fn rays_pass_through_unloaded_chunks() {
+    let mut map = Map::new();
+    map.set_voxel(0, 0, 40, voxel("stone"));
+    let mut renderer = Renderer::new(map, 1, 1, 1);
+    renderer.sky_color = Vector3::new(10, 20, 200);
+
+    let origin = Vector3::new(0.5, 0.5, 0.5);
+    let hit = renderer.dda(&origin, &Vector3::z(), 256).unwrap();
+    assert_eq!(hit.position, Vector3::new(0, 0, 40));
+
+    let image = renderer.render_image(origin, Vector3::new(0.0, std::f32::consts::PI, 0.0));
+    assert_eq!(image.get_pixel(0, 0).0, [10, 20, 200, 255]);
+}
+
+#[test]
+fn edits_wait_for_streamed_chunks() {
+    let mut map = streamed_map(33);
+    let mut streamer = ChunkStreamer::new(2);
+    let origin = Vector3::new(8.0, 8.0, 8.0);
+    streamer.update(&mut map, origin).unwrap();
+
+    // Two chunks away along x and y, so not among the first requested.
+    assert!(!map.is_within_bounds(40, 8, 8));
+    assert!(!map.is_within_bounds(8, 40, 8));
+    let stone = voxel("stone");
+    assert_eq!(
+        map.set_voxels([((40, 8, 8), stone), ((41, 8, 8), stone)]),
+        0
+    );
+    assert_eq!(map.set_voxels([((8, 40, 8), Default::default())]), 0);
+    assert!(!map.is_within_bounds(40, 8, 8));
+    assert!(!map.is_within_bounds(8, 40, 8));
+
+    streamer.load_all(&mut map, origin).unwrap();
+    let mut generated = Map::new();
+    generated.generate_region(WorldSeed::new(33), (0, 0, 0), (2, 2, 0));
+    for z in 0..16 {
+        for a in 0..16 {
+            for b in 32..48 {
+                assert_eq!(map.get_voxel(b, a, z), generated.get_voxel(b, a, z));
+                assert_eq!(map.get_voxel(a, b, z), generated.get_voxel(a, b, z));
+            }
+        }
+    }
+    assert_eq!(
+        map.set_voxel(40, 8, 8, stone),
+        *generated.get_voxel(40, 8, 8).unwrap()
+    );
+    assert_eq!(map.get_voxel(40, 8, 8), Some(&stone));
+}
+
+#[test]
+fn undo_waits_for_streamed_chunks() {
+    let mut map = streamed_map(34);
+    let mut streamer = ChunkStreamer::new(1);
+    streamer.unload_radius = 2;
+    let origin = Vector3::new(8.0, 8.0, 8.0);
+    streamer.load_all(&mut map, origin).unwrap();
+    let before = *map.get_voxel(3, 4, 5).unwrap();
+    map.set_voxel(3, 4, 5, voxel("gold_ore"));
+
+    // Without a chunk source the edited chunk is dropped once far away, and comes back
+    // as generated.
+    let far = Vector3::new(8.0 + 16.0 * 10.0, 8.0, 8.0);
+    streamer.load_all(&mut map, far).unwrap();
+    assert!(!map.is_within_bounds(3, 4, 5));
+    assert!(!map.undo());
+    assert_eq!(map.history.undo_transactions().count(), 1);
+
+    streamer.load_all(&mut map, origin).unwrap();
+    assert!(map.undo());
+    assert_eq!(map.get_voxel(3, 4, 5), Some(&before));
+    assert!(map.redo());
+    assert_eq!(map.get_voxel(3, 4, 5), Some(&voxel("gold_ore")));
+}
+
+/// Records the threads chunks are generated on.
+struct ThreadRecorder(Mutex<Vec<Option<String>>>);
+
+impl WorldGenerator for ThreadRecorder {
+    fn generate_chunk(&self, _position: ChunkPosition, _chunk: &mut Chunk) {
+        let name = std::thread::current().name().map(str::to_string);
+        self.0.lock().unwrap().push(name);
+    }
+}
+
+#[test]
+fn chunks_are_made_off_the_global_pool() {
+    let mut map = streamed_map(35);
+    let recorder = Arc::new(ThreadRecorder(Mutex::new(Vec::new())));
+    let mut streamer = ChunkStreamer::new(1);
+    streamer.set_generator(recorder.clone());
+    streamer
+        .load_all(&mut map, Vector3::new(8.0, 8.0, 8.0))
+        .unwrap();
+
+    let names = recorder.0.lock().unwrap();
+    assert_eq!(names.len(), 7);
+    assert!(names.iter().all(|name| name
+        .as_deref()
+        .is_some_and(|name| name.starts_with("chunk-streamer"))));
+}
@@ -0,0 +1,220 @@
+mod common;
+
+use common::{test_map, voxel};
+use nalgebra::Vector3;
+use std::path::PathBuf;
+use torus::map::Map;
+use torus::material::Material;
+use torus::vox::{VoxError, VoxFile};
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn albedo(map: &Map, x: i32, y: i32, z: i32) -> Option<Vector3<u8>> {
+    let voxel = map.get_voxel(x, y, z)?;
+    if voxel.is_empty() {
+        return None;
+    }
+    Some(map.materials.get(voxel.material).unwrap().albedo)
+}
+
+#[test]
+fn palette_colors_become_materials() {
+    let mut map = Map::new();
+    let materials = map.materials.len();
+    let placed = map
+        .import_vox(&fixture("palette_model.vox"), Vector3::zeros())
+        .unwrap();
+    assert_eq!(placed, 3);
+
+    // The first color is the albedo of stone, the second one is new.
+    assert_eq!(map.get_voxel(0, 0, 1), Some(&voxel("stone")));
+    assert_eq!(map.get_voxel(1, 2, 1), Some(&voxel("stone")));
+    assert_eq!(
+        map.get_voxel(2, 3, 0),
+        map.materials.voxel("vox_0a141e").as_ref()
+    );
+    assert_eq!(map.materials.len(), materials + 1);
+
+    // Importing again reuses the registered material.
+    map.import_vox(&fixture("palette_model.vox"), Vector3::new(20, 0, 0))
+        .unwrap();
+    assert_eq!(map.materials.len(), materials + 1);
+}
+
+#[test]
+fn scene_transforms_place_models() {
+    let file = VoxFile::load(&fixture("scene.vox")).unwrap();
+    assert_eq!(file.models.len(), 2);
+    assert_eq!(file.instances.len(), 2);
+
+    let mut map = Map::new();
+    let offset = Vector3::new(10, 20, 30);
+    assert_eq!(map.stamp_vox(&file, offset).unwrap(), 11);
+
+    let white = Some(Vector3::new(255, 255, 255));
+    for x in 10..12 {
+        for y in 20..22 {
+            for z in 30..32 {
+                assert_eq!(albedo(&map, x, y, z), white, "{} {} {}", x, y, z);
+            }
+        }
+    }
+    // The rotated column lies along x instead of standing up.
+    let green = Some(Vector3::new(0, 0xee, 0));
+    for x in 15..18 {
+        assert_eq!(albedo(&map, x, 21, 30), green);
+    }
+    assert_eq!(albedo(&map, 14, 21, 30), None);
+    assert_eq!(albedo(&map, 18, 21, 30), None);
+
+    // The whole import is one undoable edit.
+    assert!(map.undo());
+    assert!(map.chunks.is_empty());
+}
+
+#[test]
+fn invalid_files_are_rejected() {
+    let bytes = std::fs::read(fixture("scene.vox")).unwrap();
+    assert!(matches!(
+        VoxFile::parse(&bytes[..bytes.len() - 5]),
+        Err(VoxError::Format(_))
+    ));
+    assert!(matches!(
+        VoxFile::parse(b"PNG\0\0\0\0\0"),
+        Err(VoxError::Format(_))
+    ));
+}
+
+/// A `.vox` file holding only a scene graph of groups, as node id and children.
+fn scene_of_groups(groups: &[(i32, Vec<i32>)]) -> Vec<u8> {
+    let mut nodes = Vec::new();
+    for (id, children) in groups {
+        let mut content = Vec::new();
+        content.extend(id.to_le_bytes());
+        content.extend(0u32.to_le_bytes());
+        content.extend((children.len() as u32).to_le_bytes());
+        for child in children {
+            content.extend(child.to_le_bytes());
+        }
+        nodes.extend(b"nGRP");
+        nodes.extend((content.len() as u32).to_le_bytes());
+        nodes.extend(0u32.to_le_bytes());
+        nodes.extend(content);
+    }
+    let mut bytes = b"VOX ".to_vec();
+    bytes.extend(150u32.to_le_bytes());
+    bytes.extend(b"MAIN");
+    bytes.extend(0u32.to_le_bytes());
+    bytes.extend((nodes.len() as u32).to_le_bytes());
+    bytes.extend(nodes);
+    bytes
+}
+
+#[test]
+fn cyclic_and_exploding_scenes_are_rejected() {
+    let cycle = scene_of_groups(&[(0, vec![1]), (1, vec![2, 1]), (2, vec![])]);
+    assert!(matches!(
+        VoxFile::parse(&cycle),
+        Err(VoxError::Format(reason)) if reason.contains("contains itself")
+    ));
+
+    // Every group lists the next one twice, 2^40 paths to the last one.
+    let mut groups: Vec<_> = (0..40).map(|id| (id, vec![id + 1, id + 1])).collect();
+    groups.push((40, vec![]));
+    assert!(matches!(
+        VoxFile::parse(&scene_of_groups(&groups)),
+        Err(VoxError::Format(reason)) if reason.contains("too large")
+    ));
+
+    // Sharing a node without a cycle is fine.
+    let shared = scene_of_groups(&[(0, vec![1, 1]), (1, vec![])]);
+    assert!(VoxFile::parse(&shared).unwrap().instances.is_empty());
+}
+
+#[test]
+fn exported_regions_import_back() {
+    let map = test_map(5);
+    let (min, max) = (Vector3::new(-20, -10, -5), Vector3::new(12, 25, 18));
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("export.vox");
+    map.export_vox(&path, min, max).unwrap();
+
+    let mut imported = Map::new();
+    imported.materials = map.materials.clone();
+    imported.import_vox(&path, min).unwrap();
+    for z in min.z..=max.z {
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                assert_eq!(albedo(&imported, x, y, z), albedo(&map, x, y, z));
+            }
+        }
+    }
+    assert!(albedo(&imported, max.x + 1, max.y, max.z).is_none());
+}
+
+#[test]
+fn large_regions_are_split_into_models() {
+    let mut map = Map::new();
+    map.set_voxel(0, 0, 0, voxel("stone"));
+    map.set_voxel(299, 3, -40, voxel("sand"));
+    let file = map.to_vox(Vector3::new(0, 0, -40), Vector3::new(299, 3, 0));
+    assert_eq!(file.models.len(), 2);
+    assert_eq!(file.models[0].size, Vector3::new(256, 41, 4));
+    assert_eq!(file.models[1].size, Vector3::new(44, 41, 4));
+
+    // Chunks only stored in region files are exported too.
+    let directory = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("large_vox_regions");
+    let _ = std::fs::remove_dir_all(&directory);
+    map.save_regions(&directory).unwrap();
+    let stored = Map::open_regions(&directory, 1).unwrap();
+    assert_eq!(
+        stored.to_vox(Vector3::new(0, 0, -40), Vector3::new(299, 3, 0)),
+        file
+    );
+
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("large.vox");
+    file.save(&path).unwrap();
+    let mut imported = Map::new();
+    assert_eq!(
+        imported.import_vox(&path, Vector3::new(0, 0, -40)).unwrap(),
+        2
+    );
+    assert_eq!(imported.get_voxel(0, 0, 0), Some(&voxel("stone")));
+    assert_eq!(imported.get_voxel(299, 3, -40), Some(&voxel("sand")));
+}
+
+#[test]
+fn colors_are_quantized_to_the_palette() {
+    let mut map = Map::new();
+    for x in 0..400 {
+        let albedo = Vector3::new((x % 20 * 13) as u8, (x / 20 * 13) as u8, 90);
+        let material = Material::new(&format!("shade_{}", x), albedo);
+        let id = map.materials.register(material).unwrap();
+        map.set_voxel(
+            x,
+            0,
+            0,
+            map.materials.voxel(&format!("shade_{}", x)).unwrap(),
+        );
+        assert_eq!(map.get_voxel(x, 0, 0).unwrap().material, id);
+    }
+    let file = map.to_vox(Vector3::zeros(), Vector3::new(399, 0, 0));
+    let colors: std::collections::HashSet<_> = file.models[0]
+        .voxels
+        .iter()
+        .map(|(_, index)| *index)
+        .collect();
+    assert!(colors.len() <= 255);
+    assert!(!colors.contains(&0));
+
+    for (position, index) in &file.models[0].voxels {
+        let original = albedo(&map, position.x as i32, 0, 0).unwrap();
+        let color = file.palette[*index as usize];
+        for channel in 0..3 {
+            assert!((original[channel] as i32 - color[channel] as i32).abs() <= 13);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use common::{test_map, voxel};
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use nalgebra::Vector3;
+use std::io::Write;
+use torus::brush::Shape;
+use torus::map::Map;
+use torus::material::Material;
+use torus::world_file::{WorldFileError, FORMAT_VERSION};
+
+fn edited_map() -> Map {
+    let mut map = test_map(11);
+    map.generate_all_distance_maps(3);
+    map.materials
+        .register(Material::new("brick", Vector3::new(150, 60, 40)))
+        .unwrap();
+    map.fill_shape(
+        &Shape::Sphere {
+            center: Vector3::new(4.0, 4.0, 4.0),
+            radius: 6.0,
+        },
+        map.materials.voxel("brick").unwrap(),
+    );
+    map.set_voxel(60, 0, 0, voxel("stone"));
+    map.remove_voxel(60, 0, 0);
+    map.undo();
+    map
+}
+
+fn to_bytes(map: &Map, distance_maps: bool) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    map.write_to(&mut bytes, distance_maps).unwrap();
+    bytes
+}
+
+#[test]
+fn save_and_load_round_trip() {
+    let map = edited_map();
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("round_trip.torus");
+    map.save(&path).unwrap();
+    let loaded = Map::load(&path).unwrap();
+    assert_eq!(loaded, map);
+    assert!(loaded.history.can_undo() && loaded.history.can_redo());
+}
+
+#[test]
+fn missing_distance_maps_are_regenerated() {
+    let map = edited_map();
+    let bytes = to_bytes(&map, false);
+    assert!(bytes.len() < to_bytes(&map, true).len());
+    let loaded = Map::read_from(&mut &bytes[..]).unwrap();
+    assert_eq!(loaded, map);
+}
+
+#[test]
+fn corruption_is_reported() {
+    let bytes = to_bytes(&edited_map(), true);
+
+    let mut corrupted = bytes.clone();
+    let middle = corrupted.len() / 2;
+    corrupted[middle] ^= 0x40;
+    assert!(matches!(
+        Map::read_from(&mut &corrupted[..]),
+        Err(WorldFileError::ChecksumMismatch(_))
+    ));
+
+    let truncated = &bytes[..bytes.len() - 20];
+    assert!(matches!(
+        Map::read_from(&mut &truncated[..]),
+        Err(WorldFileError::Corrupt(_))
+    ));
+
+    assert!(matches!(
+        Map::read_from(&mut &b"PNG not a world"[..]),
+        Err(WorldFileError::NotAWorld)
+    ));
+}
+
+#[test]
+fn newer_versions_are_rejected() {
+    let mut bytes = to_bytes(&Map::new(), false);
+    bytes[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+    // Keep the header checksum valid so the version check is what fails.
+    let checksum = crc32fast::hash(&bytes[..20]);
+    bytes[20..24].copy_from_slice(&checksum.to_le_bytes());
+    assert!(matches!(
+        Map::read_from(&mut &bytes[..]),
+        Err(WorldFileError::UnsupportedVersion(v)) if v == FORMAT_VERSION + 1
+    ));
+}
+
+#[test]
+fn oversized_payloads_are_rejected() {
+    // A chunk section of 1 MiB of zeros, which deflates to about a kilobyte.
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&vec![0; 1 << 20]).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let mut bytes = to_bytes(&Map::new(), false)[..24].to_vec();
+    bytes.extend_from_slice(b"CHNK");
+    bytes.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&crc32fast::hash(&compressed).to_le_bytes());
+    bytes.extend_from_slice(&compressed);
+    assert!(matches!(
+        Map::read_from(&mut &bytes[..]),
+        Err(WorldFileError::Corrupt(message)) if message.contains("inflates")
+    ));
+}